@@ -0,0 +1,667 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::consts::{HIST, OFFS, PCAL, SCAL, SPLT};
+use crate::png::{Placement, Png};
+use crate::text;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+/// One colour of a [`SuggestedPalette`], with samples at the palette's
+/// sample depth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    /// How often the colour appears, relative to the other entries.
+    pub frequency: u16,
+}
+
+/// An sPLT chunk: a named palette that viewers limited to fewer colours may
+/// use instead of quantizing the image themselves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuggestedPalette {
+    pub name: String,
+    /// Bits per sample of the entries, 8 or 16.
+    pub sample_depth: u8,
+    pub entries: Vec<PaletteEntry>,
+}
+
+impl SuggestedPalette {
+    /// Decodes the data of an sPLT chunk.
+    pub fn decode(data: &[u8]) -> crate::Result<Self> {
+        let (name, rest) = text::split_nul(data)?;
+        let name = text::from_latin1(name);
+        text::check_keyword(&name)?;
+        let (&sample_depth, rest) = rest.split_first().ok_or("missing sample depth")?;
+        let entry_len = match sample_depth {
+            8 => 6,
+            16 => 10,
+            _ => return Err("sample depth must be 8 or 16".into()),
+        };
+        if rest.len() % entry_len != 0 {
+            return Err("sPLT entries are truncated".into());
+        }
+
+        let entries = rest
+            .chunks_exact(entry_len)
+            .map(|entry| {
+                let sample = |i: usize| match sample_depth {
+                    8 => entry[i] as u16,
+                    _ => u16::from_be_bytes([entry[2 * i], entry[2 * i + 1]]),
+                };
+                let frequency = &entry[entry_len - 2..];
+                PaletteEntry {
+                    red: sample(0),
+                    green: sample(1),
+                    blue: sample(2),
+                    alpha: sample(3),
+                    frequency: u16::from_be_bytes([frequency[0], frequency[1]]),
+                }
+            })
+            .collect();
+        Ok(Self {
+            name,
+            sample_depth,
+            entries,
+        })
+    }
+
+    /// Builds the sPLT chunk, failing if the name is not a valid keyword or
+    /// a sample does not fit the sample depth.
+    pub fn encode(&self) -> crate::Result<Chunk> {
+        text::check_keyword(&self.name)?;
+        let mut data = text::to_latin1(&self.name)?;
+        data.push(0);
+        data.push(self.sample_depth);
+        for entry in self.entries.iter() {
+            for sample in [entry.red, entry.green, entry.blue, entry.alpha] {
+                match self.sample_depth {
+                    8 => data.push(u8::try_from(sample).map_err(|_| "sample exceeds 8 bits")?),
+                    16 => data.extend(sample.to_be_bytes()),
+                    _ => return Err("sample depth must be 8 or 16".into()),
+                }
+            }
+            data.extend(entry.frequency.to_be_bytes());
+        }
+        Ok(Chunk::new(SPLT, data))
+    }
+}
+
+impl Display for SuggestedPalette {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "palette \"{}\": {} entries, {}-bit samples",
+            self.name,
+            self.entries.len(),
+            self.sample_depth
+        )
+    }
+}
+
+/// A hIST chunk: how often each PLTE entry is used, scaled to fit 16 bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    /// One frequency per palette entry, in palette order.
+    pub frequencies: Vec<u16>,
+}
+
+impl Histogram {
+    pub fn decode(data: &[u8]) -> crate::Result<Self> {
+        if !data.len().is_multiple_of(2) {
+            return Err("hIST data has an odd length".into());
+        }
+        let frequencies = data
+            .chunks_exact(2)
+            .map(|f| u16::from_be_bytes([f[0], f[1]]))
+            .collect();
+        Ok(Self { frequencies })
+    }
+
+    pub fn encode(&self) -> Chunk {
+        Chunk::new(
+            HIST,
+            self.frequencies
+                .iter()
+                .flat_map(|f| f.to_be_bytes())
+                .collect(),
+        )
+    }
+
+    /// Checks there is exactly one frequency for each of the
+    /// `palette_len` entries of PLTE.
+    pub fn check(&self, palette_len: usize) -> crate::Result<()> {
+        if self.frequencies.len() != palette_len {
+            return Err(alloc::format!(
+                "hIST has {} entries but PLTE has {}",
+                self.frequencies.len(),
+                palette_len
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl Display for Histogram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "histogram of {} entries", self.frequencies.len())
+    }
+}
+
+/// The unit of an [`ImageOffset`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetUnit {
+    Pixel,
+    Micrometre,
+}
+
+/// An oFFs chunk: where the image sits on a larger page or in a larger
+/// image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageOffset {
+    pub x: i32,
+    pub y: i32,
+    pub unit: OffsetUnit,
+}
+
+/// The unit of a [`PhysicalScale`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleUnit {
+    Metre,
+    Radian,
+}
+
+/// An sCAL chunk: the physical size of one pixel. The sizes are kept as the
+/// decimal strings the chunk stores, so they round-trip exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhysicalScale {
+    pub unit: ScaleUnit,
+    pub pixel_width: String,
+    pub pixel_height: String,
+}
+
+/// How a [`PixelCalibration`] maps stored samples to physical values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Equation {
+    /// `p0 + p1 * x / x_max`
+    Linear,
+    /// `p0 + p1 * e^(p2 * x / x_max)`
+    Exponential,
+    /// `p0 + p1 * p3^(p2 * x / x_max)`
+    ArbitraryBase,
+    /// `p0 + p1 * sinh(p2 * (x - p3) / x_max)`
+    Hyperbolic,
+}
+
+/// A pCAL chunk: how sample values translate into physical quantities, as
+/// in scientific imaging.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PixelCalibration {
+    pub name: String,
+    /// Sample values `x0` and `x1` map onto the equation's `0` and `x_max`.
+    pub x0: i32,
+    pub x1: i32,
+    pub equation: Equation,
+    /// Unit of the physical values, e.g. `K`; may be empty.
+    pub unit: String,
+    /// The equation's parameters as the decimal strings the chunk stores.
+    pub parameters: Vec<String>,
+}
+
+/// Checks `s` is a decimal floating-point number as sCAL and pCAL store
+/// them: an optional sign, digits with an optional point, and an optional
+/// exponent. Infinities and NaNs are not allowed.
+fn parse_float(s: &str) -> crate::Result<f64> {
+    let valid = s
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'))
+        && s.chars().any(|c| c.is_ascii_digit());
+    match s.parse::<f64>() {
+        Ok(value) if valid => Ok(value),
+        _ => Err(alloc::format!("{:?} is not a decimal number", s).into()),
+    }
+}
+
+fn be_i32(data: &[u8]) -> i32 {
+    i32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+impl ImageOffset {
+    pub fn decode(data: &[u8]) -> crate::Result<Self> {
+        let data: &[u8; 9] = data.try_into().map_err(|_| "oFFs data must be 9 bytes")?;
+        Ok(Self {
+            x: be_i32(&data[..4]),
+            y: be_i32(&data[4..8]),
+            unit: match data[8] {
+                0 => OffsetUnit::Pixel,
+                1 => OffsetUnit::Micrometre,
+                _ => return Err("unknown oFFs unit".into()),
+            },
+        })
+    }
+
+    pub fn encode(&self) -> Chunk {
+        let mut data = Vec::with_capacity(9);
+        data.extend(self.x.to_be_bytes());
+        data.extend(self.y.to_be_bytes());
+        data.push(self.unit as u8);
+        Chunk::new(OFFS, data)
+    }
+}
+
+impl PhysicalScale {
+    pub fn decode(data: &[u8]) -> crate::Result<Self> {
+        let (&unit, rest) = data.split_first().ok_or("sCAL data is empty")?;
+        let unit = match unit {
+            1 => ScaleUnit::Metre,
+            2 => ScaleUnit::Radian,
+            _ => return Err("unknown sCAL unit".into()),
+        };
+        let (width, height) = text::split_nul(rest)?;
+        let scale = Self {
+            unit,
+            pixel_width: text::from_latin1(width),
+            pixel_height: text::from_latin1(height),
+        };
+        scale.check()?;
+        Ok(scale)
+    }
+
+    /// Both sizes must be positive numbers.
+    fn check(&self) -> crate::Result<()> {
+        for size in [&self.pixel_width, &self.pixel_height] {
+            if parse_float(size)? <= 0.0 {
+                return Err("sCAL sizes must be positive".into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self) -> crate::Result<Chunk> {
+        self.check()?;
+        let mut data = Vec::from([self.unit as u8 + 1]);
+        data.extend(self.pixel_width.bytes());
+        data.push(0);
+        data.extend(self.pixel_height.bytes());
+        Ok(Chunk::new(SCAL, data))
+    }
+}
+
+impl Equation {
+    /// How many parameters the equation takes.
+    pub fn parameter_count(self) -> usize {
+        match self {
+            Equation::Linear => 2,
+            Equation::Exponential => 3,
+            Equation::ArbitraryBase | Equation::Hyperbolic => 4,
+        }
+    }
+}
+
+impl PixelCalibration {
+    pub fn decode(data: &[u8]) -> crate::Result<Self> {
+        let (name, rest) = text::split_nul(data)?;
+        if rest.len() < 10 {
+            return Err("pCAL data is truncated".into());
+        }
+        let equation = match rest[8] {
+            0 => Equation::Linear,
+            1 => Equation::Exponential,
+            2 => Equation::ArbitraryBase,
+            3 => Equation::Hyperbolic,
+            _ => return Err("unknown pCAL equation type".into()),
+        };
+        let count = rest[9] as usize;
+        let (unit, parameters) = text::split_nul(&rest[10..])?;
+        let calibration = Self {
+            name: text::from_latin1(name),
+            x0: be_i32(&rest[..4]),
+            x1: be_i32(&rest[4..8]),
+            equation,
+            unit: text::from_latin1(unit),
+            parameters: parameters
+                .split(|&b| b == 0)
+                .map(text::from_latin1)
+                .collect(),
+        };
+        if calibration.parameters.len() != count {
+            return Err("pCAL parameter count does not match".into());
+        }
+        calibration.check()?;
+        Ok(calibration)
+    }
+
+    fn check(&self) -> crate::Result<()> {
+        text::check_keyword(&self.name)?;
+        if self.x0 == self.x1 {
+            return Err("pCAL x0 and x1 must differ".into());
+        }
+        if self.parameters.len() != self.equation.parameter_count() {
+            return Err(alloc::format!(
+                "the equation takes {} parameters",
+                self.equation.parameter_count()
+            )
+            .into());
+        }
+        for parameter in self.parameters.iter() {
+            parse_float(parameter)?;
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self) -> crate::Result<Chunk> {
+        self.check()?;
+        let mut data = text::to_latin1(&self.name)?;
+        data.push(0);
+        data.extend(self.x0.to_be_bytes());
+        data.extend(self.x1.to_be_bytes());
+        data.push(self.equation as u8);
+        data.push(self.parameters.len() as u8);
+        data.extend(text::to_latin1(&self.unit)?);
+        for parameter in self.parameters.iter() {
+            data.push(0);
+            data.extend(parameter.bytes());
+        }
+        Ok(Chunk::new(PCAL, data))
+    }
+}
+
+impl Display for ImageOffset {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let unit = match self.unit {
+            OffsetUnit::Pixel => "px",
+            OffsetUnit::Micrometre => "µm",
+        };
+        write!(f, "{}, {} {}", self.x, self.y, unit)
+    }
+}
+
+impl Display for PhysicalScale {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let unit = match self.unit {
+            ScaleUnit::Metre => "m",
+            ScaleUnit::Radian => "rad",
+        };
+        write!(
+            f,
+            "{} x {} {} per pixel",
+            self.pixel_width, self.pixel_height, unit
+        )
+    }
+}
+
+impl Display for PixelCalibration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let equation = match self.equation {
+            Equation::Linear => "linear",
+            Equation::Exponential => "exponential",
+            Equation::ArbitraryBase => "arbitrary-base exponential",
+            Equation::Hyperbolic => "hyperbolic",
+        };
+        write!(
+            f,
+            "\"{}\": {} from {} to {}, parameters {}",
+            self.name,
+            equation,
+            self.x0,
+            self.x1,
+            self.parameters.join(", ")
+        )?;
+        if !self.unit.is_empty() {
+            write!(f, " ({})", self.unit)?;
+        }
+        Ok(())
+    }
+}
+
+impl Png {
+    fn decode_one<T>(
+        &self,
+        chunk_type: &str,
+        decode: fn(&[u8]) -> crate::Result<T>,
+    ) -> crate::Result<Option<T>> {
+        self.chunk_by_type(chunk_type)
+            .map(|c| decode(c.data()))
+            .transpose()
+    }
+
+    /// Replaces the chunk of the same type, or adds it before the first IDAT
+    /// as the extension chunks require.
+    fn set_one(&mut self, chunk: Chunk) -> crate::Result<()> {
+        let chunk_type = *chunk.chunk_type();
+        let name = chunk_type.to_string();
+        if self.chunk_by_type(&name).is_some() {
+            let data = chunk.data().to_vec();
+            self.replace_chunk(&name, data)?;
+            return Ok(());
+        }
+        self.insert_chunk(chunk, Placement::BeforeFirstIdat)
+    }
+
+    /// The decoded hIST chunk, if there is one, checked against PLTE.
+    pub fn histogram(&self) -> crate::Result<Option<Histogram>> {
+        let Some(histogram) = self.decode_one("hIST", Histogram::decode)? else {
+            return Ok(None);
+        };
+        let palette = self.chunk_by_type("PLTE").ok_or("hIST without PLTE")?;
+        histogram.check(palette.data().len() / 3)?;
+        Ok(Some(histogram))
+    }
+
+    /// The decoded oFFs chunk, if there is one.
+    pub fn image_offset(&self) -> crate::Result<Option<ImageOffset>> {
+        self.decode_one("oFFs", ImageOffset::decode)
+    }
+    pub fn set_image_offset(&mut self, offset: &ImageOffset) -> crate::Result<()> {
+        self.set_one(offset.encode())
+    }
+
+    /// The decoded sCAL chunk, if there is one.
+    pub fn physical_scale(&self) -> crate::Result<Option<PhysicalScale>> {
+        self.decode_one("sCAL", PhysicalScale::decode)
+    }
+    pub fn set_physical_scale(&mut self, scale: &PhysicalScale) -> crate::Result<()> {
+        self.set_one(scale.encode()?)
+    }
+
+    /// The decoded pCAL chunk, if there is one.
+    pub fn pixel_calibration(&self) -> crate::Result<Option<PixelCalibration>> {
+        self.decode_one("pCAL", PixelCalibration::decode)
+    }
+    pub fn set_pixel_calibration(&mut self, calibration: &PixelCalibration) -> crate::Result<()> {
+        self.set_one(calibration.encode()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette(sample_depth: u8) -> SuggestedPalette {
+        SuggestedPalette {
+            name: String::from("web safe"),
+            sample_depth,
+            entries: vec![
+                PaletteEntry {
+                    red: 255,
+                    green: 0,
+                    blue: 51,
+                    alpha: 255,
+                    frequency: 10,
+                },
+                PaletteEntry {
+                    red: 0,
+                    green: 0,
+                    blue: 0,
+                    alpha: 0,
+                    frequency: 0,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_suggested_palette_round_trip() {
+        for (depth, entry_len) in [(8, 6), (16, 10)] {
+            let chunk = palette(depth).encode().unwrap();
+            assert_eq!(chunk.chunk_type(), &SPLT);
+            assert_eq!(chunk.length(), 10 + 2 * entry_len);
+            assert_eq!(
+                SuggestedPalette::decode(chunk.data()).unwrap(),
+                palette(depth)
+            );
+        }
+        assert_eq!(
+            palette(8).to_string(),
+            "palette \"web safe\": 2 entries, 8-bit samples"
+        );
+    }
+
+    #[test]
+    fn test_suggested_palette_rejects_bad_data() {
+        let data = palette(8).encode().unwrap().data().to_vec();
+        assert!(SuggestedPalette::decode(&data[..data.len() - 1]).is_err());
+        let mut bad_depth = data.clone();
+        bad_depth[9] = 4;
+        assert!(SuggestedPalette::decode(&bad_depth).is_err());
+        assert!(SuggestedPalette::decode(b"no separator").is_err());
+
+        let mut wide = palette(8);
+        wide.entries[0].red = 256;
+        assert!(wide.encode().is_err());
+        let mut unnamed = palette(16);
+        unnamed.name.clear();
+        assert!(unnamed.encode().is_err());
+    }
+
+    fn calibration() -> PixelCalibration {
+        PixelCalibration {
+            name: String::from("temperature"),
+            x0: 0,
+            x1: 65535,
+            equation: Equation::Linear,
+            unit: String::from("K"),
+            parameters: vec![String::from("-273.15"), String::from("1e3")],
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        use crate::chunk_type::consts::{IDAT, IHDR, PLTE};
+        let histogram = Histogram {
+            frequencies: vec![7, 0, 65535],
+        };
+        let chunk = histogram.encode();
+        assert_eq!(chunk.data(), [0, 7, 0, 0, 255, 255]);
+        assert_eq!(Histogram::decode(chunk.data()).unwrap(), histogram);
+        assert!(Histogram::decode(&[1, 2, 3]).is_err());
+        assert!(histogram.check(3).is_ok());
+        assert!(histogram.check(4).is_err());
+
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(IHDR, vec![0; 13]),
+            Chunk::new(PLTE, vec![0; 9]),
+            chunk,
+            Chunk::new(IDAT, Vec::new()),
+        ]);
+        assert_eq!(png.histogram().unwrap(), Some(histogram));
+        png.replace_chunk("PLTE", vec![0; 6]).unwrap();
+        assert!(png.histogram().is_err());
+        png.remove_chunk("hIST", crate::png::Removal::All).unwrap();
+        assert_eq!(png.histogram().unwrap(), None);
+    }
+
+    #[test]
+    fn test_image_offset() {
+        let offset = ImageOffset {
+            x: -20,
+            y: 300,
+            unit: OffsetUnit::Micrometre,
+        };
+        let chunk = offset.encode();
+        assert_eq!(chunk.length(), 9);
+        assert_eq!(ImageOffset::decode(chunk.data()).unwrap(), offset);
+        assert_eq!(offset.to_string(), "-20, 300 µm");
+        assert!(ImageOffset::decode(&chunk.data()[..8]).is_err());
+        let mut bad_unit = chunk.data().to_vec();
+        bad_unit[8] = 2;
+        assert!(ImageOffset::decode(&bad_unit).is_err());
+    }
+
+    #[test]
+    fn test_physical_scale() {
+        let scale = PhysicalScale {
+            unit: ScaleUnit::Metre,
+            pixel_width: String::from("0.0254"),
+            pixel_height: String::from("2.54E-2"),
+        };
+        let chunk = scale.encode().unwrap();
+        assert_eq!(chunk.data(), b"\x010.0254\x002.54E-2");
+        assert_eq!(PhysicalScale::decode(chunk.data()).unwrap(), scale);
+
+        for size in ["0", "-1", "inf", "NaN", "", "1.5x"] {
+            let bad = PhysicalScale {
+                pixel_height: String::from(size),
+                ..scale.clone()
+            };
+            assert!(bad.encode().is_err(), "{}", size);
+        }
+        assert!(PhysicalScale::decode(b"\x031\x001").is_err());
+    }
+
+    #[test]
+    fn test_pixel_calibration() {
+        let chunk = calibration().encode().unwrap();
+        assert_eq!(
+            PixelCalibration::decode(chunk.data()).unwrap(),
+            calibration()
+        );
+        assert_eq!(
+            calibration().to_string(),
+            "\"temperature\": linear from 0 to 65535, parameters -273.15, 1e3 (K)"
+        );
+
+        let mut short = calibration();
+        short.parameters.pop();
+        assert!(short.encode().is_err());
+        let mut flat = calibration();
+        flat.x1 = 0;
+        assert!(flat.encode().is_err());
+        let mut data = chunk.data().to_vec();
+        data[12 + 9] = 3;
+        assert!(PixelCalibration::decode(&data).is_err());
+    }
+
+    #[test]
+    fn test_png_accessors() {
+        use crate::chunk_type::consts::{IDAT, IEND, IHDR};
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(IHDR, vec![0; 13]),
+            Chunk::new(IDAT, Vec::new()),
+            Chunk::new(IEND, Vec::new()),
+        ]);
+        assert_eq!(png.image_offset().unwrap(), None);
+
+        let offset = ImageOffset {
+            x: 1,
+            y: 2,
+            unit: OffsetUnit::Pixel,
+        };
+        png.set_image_offset(&offset).unwrap();
+        png.set_pixel_calibration(&calibration()).unwrap();
+        let moved = ImageOffset { x: 5, ..offset };
+        png.set_image_offset(&moved).unwrap();
+        assert_eq!(png.image_offset().unwrap(), Some(moved));
+        assert_eq!(png.pixel_calibration().unwrap(), Some(calibration()));
+        assert_eq!(png.physical_scale().unwrap(), None);
+
+        let types: Vec<_> = png.chunks().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "oFFs", "pCAL", "IDAT", "IEND"]);
+
+        png.replace_chunk("oFFs", vec![0; 3]).unwrap();
+        assert!(png.image_offset().is_err());
+    }
+}
@@ -0,0 +1,942 @@
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use pngme::ancillary::{Equation, OffsetUnit, ScaleUnit};
+use pngme::options::ParseOptions;
+use pngme::payload::Compression;
+use pngme::png::Placement;
+use pngme::text::TextFormat;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser)]
+#[command(version, about = "Hide secret messages in PNG files")]
+pub struct Cli {
+    /// Log what the tool is doing; repeat for more detail
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+    /// Never colour the output (the NO_COLOR variable is also honoured)
+    #[arg(long, global = true)]
+    pub no_color: bool,
+    /// Read defaults from this file instead of ~/.config/pngme/config.toml
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// How strictly input files are parsed
+    #[arg(long, global = true, value_name = "MODE")]
+    pub parse_mode: Option<ParseMode>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// The parse option presets a user can pick between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseMode {
+    /// Accept anything a typical decoder would read
+    Default,
+    /// Reject anything the specification does not allow
+    Strict,
+    /// Skip every check that can be skipped
+    Lenient,
+}
+
+impl ParseMode {
+    pub fn options(self) -> ParseOptions {
+        match self {
+            ParseMode::Default => ParseOptions::default(),
+            ParseMode::Strict => ParseOptions::strict(),
+            ParseMode::Lenient => ParseOptions::lenient(),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Encode a message into a new chunk
+    Encode(EncodeArgs),
+    /// Decode the message stored in a chunk
+    Decode(DecodeArgs),
+    /// Remove a chunk
+    Remove(RemoveArgs),
+    /// Print every chunk in the file
+    Print(PrintArgs),
+    /// Summarize the image: size, format and what the other chunks add
+    Info(InfoArgs),
+    /// List the palette entries with their transparency and frequency
+    Palette(PaletteArgs),
+    /// Report how much payload the image could hide, and at what cost
+    Capacity(CapacityArgs),
+    /// Show which chunks take up the file's bytes
+    Stats(StatsArgs),
+    /// Check that the file is well formed
+    Validate(ValidateArgs),
+    /// Classify every chunk and flag anything suspicious
+    Scan(ScanArgs),
+    /// List, read or set textual metadata
+    Meta(MetaArgs),
+    /// Move misplaced chunks to where the specification allows them
+    Reorder(ReorderArgs),
+    /// Rewrite the file into a byte-for-byte reproducible form
+    Canonicalize(CanonicalizeArgs),
+    /// Remove any bytes hidden after IEND
+    Trim(TrimArgs),
+    /// Remove repeated copies of chunks and flag conflicting ones
+    Dedup(DedupArgs),
+    /// Check CRCs and structure, listing failures as JSON
+    Verify(VerifyArgs),
+    /// Print a SHA-256 fingerprint of the image
+    Hash(HashArgs),
+    /// Check whether two files hold the same image
+    ///
+    /// --stream-index picks the same stream from both files.
+    Equal(EqualArgs),
+    /// Write every chunk to its own file in a directory
+    Explode(ExplodeArgs),
+    /// Rebuild a PNG from a directory written by explode
+    Implode(ImplodeArgs),
+    /// Re-encode a payload into a carrier whenever either changes
+    ///
+    /// The payload is stored as is, in a chunk before IEND; use encode for
+    /// compression, encryption, signing or another placement.
+    Watch(WatchArgs),
+    /// Work with tEXt, zTXt and iTXt chunks
+    #[command(subcommand)]
+    Text(TextCommand),
+    /// Set or remove the oFFs, sCAL and pCAL extension chunks
+    #[command(subcommand)]
+    Ext(ExtCommand),
+    /// Read, write or remove the XMP metadata packet
+    #[command(subcommand)]
+    Xmp(XmpCommand),
+    /// Browse, edit and export chunks interactively
+    #[cfg(feature = "tui")]
+    Tui(TuiArgs),
+    /// Create an X25519 key pair for encrypted messages
+    Keygen(KeygenArgs),
+    /// Manage keys kept in the OS keychain
+    #[command(subcommand)]
+    Key(KeyCommand),
+    /// Print a random private ancillary chunk type
+    GenType(GenTypeArgs),
+    /// Print a shell completion script
+    Completions(CompletionsArgs),
+}
+
+#[derive(Args)]
+pub struct EncodeArgs {
+    pub file: PathBuf,
+    pub chunk_type: String,
+    pub message: String,
+    /// How MESSAGE is written, so binary payloads can be passed safely
+    #[arg(long, value_name = "ENCODING", default_value = "raw")]
+    pub input_encoding: PayloadEncoding,
+    /// Compress the message with deflate or zstd, optionally at a level,
+    /// e.g. zstd:19; decode recognizes and undoes it
+    #[arg(long, value_name = "METHOD[:LEVEL]", value_parser = parse_compression)]
+    pub compress: Option<Compression>,
+    /// Fail if the output would be larger than this many bytes, or this
+    /// percentage of FILE, e.g. 110%; with --compress, the strongest level
+    /// is tried first
+    #[arg(long, value_name = "BYTES|PERCENT", value_parser = parse_max_size)]
+    pub max_size: Option<MaxSize>,
+    /// Encrypt the message so only holders of the secret half of these
+    /// public keys can read it; may be repeated
+    #[arg(
+        long = "recipient",
+        visible_alias = "recipient-pubkey",
+        value_name = "FILE"
+    )]
+    pub recipients: Vec<PathBuf>,
+    /// Also encrypt the message to the key stored in the OS keychain under
+    /// this name
+    #[arg(long, value_name = "NAME")]
+    pub key_from_keychain: Option<String>,
+    /// Sign the payload with this secret key from `keygen --signing`, for
+    /// `verify --pubkey`
+    #[arg(long, value_name = "FILE")]
+    pub sign_key: Option<PathBuf>,
+    /// Tag the payload with an HMAC-SHA256 keyed by the bytes of this file,
+    /// for `verify --hmac-key`
+    #[arg(long, value_name = "FILE")]
+    pub hmac_key: Option<PathBuf>,
+    /// Where the payload chunk goes
+    #[arg(long, default_value = "before-iend")]
+    pub placement: PayloadPlacement,
+    /// Store the payload as text under this keyword, so other tools see and
+    /// keep it; CHUNK_TYPE must then be tEXt, zTXt or iTXt
+    #[arg(long, value_name = "KEYWORD")]
+    pub as_text: Option<String>,
+    /// How the payload is written as text; binary payloads need base64 or hex
+    #[arg(
+        long,
+        value_name = "ENCODING",
+        default_value = "raw",
+        requires = "as_text"
+    )]
+    pub text_encoding: PayloadEncoding,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+/// Parses `--compress`: a method name with an optional `:level`.
+pub(crate) fn parse_compression(value: &str) -> Result<Compression, String> {
+    let (method, level) = match value.split_once(':') {
+        Some((method, level)) => (method, Some(level)),
+        None => (value, None),
+    };
+    let invalid = |_| format!("invalid level {:?}", level.unwrap_or_default());
+    let compression = match method {
+        "deflate" => Compression::Deflate {
+            level: level
+                .map_or(Ok(Compression::DEFAULT_DEFLATE_LEVEL), str::parse)
+                .map_err(invalid)?,
+        },
+        "zstd" => Compression::Zstd {
+            level: level
+                .map_or(Ok(Compression::DEFAULT_ZSTD_LEVEL), str::parse)
+                .map_err(invalid)?,
+        },
+        _ => return Err(format!("unknown method {:?}; use deflate or zstd", method)),
+    };
+    compression.check().map_err(|e| e.to_string())?;
+    Ok(compression)
+}
+
+/// A limit on the size of an output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaxSize {
+    Bytes(u64),
+    /// A percentage of the input file's size.
+    Percent(u64),
+}
+
+impl MaxSize {
+    /// The limit in bytes for an input of `input_len` bytes.
+    pub fn limit(self, input_len: u64) -> u64 {
+        match self {
+            MaxSize::Bytes(bytes) => bytes,
+            MaxSize::Percent(percent) => {
+                let limit = input_len as u128 * percent as u128 / 100;
+                limit.min(u64::MAX as u128) as u64
+            }
+        }
+    }
+}
+
+/// Parses `--max-size`: a byte count, or a percentage ending in `%`.
+fn parse_max_size(value: &str) -> Result<MaxSize, String> {
+    let invalid = |_| format!("invalid size {:?}", value);
+    match value.strip_suffix('%') {
+        Some(percent) => Ok(MaxSize::Percent(percent.parse().map_err(invalid)?)),
+        None => Ok(MaxSize::Bytes(value.parse().map_err(invalid)?)),
+    }
+}
+
+/// Where `encode` puts the payload chunk, as named on the command line.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PayloadPlacement {
+    /// Last before IEND, as the specification expects
+    BeforeIend,
+    /// After IEND, which some naive optimizers leave alone
+    AfterIend,
+    /// First after IHDR
+    AfterIhdr,
+}
+
+impl From<PayloadPlacement> for Placement {
+    fn from(placement: PayloadPlacement) -> Self {
+        match placement {
+            PayloadPlacement::BeforeIend => Placement::BeforeIend,
+            PayloadPlacement::AfterIend => Placement::AfterIend,
+            PayloadPlacement::AfterIhdr => Placement::AfterIhdr,
+        }
+    }
+}
+
+/// How a payload is written on the command line or printed.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PayloadEncoding {
+    /// The bytes as they are (printed as UTF-8, lossily)
+    Raw,
+    /// Standard base64 with padding
+    Base64,
+    /// Two hex digits per byte; whitespace is ignored on input
+    Hex,
+}
+
+#[derive(Args)]
+pub struct DecodeArgs {
+    pub file: PathBuf,
+    /// Defaults to the chunk type in the config file
+    pub chunk_type: Option<String>,
+    /// How to print the payload, so binary data survives a terminal
+    #[arg(long, value_name = "ENCODING", default_value = "raw")]
+    pub output_encoding: PayloadEncoding,
+    /// Write the payload byte for byte even if it does not look like text;
+    /// without it, binary is summarized only when stdout is a terminal
+    #[arg(long, conflicts_with = "output_encoding")]
+    pub raw: bool,
+    /// Leave a payload that starts like one written by `encode --compress`
+    /// as it is, for raw payloads that happen to begin with the same bytes
+    #[arg(long)]
+    pub no_decompress: bool,
+    /// Decrypt the payload with this secret key
+    #[arg(long, value_name = "FILE")]
+    pub identity: Option<PathBuf>,
+    /// Decrypt the payload with the secret key stored in the OS keychain
+    /// under this name
+    #[arg(long, value_name = "NAME", conflicts_with = "identity")]
+    pub key_from_keychain: Option<String>,
+    /// Read the payload from the text chunk with this keyword, as written by
+    /// `encode --as-text`, instead of by chunk type
+    #[arg(long, value_name = "KEYWORD")]
+    pub as_text: Option<String>,
+    /// How the payload was written as text
+    #[arg(
+        long,
+        value_name = "ENCODING",
+        default_value = "raw",
+        requires = "as_text"
+    )]
+    pub text_encoding: PayloadEncoding,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Args)]
+pub struct RemoveArgs {
+    pub file: PathBuf,
+    /// Defaults to the chunk type in the config file
+    pub chunk_type: Option<String>,
+    /// Remove every chunk of the type instead of only the first
+    #[arg(long, conflicts_with = "index")]
+    pub all: bool,
+    /// Remove the nth (zero-based) chunk of the type
+    #[arg(long)]
+    pub index: Option<usize>,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+/// Flags shared by commands that read a single image.
+#[derive(Args)]
+pub struct StreamArgs {
+    /// Read the Nth (from 0) of several PNGs stored back to back in FILE;
+    /// offsets shown count from where it begins
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub stream_index: usize,
+}
+
+/// Flags shared by every command that modifies a file.
+#[derive(Args)]
+pub struct WriteArgs {
+    /// Write here instead of editing FILE in place; a directory keeps the
+    /// file name. Defaults to the config output directory
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+    /// Overwrite an existing output file
+    #[arg(short, long)]
+    pub force: bool,
+    /// Report what would change without writing anything
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Keep a copy of any file being replaced, named with this suffix added
+    #[arg(long, value_name = "SUFFIX")]
+    pub backup: Option<OsString>,
+}
+
+#[derive(Args)]
+pub struct PrintArgs {
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Args)]
+pub struct InfoArgs {
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Args)]
+pub struct PaletteArgs {
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    pub file: PathBuf,
+    /// How many of the largest chunks to list
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub top: usize,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Args)]
+pub struct CapacityArgs {
+    pub file: PathBuf,
+    /// Where the payload would be hidden
+    #[arg(long, default_value = "lsb")]
+    pub mode: CapacityMode,
+    /// Estimate the visual impact of hiding this many bytes
+    #[arg(long, value_name = "BYTES")]
+    pub payload_size: Option<u64>,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+/// The hiding places `capacity` can measure.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CapacityMode {
+    /// The least significant bits of the pixel samples
+    Lsb,
+}
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Args)]
+pub struct ScanArgs {
+    pub file: PathBuf,
+    /// Print the findings as JSON for scripts
+    #[arg(long)]
+    pub json: bool,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Args)]
+pub struct MetaArgs {
+    pub file: PathBuf,
+    /// Print only the text stored under this keyword
+    #[arg(long, value_name = "KEYWORD", conflicts_with = "set")]
+    pub get: Option<String>,
+    /// Store VALUE under KEYWORD, replacing any existing text
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["KEYWORD", "VALUE"],
+        conflicts_with = "stream_index"
+    )]
+    pub set: Option<Vec<String>>,
+    #[command(flatten)]
+    pub write: WriteArgs,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Args)]
+pub struct ReorderArgs {
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(Args)]
+pub struct CanonicalizeArgs {
+    pub file: PathBuf,
+    /// Fail if the output would be larger than this many bytes, or this
+    /// percentage of FILE, e.g. 110%
+    #[arg(long, value_name = "BYTES|PERCENT", value_parser = parse_max_size)]
+    pub max_size: Option<MaxSize>,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(Args)]
+pub struct DedupArgs {
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(Args)]
+pub struct TrimArgs {
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    pub file: PathBuf,
+    /// Also require every payload written by `encode --sign-key` to carry a
+    /// valid signature from the secret half of this public key
+    #[arg(long, value_name = "FILE")]
+    pub pubkey: Option<PathBuf>,
+    /// Also require every payload written by `encode --hmac-key` to carry a
+    /// valid HMAC under the bytes of this file
+    #[arg(long, value_name = "FILE")]
+    pub hmac_key: Option<PathBuf>,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Args)]
+pub struct HashArgs {
+    pub file: PathBuf,
+    /// Hash only the image header, palette and pixel rows
+    #[arg(long, conflicts_with = "chunks")]
+    pub pixels_only: bool,
+    /// Which chunks to hash
+    #[arg(long, value_name = "WHICH", default_value = "all")]
+    pub chunks: ChunkSelection,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ChunkSelection {
+    /// Every chunk
+    All,
+    /// Critical chunks only, ignoring metadata
+    Critical,
+}
+
+#[derive(Args)]
+pub struct EqualArgs {
+    pub first: PathBuf,
+    pub second: PathBuf,
+    /// Compare only critical chunks
+    #[arg(long, conflicts_with = "pixels")]
+    pub ignore_ancillary: bool,
+    /// Compare only the image header, palette and pixel rows
+    #[arg(long)]
+    pub pixels: bool,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Args)]
+pub struct ExplodeArgs {
+    pub file: PathBuf,
+    pub dir: PathBuf,
+    /// Write into DIR even if it is not empty
+    #[arg(short, long)]
+    pub force: bool,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Args)]
+pub struct ImplodeArgs {
+    pub dir: PathBuf,
+    pub output: PathBuf,
+    /// Overwrite OUTPUT if it exists
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+#[derive(Subcommand)]
+pub enum TextCommand {
+    /// Rewrite every text chunk in one format
+    Convert(ConvertArgs),
+}
+
+/// The text chunk formats, as named on the command line.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum TextFormatArg {
+    /// Uncompressed Latin-1
+    Text,
+    /// Compressed Latin-1
+    Ztxt,
+    /// UTF-8, compression kept
+    Itxt,
+}
+
+impl From<TextFormatArg> for TextFormat {
+    fn from(format: TextFormatArg) -> Self {
+        match format {
+            TextFormatArg::Text => TextFormat::Text,
+            TextFormatArg::Ztxt => TextFormat::Ztxt,
+            TextFormatArg::Itxt => TextFormat::Itxt,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum ExtCommand {
+    /// Set where the image sits on a page or larger image (oFFs)
+    Offset(OffsetArgs),
+    /// Set the physical size of one pixel (sCAL)
+    Scale(ScaleArgs),
+    /// Set how samples map to physical values (pCAL)
+    Calibration(CalibrationArgs),
+    /// Remove an extension chunk
+    Remove(ExtRemoveArgs),
+}
+
+impl ExtCommand {
+    pub fn file(&self) -> &PathBuf {
+        match self {
+            ExtCommand::Offset(args) => &args.file,
+            ExtCommand::Scale(args) => &args.file,
+            ExtCommand::Calibration(args) => &args.file,
+            ExtCommand::Remove(args) => &args.file,
+        }
+    }
+
+    pub fn write(&self) -> &WriteArgs {
+        match self {
+            ExtCommand::Offset(args) => &args.write,
+            ExtCommand::Scale(args) => &args.write,
+            ExtCommand::Calibration(args) => &args.write,
+            ExtCommand::Remove(args) => &args.write,
+        }
+    }
+
+    pub fn write_args(&mut self) -> (&PathBuf, &mut WriteArgs) {
+        match self {
+            ExtCommand::Offset(args) => (&args.file, &mut args.write),
+            ExtCommand::Scale(args) => (&args.file, &mut args.write),
+            ExtCommand::Calibration(args) => (&args.file, &mut args.write),
+            ExtCommand::Remove(args) => (&args.file, &mut args.write),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct OffsetArgs {
+    pub file: PathBuf,
+    #[arg(allow_negative_numbers = true)]
+    pub x: i32,
+    #[arg(allow_negative_numbers = true)]
+    pub y: i32,
+    #[arg(long, default_value = "pixel")]
+    pub unit: OffsetUnitArg,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OffsetUnitArg {
+    Pixel,
+    Micrometre,
+}
+
+impl From<OffsetUnitArg> for OffsetUnit {
+    fn from(unit: OffsetUnitArg) -> Self {
+        match unit {
+            OffsetUnitArg::Pixel => OffsetUnit::Pixel,
+            OffsetUnitArg::Micrometre => OffsetUnit::Micrometre,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ScaleArgs {
+    pub file: PathBuf,
+    /// Width of a pixel, as a decimal number such as 0.0254 or 2.5e-6
+    pub width: String,
+    /// Height of a pixel
+    pub height: String,
+    #[arg(long, default_value = "metre")]
+    pub unit: ScaleUnitArg,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ScaleUnitArg {
+    Metre,
+    Radian,
+}
+
+impl From<ScaleUnitArg> for ScaleUnit {
+    fn from(unit: ScaleUnitArg) -> Self {
+        match unit {
+            ScaleUnitArg::Metre => ScaleUnit::Metre,
+            ScaleUnitArg::Radian => ScaleUnit::Radian,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct CalibrationArgs {
+    pub file: PathBuf,
+    /// What the values measure, e.g. "temperature"
+    pub name: String,
+    /// The sample value mapped to the start of the equation's range
+    #[arg(allow_negative_numbers = true)]
+    pub x0: i32,
+    /// The sample value mapped to the end of the equation's range
+    #[arg(allow_negative_numbers = true)]
+    pub x1: i32,
+    /// The equation's parameters, p0 first
+    #[arg(required = true, allow_negative_numbers = true)]
+    pub parameters: Vec<String>,
+    #[arg(long, default_value = "linear")]
+    pub equation: EquationArg,
+    /// Unit of the physical values, e.g. K
+    #[arg(long, default_value = "")]
+    pub unit: String,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+/// The pCAL equation types, as named on the command line.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum EquationArg {
+    /// p0 + p1 * x / x_max
+    Linear,
+    /// p0 + p1 * e^(p2 * x / x_max)
+    Exponential,
+    /// p0 + p1 * p3^(p2 * x / x_max)
+    ArbitraryBase,
+    /// p0 + p1 * sinh(p2 * (x - p3) / x_max)
+    Hyperbolic,
+}
+
+impl From<EquationArg> for Equation {
+    fn from(equation: EquationArg) -> Self {
+        match equation {
+            EquationArg::Linear => Equation::Linear,
+            EquationArg::Exponential => Equation::Exponential,
+            EquationArg::ArbitraryBase => Equation::ArbitraryBase,
+            EquationArg::Hyperbolic => Equation::Hyperbolic,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ExtRemoveArgs {
+    pub file: PathBuf,
+    pub extension: ExtensionArg,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+/// The extension chunks `ext` edits.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExtensionArg {
+    /// oFFs
+    Offset,
+    /// sCAL
+    Scale,
+    /// pCAL
+    Calibration,
+}
+
+#[derive(Subcommand)]
+pub enum XmpCommand {
+    /// Print the XMP packet
+    Get(XmpGetArgs),
+    /// Store an XMP packet, replacing any already there
+    Set(XmpSetArgs),
+    /// Remove the XMP packet
+    Remove(XmpRemoveArgs),
+}
+
+impl XmpCommand {
+    pub fn file(&self) -> &PathBuf {
+        match self {
+            XmpCommand::Get(args) => &args.file,
+            XmpCommand::Set(args) => &args.file,
+            XmpCommand::Remove(args) => &args.file,
+        }
+    }
+
+    /// The file and write options of the commands that edit the file.
+    pub fn write_args(&mut self) -> Option<(&PathBuf, &mut WriteArgs)> {
+        match self {
+            XmpCommand::Get(_) => None,
+            XmpCommand::Set(args) => Some((&args.file, &mut args.write)),
+            XmpCommand::Remove(args) => Some((&args.file, &mut args.write)),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct XmpGetArgs {
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub stream: StreamArgs,
+}
+
+#[derive(Args)]
+pub struct XmpSetArgs {
+    pub file: PathBuf,
+    /// File holding the XMP packet, or - to read it from standard input
+    pub packet: PathBuf,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(Args)]
+pub struct XmpRemoveArgs {
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(Args)]
+pub struct ConvertArgs {
+    pub file: PathBuf,
+    /// The format to convert to
+    #[arg(long, value_name = "FORMAT")]
+    pub to: TextFormatArg,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(Args)]
+pub struct KeygenArgs {
+    /// Where the secret key goes; the public key is written beside it with
+    /// .pub added
+    pub path: PathBuf,
+    /// Make an Ed25519 signing key pair for `encode --sign-key` and
+    /// `verify --pubkey` instead of an encryption key pair
+    #[arg(long)]
+    pub signing: bool,
+    /// Overwrite existing key files
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+#[derive(Subcommand)]
+pub enum KeyCommand {
+    /// Copy a secret key file into the OS keychain
+    Store(KeyStoreArgs),
+}
+
+#[derive(Args)]
+pub struct KeyStoreArgs {
+    /// The name to pass to --key-from-keychain
+    pub name: String,
+    /// A secret key file written by keygen
+    pub file: PathBuf,
+}
+
+#[derive(Args)]
+pub struct GenTypeArgs {
+    /// Mark the type safe to copy for editors that do not know it
+    #[arg(long)]
+    pub safe_to_copy: bool,
+}
+
+#[cfg(feature = "tui")]
+#[derive(Args)]
+pub struct TuiArgs {
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+    /// File whose contents become the chunk data
+    #[arg(long, value_name = "PATH")]
+    pub payload: PathBuf,
+    /// PNG the chunk is added to; it is never modified
+    #[arg(long, value_name = "PATH")]
+    pub carrier: PathBuf,
+    /// Where the carrier with the payload added is written, replacing it
+    /// each time
+    #[arg(short, long, value_name = "PATH")]
+    pub output: PathBuf,
+    /// Defaults to the chunk type in the config file
+    #[arg(long)]
+    pub chunk_type: Option<String>,
+    /// Replace OUTPUT even if it exists before watching starts
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+    pub shell: Shell,
+}
+
+/// The full argument definition, for tools such as completion generators
+/// that need it as a builder rather than parsed values.
+pub fn command() -> clap::Command {
+    Cli::command()
+}
+
+impl Command {
+    /// The PNG file the command operates on, if any.
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            Command::Encode(args) => Some(&args.file),
+            Command::Decode(args) => Some(&args.file),
+            Command::Remove(args) => Some(&args.file),
+            Command::Print(args) => Some(&args.file),
+            Command::Info(args) => Some(&args.file),
+            Command::Palette(args) => Some(&args.file),
+            Command::Capacity(args) => Some(&args.file),
+            Command::Stats(args) => Some(&args.file),
+            Command::Validate(args) => Some(&args.file),
+            Command::Scan(args) => Some(&args.file),
+            Command::Meta(args) => Some(&args.file),
+            Command::Reorder(args) => Some(&args.file),
+            Command::Canonicalize(args) => Some(&args.file),
+            Command::Dedup(args) => Some(&args.file),
+            Command::Trim(args) => Some(&args.file),
+            Command::Verify(args) => Some(&args.file),
+            Command::Hash(args) => Some(&args.file),
+            Command::Equal(_) => None,
+            Command::Explode(args) => Some(&args.file),
+            Command::Implode(_) => None,
+            Command::Watch(args) => Some(&args.carrier),
+            Command::Text(TextCommand::Convert(args)) => Some(&args.file),
+            Command::Ext(command) => Some(command.file()),
+            Command::Xmp(command) => Some(command.file()),
+            #[cfg(feature = "tui")]
+            Command::Tui(args) => Some(&args.file),
+            Command::Keygen(_) => None,
+            Command::Key(_) => None,
+            Command::GenType(_) => None,
+            Command::Completions(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_compression() {
+        assert_eq!(
+            parse_compression("deflate").unwrap(),
+            Compression::Deflate {
+                level: Compression::DEFAULT_DEFLATE_LEVEL
+            }
+        );
+        assert_eq!(
+            parse_compression("zstd:19").unwrap(),
+            Compression::Zstd { level: 19 }
+        );
+        assert!(parse_compression("deflate:11").is_err());
+        assert!(parse_compression("zstd:high").is_err());
+        assert!(parse_compression("brotli").is_err());
+    }
+
+    #[test]
+    fn test_parse_max_size() {
+        assert_eq!(parse_max_size("4096").unwrap(), MaxSize::Bytes(4096));
+        assert_eq!(parse_max_size("110%").unwrap(), MaxSize::Percent(110));
+        assert!(parse_max_size("").is_err());
+        assert!(parse_max_size("%").is_err());
+        assert!(parse_max_size("-1").is_err());
+        assert!(parse_max_size("1.5%").is_err());
+        assert!(parse_max_size("10 KiB").is_err());
+    }
+
+    #[test]
+    fn test_max_size_limit() {
+        assert_eq!(MaxSize::Bytes(100).limit(1_000), 100);
+        assert_eq!(MaxSize::Percent(110).limit(1_000), 1_100);
+        assert_eq!(MaxSize::Percent(50).limit(3), 1);
+        assert_eq!(MaxSize::Percent(200).limit(u64::MAX), u64::MAX);
+        assert_eq!(MaxSize::Percent(50).limit(u64::MAX), u64::MAX / 2);
+    }
+}
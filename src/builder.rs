@@ -0,0 +1,166 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::consts::{IDAT, IEND, IHDR, TEXT};
+use crate::order;
+use crate::png::Png;
+use crate::text::{self, TextChunk, TextFormat};
+use alloc::format;
+use alloc::vec::Vec;
+
+/// Builds a [`Png`] chunk by chunk, starting from an IHDR and ending with an
+/// IEND that [`PngBuilder::build`] adds.
+pub struct PngBuilder {
+    chunks: Vec<Chunk>,
+}
+
+fn latin1(s: &str) -> impl Iterator<Item = u8> + '_ {
+    s.chars().map(|c| u8::try_from(c).unwrap_or(b'?'))
+}
+
+impl PngBuilder {
+    /// Starts a file whose IHDR holds `ihdr`, the 13 raw bytes of the header.
+    pub fn new(ihdr: Vec<u8>) -> crate::Result<Self> {
+        if ihdr.len() != 13 {
+            return Err("IHDR data must be 13 bytes".into());
+        }
+        Ok(Self {
+            chunks: Vec::from([Chunk::new(IHDR, ihdr)]),
+        })
+    }
+
+    /// Starts a file with the same IHDR (and PLTE, if any) as `template`.
+    pub fn from_template(template: &Png) -> crate::Result<Self> {
+        let ihdr = template
+            .chunk_by_type("IHDR")
+            .ok_or("template has no IHDR chunk")?;
+        let mut builder = Self::new(ihdr.data().to_vec())?;
+        if let Some(plte) = template.chunk_by_type("PLTE") {
+            builder = builder.add_chunk(plte.clone());
+        }
+        Ok(builder)
+    }
+
+    pub fn add_idat(self, data: Vec<u8>) -> Self {
+        self.add_chunk(Chunk::new(IDAT, data))
+    }
+
+    /// Adds a tEXt chunk. `keyword` and `text` are stored as Latin-1, so any
+    /// character above U+00FF is replaced with `?`.
+    pub fn add_text(self, keyword: &str, text: &str) -> Self {
+        let data = latin1(keyword).chain([0]).chain(latin1(text)).collect();
+        self.add_chunk(Chunk::new(TEXT, data))
+    }
+
+    pub fn add_chunk(mut self, chunk: Chunk) -> Self {
+        self.chunks.push(chunk);
+        self
+    }
+
+    /// Appends IEND and checks the chunks are in a legal order and that every
+    /// text chunk has a valid keyword.
+    pub fn build(mut self) -> crate::Result<Png> {
+        if self.chunks.iter().all(|c| *c.chunk_type() != IDAT) {
+            return Err("a PNG needs at least one IDAT chunk".into());
+        }
+        for chunk in self.chunks.iter() {
+            if TextFormat::of(chunk.chunk_type()).is_some() {
+                text::check_keyword(&TextChunk::try_from(chunk)?.keyword)?;
+            }
+        }
+        self.chunks.push(Chunk::new(IEND, Vec::new()));
+
+        let types: Vec<_> = self.chunks.iter().map(|c| c.chunk_type()).collect();
+        if let Some(v) = order::violations(&types).first() {
+            return Err(format!("invalid chunk order: {}", v.message).into());
+        }
+        Ok(Png::from_chunks(self.chunks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::consts::PLTE;
+
+    fn ihdr() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(4u32.to_be_bytes());
+        data.extend(4u32.to_be_bytes());
+        data.extend([8, 2, 0, 0, 0]);
+        data
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks().map(|c| c.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_build() {
+        let png = PngBuilder::new(ihdr())
+            .unwrap()
+            .add_idat(b"pixels".to_vec())
+            .add_idat(b"more pixels".to_vec())
+            .add_text("Comment", "héllo ✓")
+            .build()
+            .unwrap();
+
+        assert_eq!(types(&png), ["IHDR", "IDAT", "IDAT", "tEXt", "IEND"]);
+        assert_eq!(
+            png.chunk_by_type("tEXt").unwrap().data(),
+            b"Comment\0h\xe9llo ?"
+        );
+        assert!(Png::try_from(png.as_bytes().as_ref()).is_ok());
+    }
+
+    #[test]
+    fn test_build_enforces_order() {
+        let builder = PngBuilder::new(ihdr())
+            .unwrap()
+            .add_idat(b"pixels".to_vec())
+            .add_chunk(Chunk::new(PLTE, b"late palette".to_vec()));
+        assert!(builder.build().is_err());
+
+        let builder = PngBuilder::new(ihdr())
+            .unwrap()
+            .add_chunk(Chunk::new(IHDR, ihdr()))
+            .add_idat(b"pixels".to_vec());
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn test_build_checks_keywords() {
+        let builder = PngBuilder::new(ihdr())
+            .unwrap()
+            .add_idat(b"pixels".to_vec())
+            .add_text("Creation  Time", "today");
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn test_build_requires_idat() {
+        assert!(PngBuilder::new(ihdr()).unwrap().build().is_err());
+    }
+
+    #[test]
+    fn test_invalid_ihdr() {
+        assert!(PngBuilder::new(b"short".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_from_template() {
+        let template = PngBuilder::new(ihdr())
+            .unwrap()
+            .add_chunk(Chunk::new(PLTE, b"palette".to_vec()))
+            .add_idat(b"pixels".to_vec())
+            .add_text("Title", "template")
+            .build()
+            .unwrap();
+
+        let png = PngBuilder::from_template(&template)
+            .unwrap()
+            .add_idat(b"new pixels".to_vec())
+            .build()
+            .unwrap();
+        assert_eq!(types(&png), ["IHDR", "PLTE", "IDAT", "IEND"]);
+        assert_eq!(png.chunk_by_type("IHDR").unwrap().data(), ihdr());
+    }
+}
@@ -1,101 +1,429 @@
 use crate::chunk_type::ChunkType;
-use crc::{Algorithm, Crc, CRC_32_ISO_HDLC};
-use std::fmt::{Display, Formatter};
-
+use crate::error::{ParseError, ParseErrorKind};
+use crate::options::ParseOptions;
+use alloc::borrow::Cow;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Display, Formatter};
+use core::hash::{Hash, Hasher};
+use core::ops::Range;
+#[cfg(not(feature = "crc32fast"))]
+use crc::{CRC_32_ISO_HDLC, Crc};
+
+/// Chunks compare and hash by type and data; the CRC is derived from both.
+#[derive(Clone)]
 pub struct Chunk {
     typ: ChunkType,
-    data: Vec<u8>,
+    data: Data,
     crc: u32,
 }
 
-const CRC_32_POLY: u32 = 0x04C11DB7;
-const CRC_32_ALGO: Algorithm<u32> = Algorithm {
-    poly: CRC_32_POLY,
-    init: 0xFFFFFFFF,
-    refin: true,
-    refout: true,
-    xorout: 0xFFFFFFFF,
-    check: 0xCBF43926,
-    residue: 0x00000000,
-    width: 32,
-};
+/// Where a [`Chunk`]'s data lives: a buffer of its own, or a range of a file
+/// buffer shared with the other chunks parsed from it, so that editing one
+/// chunk of a parsed file copies no other chunk's data.
+#[derive(Clone)]
+enum Data {
+    Owned(Vec<u8>),
+    Shared(Arc<Vec<u8>>, Range<usize>),
+}
+
+impl Data {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Data::Owned(data) => data,
+            Data::Shared(buffer, range) => &buffer[range.clone()],
+        }
+    }
+
+    fn into_vec(self) -> Vec<u8> {
+        match self {
+            Data::Owned(data) => data,
+            Data::Shared(buffer, range) => buffer[range].to_vec(),
+        }
+    }
+}
+
+impl PartialEq for Chunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.typ == other.typ && self.data() == other.data() && self.crc == other.crc
+    }
+}
+
+impl Eq for Chunk {}
+
+impl Hash for Chunk {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.typ.hash(state);
+        self.data().hash(state);
+        self.crc.hash(state);
+    }
+}
+
+/// A chunk whose data borrows from the buffer it was parsed from until it is
+/// replaced, so editing one chunk of a file leaves the rest uncopied.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ChunkRef<'a> {
+    typ: ChunkType,
+    data: Cow<'a, [u8]>,
+    crc: u32,
+}
+
+/// Adaptors for iterators over chunks.
+pub trait ChunksExt<'a>: Iterator<Item = &'a Chunk> + Sized {
+    /// Keeps only ancillary chunks, i.e. those decoders may safely ignore.
+    fn filter_ancillary(self) -> impl Iterator<Item = &'a Chunk> {
+        self.filter(|c| !c.chunk_type().is_critical())
+    }
+
+    /// Keeps only critical chunks.
+    fn filter_critical(self) -> impl Iterator<Item = &'a Chunk> {
+        self.filter(|c| c.chunk_type().is_critical())
+    }
+}
+
+impl<'a, I: Iterator<Item = &'a Chunk>> ChunksExt<'a> for I {}
+
+#[cfg(not(feature = "crc32fast"))]
+static CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// A chunk CRC computed over data that arrives in pieces.
+#[cfg(not(feature = "crc32fast"))]
+pub(crate) struct Checksum(crc::Digest<'static, u32>);
+
+/// A chunk CRC computed over data that arrives in pieces, hardware-accelerated
+/// (SSE4.2/PCLMULQDQ or the ARM CRC extension) where the CPU supports it.
+#[cfg(feature = "crc32fast")]
+pub(crate) struct Checksum(crc32fast::Hasher);
+
+impl Checksum {
+    /// Starts the CRC of a chunk of type `chunk_type`.
+    pub(crate) fn new(chunk_type: &ChunkType) -> Self {
+        #[cfg(not(feature = "crc32fast"))]
+        let mut checksum = Self(CRC32.digest());
+        #[cfg(feature = "crc32fast")]
+        let mut checksum = Self(crc32fast::Hasher::new());
+        checksum.update(&chunk_type.bytes());
+        checksum
+    }
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+    pub(crate) fn finalize(self) -> u32 {
+        self.0.finalize()
+    }
+}
+
+fn checksum(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+    let mut checksum = Checksum::new(chunk_type);
+    checksum.update(data);
+    checksum.finalize()
+}
 
 impl TryFrom<&[u8]> for Chunk {
-    type Error = ();
+    type Error = ParseError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        if value.len() < 4 {
-            return Err(());
-        }
+        Self::parse(value, &ParseOptions::default())
+    }
+}
 
-        let mut len = [0u8; 4];
-        len.copy_from_slice(&value[..4]);
-        let len = u32::from_be_bytes(len) as usize;
-        let mut remain = &value[4..];
-        if remain.len() < len + 8 {
-            return Err(());
-        }
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+    type Error = ParseError;
 
-        let mut typ = [0u8; 4];
-        typ.copy_from_slice(&remain[..4]);
-        remain = &remain[4..];
-        let data = &remain[..len];
-        remain = &remain[len..];
-        let typ = ChunkType::try_from(typ)?;
-        let chunk = Chunk::new(typ, data.to_vec());
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        Self::parse(value, &ParseOptions::default())
+    }
+}
 
-        let mut crc = [0u8; 4];
-        crc.copy_from_slice(remain);
-        let crc = u32::from_be_bytes(crc);
+/// Number of bytes shown in the hex preview of binary data.
+const HEX_PREVIEW_LEN: usize = 16;
+
+/// Writes `data` as text if it is printable UTF-8, or as an abbreviated hex
+/// preview such as `<2048 bytes: 78 9c 6b ...>` otherwise.
+fn fmt_data(data: &[u8], f: &mut Formatter<'_>) -> core::fmt::Result {
+    if let Ok(text) = core::str::from_utf8(data)
+        && !text
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r'))
+    {
+        return f.write_str(text);
+    }
+    fmt_hex(data, f)
+}
 
-        if chunk.crc != crc {
-            return Err(());
-        }
+/// Writes `data` as its length and an abbreviated hex preview, whatever it
+/// holds.
+fn fmt_hex(data: &[u8], f: &mut Formatter<'_>) -> core::fmt::Result {
+    write!(f, "<{} bytes:", data.len())?;
+    for b in data.iter().take(HEX_PREVIEW_LEN) {
+        write!(f, " {:02x}", b)?;
+    }
+    if data.len() > HEX_PREVIEW_LEN {
+        f.write_str(" ...")?;
+    }
+    f.write_str(">")
+}
 
-        Ok(chunk)
+/// Shows raw bytes in `Debug` output the way [`fmt_hex`] writes them.
+pub(crate) struct HexPreview<'a>(pub(crate) &'a [u8]);
+
+impl Debug for HexPreview<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        fmt_hex(self.0, f)
     }
 }
 
 impl Display for Chunk {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self.data_as_string() {
-            Ok(v) => write!(f, "{}", v),
-            Err(e) => write!(f, "Error: {}", e),
-        }
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        fmt_data(self.data(), f)
+    }
+}
+
+impl Display for ChunkRef<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        fmt_data(&self.data, f)
+    }
+}
+
+impl Debug for Chunk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Chunk")
+            .field("chunk_type", &format_args!("{}", self.typ))
+            .field("length", &self.length())
+            .field("crc", &format_args!("{:#010x}", self.crc))
+            .finish()
+    }
+}
+
+impl Debug for ChunkRef<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ChunkRef")
+            .field("chunk_type", &format_args!("{}", self.typ))
+            .field("length", &self.length())
+            .field("crc", &format_args!("{:#010x}", self.crc))
+            .finish()
     }
 }
 
 impl Chunk {
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self {
-        let crc32 = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-        let mut digest = crc32.digest();
-        digest.update(&chunk_type.bytes());
-        digest.update(&data);
-        let crc = digest.finalize();
+        let crc = checksum(&chunk_type, &data);
 
         Self {
             typ: chunk_type,
-            data,
+            data: Data::Owned(data),
             crc,
         }
     }
-    fn length(&self) -> u32 {
+    /// Builds a chunk read from a file, keeping its stored CRC as is.
+    #[cfg(feature = "std")]
+    pub(crate) fn with_crc(chunk_type: ChunkType, data: Vec<u8>, crc: u32) -> Self {
+        Self {
+            typ: chunk_type,
+            data: Data::Owned(data),
+            crc,
+        }
+    }
+    /// Parses a single chunk occupying all of `value`.
+    pub fn parse(value: &[u8], options: &ParseOptions) -> Result<Self, ParseError> {
+        ChunkRef::parse(value, options).map(|c| c.to_chunk())
+    }
+    /// Builds a chunk whose data is `range` of `buffer`, keeping its stored
+    /// CRC as is.
+    pub(crate) fn shared(
+        chunk_type: ChunkType,
+        buffer: &Arc<Vec<u8>>,
+        range: Range<usize>,
+        crc: u32,
+    ) -> Self {
+        Self {
+            typ: chunk_type,
+            data: Data::Shared(Arc::clone(buffer), range),
+            crc,
+        }
+    }
+    /// Replaces the chunk data, recomputing the CRC, and returns the old data.
+    pub fn set_data(&mut self, data: Vec<u8>) -> Vec<u8> {
+        self.crc = checksum(&self.typ, &data);
+        core::mem::replace(&mut self.data, Data::Owned(data)).into_vec()
+    }
+    /// Length of the chunk's data in bytes, as stored in its length field.
+    pub fn length(&self) -> u32 {
+        self.data().len() as u32
+    }
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.typ
+    }
+    /// The chunk's data, without the length, type and CRC around it.
+    pub fn data(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+    /// The CRC over the chunk's type and data, kept up to date as the data
+    /// is replaced.
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+    pub fn data_as_string(&self) -> crate::Result<String> {
+        Ok(String::from_utf8_lossy(self.data()).to_string())
+    }
+    /// Whether the data still lies in a buffer shared with other chunks,
+    /// as after [`Png::parse_owned`](crate::png::Png::parse_owned).
+    pub fn is_shared(&self) -> bool {
+        matches!(self.data, Data::Shared(..))
+    }
+    /// The chunk as it appears in a file: length, type, data and CRC.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.data().len() + 12);
+        self.write_bytes(&mut bytes);
+        bytes
+    }
+    /// Appends the chunk as it appears in a file to `bytes`.
+    pub(crate) fn write_bytes(&self, bytes: &mut Vec<u8>) {
+        write_bytes(bytes, &self.typ, self.data(), self.crc);
+    }
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, chunk_type: &ChunkType, data: &[u8], crc: u32) {
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&chunk_type.bytes());
+    bytes.extend_from_slice(data);
+    bytes.extend_from_slice(&crc.to_be_bytes());
+}
+
+impl From<Chunk> for ChunkRef<'_> {
+    fn from(chunk: Chunk) -> Self {
+        Self {
+            typ: chunk.typ,
+            data: Cow::Owned(chunk.data.into_vec()),
+            crc: chunk.crc,
+        }
+    }
+}
+
+impl<'a> ChunkRef<'a> {
+    /// Parses a single chunk occupying all of `value`.
+    pub fn parse(value: &'a [u8], options: &ParseOptions) -> Result<Self, ParseError> {
+        let (chunk, remain) = Self::parse_prefix(value, 0, None, options)?;
+        if !remain.is_empty() {
+            let kind = ParseErrorKind::TrailingData {
+                length: remain.len() as u64,
+            };
+            return Err(ParseError::new(kind, (value.len() - remain.len()) as u64)
+                .in_chunk(None, Some(chunk.typ)));
+        }
+
+        Ok(chunk)
+    }
+    /// Parses the chunk at the start of `value`, returning it together with the
+    /// bytes that follow it. `offset` is the absolute position of `value` and
+    /// `index` the chunk's position in its file, both used to locate errors.
+    pub(crate) fn parse_prefix(
+        value: &'a [u8],
+        offset: u64,
+        index: Option<usize>,
+        options: &ParseOptions,
+    ) -> Result<(Self, &'a [u8]), ParseError> {
+        let error =
+            |kind, at: usize, typ| ParseError::new(kind, offset + at as u64).in_chunk(index, typ);
+        if value.len() < 8 {
+            return Err(error(ParseErrorKind::Truncated, 0, None));
+        }
+
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&value[..4]);
+        let len = u32::from_be_bytes(len);
+        let mut typ = [0u8; 4];
+        typ.copy_from_slice(&value[4..8]);
+        let typ = ChunkType::try_from(typ)
+            .map_err(|_| error(ParseErrorKind::InvalidChunkType(typ), 4, None))?;
+        options
+            .check_chunk_len(len)
+            .map_err(|kind| error(kind, 0, Some(typ)))?;
+        let len = len as usize;
+        if value.len() < len + 12 {
+            return Err(error(ParseErrorKind::Truncated, 0, Some(typ)));
+        }
+
+        let data = &value[8..8 + len];
+        let mut crc = [0u8; 4];
+        crc.copy_from_slice(&value[8 + len..12 + len]);
+        let crc = u32::from_be_bytes(crc);
+
+        if options.verify_crc {
+            let computed = checksum(&typ, data);
+            if computed != crc {
+                event!(debug, offset, chunk_type = %typ, stored = crc, computed, "CRC mismatch");
+                let kind = ParseErrorKind::CrcMismatch {
+                    stored: crc,
+                    computed,
+                };
+                return Err(error(kind, 8 + len, Some(typ)));
+            }
+            event!(trace, offset, chunk_type = %typ, crc, "verified CRC");
+        }
+
+        event!(trace, offset, chunk_type = %typ, length = len, "decoded chunk");
+        let data = Cow::Borrowed(data);
+        Ok((Self { typ, data, crc }, &value[12 + len..]))
+    }
+    pub fn length(&self) -> u32 {
         self.data.len() as u32
     }
-    fn chunk_type(&self) -> &ChunkType {
+    pub fn chunk_type(&self) -> &ChunkType {
         &self.typ
     }
-    fn data(&self) -> &[u8] {
+    pub fn data(&self) -> &[u8] {
         &self.data
     }
-    fn crc(&self) -> u32 {
+    /// The data with the lifetime of the source buffer, or `None` once it
+    /// has been replaced with [`ChunkRef::set_data`].
+    pub fn borrowed_data(&self) -> Option<&'a [u8]> {
+        match self.data {
+            Cow::Borrowed(data) => Some(data),
+            Cow::Owned(_) => None,
+        }
+    }
+    /// Replaces the data, recomputing the CRC, and returns the old data.
+    /// Only this chunk stops borrowing from the source buffer.
+    pub fn set_data(&mut self, data: Vec<u8>) -> Cow<'a, [u8]> {
+        self.crc = checksum(&self.typ, &data);
+        core::mem::replace(&mut self.data, Cow::Owned(data))
+    }
+    /// Whether the data has been replaced rather than borrowed.
+    pub fn is_owned(&self) -> bool {
+        matches!(self.data, Cow::Owned(_))
+    }
+    pub fn crc(&self) -> u32 {
         self.crc
     }
-    fn data_as_string(&self) -> crate::Result<String> {
+    pub fn data_as_string(&self) -> crate::Result<String> {
         Ok(String::from_utf8_lossy(&self.data).to_string())
     }
-    fn as_bytes(&self) -> Vec<u8> {
-        self.data.clone()
+    /// Whether the stored CRC matches the chunk's type and data.
+    pub fn verify_crc(&self) -> bool {
+        checksum(&self.typ, &self.data) == self.crc
+    }
+    /// Copies the borrowed data into an owned [`Chunk`].
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk {
+            typ: self.typ,
+            data: Data::Owned(self.data.to_vec()),
+            crc: self.crc,
+        }
+    }
+    /// Turns this into an owned [`Chunk`], copying the data only if it is
+    /// still borrowed.
+    pub fn into_chunk(self) -> Chunk {
+        Chunk {
+            typ: self.typ,
+            data: Data::Owned(self.data.into_owned()),
+            crc: self.crc,
+        }
+    }
+    /// Appends the chunk as it appears in a file to `bytes`.
+    pub(crate) fn write_bytes(&self, bytes: &mut Vec<u8>) {
+        write_bytes(bytes, &self.typ, &self.data, self.crc);
     }
 }
 
@@ -103,7 +431,7 @@ impl Chunk {
 mod tests {
     use super::*;
     use crate::chunk_type::ChunkType;
-    use std::str::FromStr;
+    use core::str::FromStr;
 
     fn testing_chunk() -> Chunk {
         let data_length: u32 = 42;
@@ -228,4 +556,130 @@ mod tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[test]
+    fn test_set_data() {
+        let mut chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"old".to_vec());
+        let old = chunk.set_data(b"This is where your secret message will be!".to_vec());
+
+        assert_eq!(old, b"old");
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.crc(), 2882656334);
+        assert_eq!(chunk.as_bytes(), testing_chunk().as_bytes());
+    }
+
+    #[test]
+    fn test_chunk_display_text() {
+        let chunk = testing_chunk();
+        assert_eq!(
+            format!("{}", chunk),
+            "This is where your secret message will be!"
+        );
+    }
+
+    #[test]
+    fn test_chunk_display_binary() {
+        let chunk_type = ChunkType::from_str("IDAT").unwrap();
+        let mut data = vec![0x78, 0x9c, 0x6b];
+        data.resize(2048, 0xff);
+        let chunk = Chunk::new(chunk_type, data);
+        assert_eq!(
+            format!("{}", chunk),
+            "<2048 bytes: 78 9c 6b ff ff ff ff ff ff ff ff ff ff ff ff ff ...>"
+        );
+
+        let chunk = Chunk::new(chunk_type, b"key\0value".to_vec());
+        assert_eq!(
+            format!("{}", chunk),
+            "<9 bytes: 6b 65 79 00 76 61 6c 75 65>"
+        );
+    }
+
+    #[test]
+    fn test_chunk_debug() {
+        let chunk = testing_chunk();
+        assert_eq!(
+            format!("{:?}", chunk),
+            "Chunk { chunk_type: RuSt, length: 42, crc: 0xabd1d84e }"
+        );
+    }
+
+    #[test]
+    fn test_chunk_eq() {
+        let chunk = testing_chunk();
+        assert_eq!(chunk.clone(), chunk);
+
+        let mut other = chunk.clone();
+        other.set_data(b"Something else".to_vec());
+        assert_ne!(other, chunk);
+
+        let renamed = Chunk::new(ChunkType::from_str("RUSt").unwrap(), chunk.data().to_vec());
+        assert_ne!(renamed, chunk);
+    }
+
+    #[test]
+    fn test_chunk_ref_from_bytes() {
+        let chunk_data = testing_chunk().as_bytes();
+        let chunk = ChunkRef::try_from(chunk_data.as_ref()).unwrap();
+
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.chunk_type().to_string(), String::from("RuSt"));
+        assert_eq!(chunk.data(), &chunk_data[8..50]);
+        assert_eq!(chunk.crc(), 2882656334);
+        assert_eq!(chunk.to_chunk().as_bytes(), chunk_data);
+    }
+
+    #[test]
+    fn test_chunk_parse_error_location() {
+        let mut chunk_data = testing_chunk().as_bytes();
+        chunk_data[20] ^= 0xff;
+        let error = Chunk::try_from(chunk_data.as_ref()).unwrap_err();
+        assert_eq!(error.offset(), 50);
+        assert_eq!(error.chunk_type().unwrap().to_string(), "RuSt");
+        assert!(matches!(error.kind(), ParseErrorKind::CrcMismatch { .. }));
+
+        chunk_data[5] = b'1';
+        let error = Chunk::try_from(chunk_data.as_ref()).unwrap_err();
+        assert_eq!(error.offset(), 4);
+        assert!(matches!(error.kind(), ParseErrorKind::InvalidChunkType(_)));
+
+        let chunk_data = testing_chunk().as_bytes();
+        let error = Chunk::try_from(&chunk_data[..30]).unwrap_err();
+        assert_eq!(error.kind(), &ParseErrorKind::Truncated);
+    }
+
+    #[test]
+    fn test_chunk_parse_length_limit() {
+        let chunk_data = testing_chunk().as_bytes();
+        let options = ParseOptions {
+            max_chunk_len: Some(41),
+            ..ParseOptions::default()
+        };
+        assert!(Chunk::parse(&chunk_data, &options).is_err());
+        assert!(Chunk::parse(&chunk_data, &ParseOptions::unlimited()).is_ok());
+
+        let mut huge = chunk_data.clone();
+        huge[..4].copy_from_slice(&0x7fff_fff0u32.to_be_bytes());
+        assert!(Chunk::parse(&huge, &ParseOptions::unlimited()).is_err());
+    }
+
+    #[test]
+    fn test_chunk_parse_without_crc_check() {
+        let mut chunk_data = testing_chunk().as_bytes();
+        let last = chunk_data.len() - 1;
+        chunk_data[last] ^= 0xff;
+
+        assert!(Chunk::parse(&chunk_data, &ParseOptions::default()).is_err());
+        let chunk = Chunk::parse(&chunk_data, &ParseOptions::lenient()).unwrap();
+        assert_eq!(chunk.as_bytes(), chunk_data);
+    }
+
+    #[test]
+    fn test_chunk_from_bytes_with_trailing_data() {
+        let mut chunk_data = testing_chunk().as_bytes();
+        chunk_data.push(0);
+
+        assert!(Chunk::try_from(chunk_data.as_ref()).is_err());
+        assert!(ChunkRef::try_from(chunk_data.as_ref()).is_err());
+    }
 }
@@ -1,27 +1,19 @@
-use std::fmt::{Debug, Display, Formatter};
-use std::str::FromStr;
+use core::fmt::{Debug, Display, Formatter};
+use core::str::FromStr;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ChunkType {
-    data: Vec<u8>,
+    data: [u8; 4],
 }
 
 fn is_valid_byte(b: u8) -> bool {
-    b >= 65 && b <= 90 || (b >= 97 && b <= 122)
+    b.is_ascii_alphabetic()
 }
 
-impl PartialEq for ChunkType {
-    fn eq(&self, other: &Self) -> bool {
-        self.data.eq(&other.data)
-    }
-}
-
-impl Eq for ChunkType {}
-
 impl Display for ChunkType {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let str = String::from_utf8(self.data.clone()).unwrap();
-        f.write_str(&str)
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let str = core::str::from_utf8(&self.data).unwrap();
+        f.write_str(str)
     }
 }
 
@@ -53,20 +45,119 @@ impl ChunkType {
             }
         }
 
-        let t = Self {
-            data: Vec::from(data),
-        };
+        let mut t = Self { data: [0u8; 4] };
+        t.data.copy_from_slice(data);
 
         Ok(t)
     }
 
+    /// Builds a type from `base`, a case-insensitive four-letter name, setting
+    /// the case of each letter from the given properties. The reserved bit is
+    /// always left valid.
+    pub fn with_properties(
+        base: &str,
+        critical: bool,
+        public: bool,
+        safe_to_copy: bool,
+    ) -> crate::Result<Self> {
+        let mut t = Self::from_slice(base.as_bytes())
+            .map_err(|_| "chunk type must be four ASCII letters")?;
+        t.data[2].make_ascii_uppercase();
+        Ok(t.with_critical(critical)
+            .with_public(public)
+            .with_safe_to_copy(safe_to_copy))
+    }
+
+    /// A private ancillary type, not safe to copy, named after `base`.
+    pub fn new_private_ancillary(base: &str) -> crate::Result<Self> {
+        Self::with_properties(base, false, false, false)
+    }
+
+    /// A private critical type, not safe to copy, named after `base`.
+    pub fn new_private_critical(base: &str) -> crate::Result<Self> {
+        Self::with_properties(base, true, false, false)
+    }
+
+    /// A random private ancillary type, not safe to copy, whose letters do not
+    /// spell a standard or registered extension type in any case.
+    #[cfg(feature = "std")]
+    pub fn random_private() -> Self {
+        use std::hash::{BuildHasher, RandomState};
+
+        loop {
+            // Every RandomState is freshly keyed, so hashing a constant with
+            // one gives a new random value without an RNG dependency.
+            if let Some(t) = Self::private_from_seed(RandomState::new().hash_one(0u8)) {
+                return t;
+            }
+        }
+    }
+
+    /// The type [`ChunkType::random_private`] builds from `seed`, or `None`
+    /// if it would clash with a standard or registered extension type.
+    #[cfg(feature = "std")]
+    fn private_from_seed(seed: u64) -> Option<Self> {
+        let mut t = Self { data: [0; 4] };
+        for (i, b) in t.data.iter_mut().enumerate() {
+            *b = b'a' + ((seed >> (i * 16)) as u16 % 26) as u8;
+        }
+        t.data[2].make_ascii_uppercase();
+        t.data[3].make_ascii_uppercase();
+        let clash = consts::STANDARD
+            .iter()
+            .chain(consts::EXTENSIONS.iter())
+            .any(|s| s.data.eq_ignore_ascii_case(&t.data));
+        (!clash).then_some(t)
+    }
+
+    pub fn with_critical(self, critical: bool) -> Self {
+        self.with_case(0, critical)
+    }
+
+    pub fn with_public(self, public: bool) -> Self {
+        self.with_case(1, public)
+    }
+
+    pub fn with_safe_to_copy(self, safe_to_copy: bool) -> Self {
+        self.with_case(3, !safe_to_copy)
+    }
+
+    fn with_case(mut self, index: usize, uppercase: bool) -> Self {
+        if uppercase {
+            self.data[index].make_ascii_uppercase();
+        } else {
+            self.data[index].make_ascii_lowercase();
+        }
+        self
+    }
+
     pub fn bytes(&self) -> [u8; 4] {
-        let mut data = [0u8; 4];
-        data.copy_from_slice(&self.data);
-        data
+        self.data
+    }
+
+    /// Whether this type is exactly `name`.
+    pub fn matches(&self, name: &str) -> bool {
+        self.data == *name.as_bytes()
     }
 
-    fn is_valid(&self) -> bool {
+    /// Whether this type is `name`, ignoring case.
+    pub fn matches_ignore_case(&self, name: &str) -> bool {
+        self.data.eq_ignore_ascii_case(name.as_bytes())
+    }
+
+    /// Whether this is one of the chunk types defined by the PNG specification
+    /// (including APNG), as listed in [`consts::STANDARD`].
+    pub fn is_standard(&self) -> bool {
+        consts::STANDARD.contains(self)
+    }
+
+    /// Whether this is one of the registered extension types listed in
+    /// [`consts::EXTENSIONS`].
+    pub fn is_registered_extension(&self) -> bool {
+        consts::EXTENSIONS.contains(self)
+    }
+
+    pub fn is_valid(&self) -> bool {
         self.data[2].is_ascii_uppercase()
     }
 
@@ -87,11 +178,65 @@ impl ChunkType {
     }
 }
 
+/// Well-known chunk types.
+pub mod consts {
+    use super::ChunkType;
+
+    const fn chunk_type(data: &[u8; 4]) -> ChunkType {
+        ChunkType { data: *data }
+    }
+
+    pub const IHDR: ChunkType = chunk_type(b"IHDR");
+    pub const PLTE: ChunkType = chunk_type(b"PLTE");
+    pub const IDAT: ChunkType = chunk_type(b"IDAT");
+    pub const IEND: ChunkType = chunk_type(b"IEND");
+    pub const TRNS: ChunkType = chunk_type(b"tRNS");
+    pub const CHRM: ChunkType = chunk_type(b"cHRM");
+    pub const GAMA: ChunkType = chunk_type(b"gAMA");
+    pub const ICCP: ChunkType = chunk_type(b"iCCP");
+    pub const SBIT: ChunkType = chunk_type(b"sBIT");
+    pub const SRGB: ChunkType = chunk_type(b"sRGB");
+    pub const CICP: ChunkType = chunk_type(b"cICP");
+    pub const MDCV: ChunkType = chunk_type(b"mDCV");
+    pub const CLLI: ChunkType = chunk_type(b"cLLI");
+    pub const TEXT: ChunkType = chunk_type(b"tEXt");
+    pub const ZTXT: ChunkType = chunk_type(b"zTXt");
+    pub const ITXT: ChunkType = chunk_type(b"iTXt");
+    pub const BKGD: ChunkType = chunk_type(b"bKGD");
+    pub const HIST: ChunkType = chunk_type(b"hIST");
+    pub const PHYS: ChunkType = chunk_type(b"pHYs");
+    pub const SPLT: ChunkType = chunk_type(b"sPLT");
+    pub const EXIF: ChunkType = chunk_type(b"eXIf");
+    pub const TIME: ChunkType = chunk_type(b"tIME");
+    pub const ACTL: ChunkType = chunk_type(b"acTL");
+    pub const FCTL: ChunkType = chunk_type(b"fcTL");
+    pub const FDAT: ChunkType = chunk_type(b"fdAT");
+
+    pub const OFFS: ChunkType = chunk_type(b"oFFs");
+    pub const PCAL: ChunkType = chunk_type(b"pCAL");
+    pub const SCAL: ChunkType = chunk_type(b"sCAL");
+    pub const STER: ChunkType = chunk_type(b"sTER");
+    pub const GIFG: ChunkType = chunk_type(b"gIFg");
+    pub const GIFT: ChunkType = chunk_type(b"gIFt");
+    pub const GIFX: ChunkType = chunk_type(b"gIFx");
+    pub const FRAC: ChunkType = chunk_type(b"fRAc");
+    pub const DSIG: ChunkType = chunk_type(b"dSIG");
+
+    /// Every chunk type defined by the PNG specification.
+    pub const STANDARD: [ChunkType; 25] = [
+        IHDR, PLTE, IDAT, IEND, TRNS, CHRM, GAMA, ICCP, SBIT, SRGB, CICP, MDCV, CLLI, TEXT, ZTXT,
+        ITXT, BKGD, HIST, PHYS, SPLT, EXIF, TIME, ACTL, FCTL, FDAT,
+    ];
+
+    /// Public chunk types registered as extensions to the specification.
+    pub const EXTENSIONS: [ChunkType; 9] = [OFFS, PCAL, SCAL, STER, GIFG, GIFT, GIFX, FRAC, DSIG];
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::convert::TryFrom;
-    use std::str::FromStr;
+    use core::convert::TryFrom;
+    use core::str::FromStr;
 
     #[test]
     pub fn test_chunk_type_from_bytes() {
@@ -171,6 +316,116 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_chunk_type_matches() {
+        let chunk = ChunkType::from_str("RuSt").unwrap();
+        assert!(chunk.matches("RuSt"));
+        assert!(!chunk.matches("rust"));
+        assert!(chunk.matches_ignore_case("rust"));
+        assert!(!chunk.matches_ignore_case("rusty"));
+    }
+
+    #[test]
+    pub fn test_chunk_type_as_map_key() {
+        use std::collections::{BTreeSet, HashMap};
+
+        let rust = ChunkType::from_str("RuSt").unwrap();
+        let copy = rust;
+        let mut counts = HashMap::new();
+        *counts.entry(rust).or_insert(0) += 1;
+        *counts.entry(copy).or_insert(0) += 1;
+        assert_eq!(counts[&rust], 2);
+
+        let sorted: BTreeSet<_> = ["tEXt", "IHDR", "IDAT"]
+            .iter()
+            .map(|s| ChunkType::from_str(s).unwrap().to_string())
+            .collect();
+        assert_eq!(
+            sorted.into_iter().collect::<Vec<_>>(),
+            ["IDAT", "IHDR", "tEXt"]
+        );
+    }
+
+    #[test]
+    pub fn test_chunk_type_consts() {
+        assert_eq!(consts::IHDR, ChunkType::from_str("IHDR").unwrap());
+        assert_eq!(consts::TEXT.to_string(), "tEXt");
+        for chunk_type in consts::STANDARD {
+            assert_eq!(ChunkType::try_from(chunk_type.bytes()), Ok(chunk_type));
+            assert!(chunk_type.is_valid());
+        }
+    }
+
+    #[test]
+    pub fn test_chunk_type_is_standard() {
+        assert!(consts::IDAT.is_standard());
+        assert!(consts::FDAT.is_standard());
+        assert!(!consts::OFFS.is_standard());
+        assert!(!ChunkType::from_str("RuSt").unwrap().is_standard());
+        assert!(!ChunkType::from_str("text").unwrap().is_standard());
+    }
+
+    #[test]
+    pub fn test_chunk_type_is_registered_extension() {
+        assert!(consts::OFFS.is_registered_extension());
+        assert!(consts::DSIG.is_registered_extension());
+        assert!(!consts::IDAT.is_registered_extension());
+        assert!(
+            !ChunkType::from_str("ruSt")
+                .unwrap()
+                .is_registered_extension()
+        );
+    }
+
+    #[test]
+    pub fn test_chunk_type_with_properties() {
+        let chunk = ChunkType::new_private_ancillary("rust").unwrap();
+        assert_eq!(chunk.to_string(), "ruST");
+        assert!(!chunk.is_critical());
+        assert!(!chunk.is_public());
+        assert!(chunk.is_reserved_bit_valid());
+        assert!(!chunk.is_safe_to_copy());
+
+        let chunk = chunk.with_safe_to_copy(true).with_critical(true);
+        assert_eq!(chunk.to_string(), "RuSt");
+
+        let chunk = ChunkType::new_private_critical("RUST").unwrap();
+        assert_eq!(chunk.to_string(), "RuST");
+
+        let chunk = ChunkType::with_properties("time", false, true, false).unwrap();
+        assert_eq!(chunk, consts::TIME);
+    }
+
+    #[test]
+    pub fn test_chunk_type_with_properties_invalid_base() {
+        assert!(ChunkType::new_private_ancillary("ru5t").is_err());
+        assert!(ChunkType::new_private_ancillary("rusty").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    pub fn test_random_private() {
+        for _ in 0..100 {
+            let t = ChunkType::random_private();
+            assert!(t.is_valid());
+            assert!(!t.is_critical());
+            assert!(!t.is_public());
+            assert!(!t.is_safe_to_copy());
+            assert!(!t.is_standard());
+            assert!(!t.is_registered_extension());
+        }
+        assert_ne!(ChunkType::random_private(), ChunkType::random_private());
+
+        let text: u64 = 19 | 4 << 16 | 23 << 32 | 19 << 48;
+        assert_eq!(ChunkType::private_from_seed(text), None);
+        assert_eq!(
+            ChunkType::private_from_seed(text + 1).unwrap().to_string(),
+            "ueXT"
+        );
+        let offs: u64 = 14 | 5 << 16 | 5 << 32 | 18 << 48;
+        assert_eq!(ChunkType::private_from_seed(offs), None);
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
@@ -0,0 +1,2026 @@
+use crate::args::{
+    self, CanonicalizeArgs, CapacityArgs, CapacityMode, ChunkSelection, CompletionsArgs,
+    ConvertArgs, DecodeArgs, DedupArgs, EncodeArgs, EqualArgs, ExplodeArgs, ExtCommand,
+    ExtensionArg, GenTypeArgs, HashArgs, ImplodeArgs, InfoArgs, KeyStoreArgs, KeygenArgs, MetaArgs,
+    PaletteArgs, PayloadEncoding, PrintArgs, RemoveArgs, ReorderArgs, ScanArgs, StatsArgs,
+    TrimArgs, ValidateArgs, VerifyArgs, WatchArgs, WriteArgs, XmpCommand,
+};
+use anstyle::{AnsiColor, Style};
+use base64::prelude::{BASE64_STANDARD, Engine};
+use indicatif::{ProgressBar, ProgressStyle};
+use notify::{EventKind, RecursiveMode, Watcher};
+use pngme::Result;
+use pngme::ancillary::{Histogram, ImageOffset, PhysicalScale, PixelCalibration, SuggestedPalette};
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::chunk_type::consts::{OFFS, PCAL, SCAL};
+use pngme::crypto::{self, KEY_LEN, PublicKey, SecretKey, SigningKey, VerifyingKey};
+use pngme::fingerprint::Scope;
+use pngme::handler::Registry;
+use pngme::lazy::LazyPng;
+use pngme::options::ParseOptions;
+use pngme::payload::{self, Compression};
+use pngme::png::{Placement, Png, PngRef, Removal};
+use pngme::progress::Progress;
+use pngme::report::Severity;
+use pngme::text::{self, TextChunk, TextFormat};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Cursor, IsTerminal, Read, Seek, SeekFrom, Write as _};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const NO_CHUNK_TYPE: &str = "no chunk type given and none set in the config file";
+
+/// The service keys are filed under in the OS keychain.
+const KEYCHAIN_SERVICE: &str = "pngme";
+
+/// Files smaller than this parse too quickly to be worth a progress bar.
+const PROGRESS_THRESHOLD: u64 = 64 << 20;
+
+/// A progress bar for an operation over `total` bytes, hidden for small
+/// inputs. indicatif also hides it when stderr is not a terminal.
+fn progress_bar(total: u64) -> ProgressBar {
+    if total < PROGRESS_THRESHOLD {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::new(total);
+    if let Ok(style) =
+        ProgressStyle::with_template("{bar:40} {bytes}/{total_bytes} ({eta} remaining)")
+    {
+        bar.set_style(style);
+    }
+    bar
+}
+
+/// Runs `f` with a progress bar sized for `bytes`, clearing it afterwards.
+fn with_progress<T>(bytes: &[u8], f: impl FnOnce(&mut dyn Progress) -> T) -> T {
+    let bar = progress_bar(bytes.len() as u64);
+    let result = f(&mut |processed, _| bar.set_position(processed));
+    bar.finish_and_clear();
+    result
+}
+
+/// Reads the file into a [`Png`] whose chunks share the file's buffer, so
+/// commands that edit a few chunks copy no others.
+pub(crate) fn read_png(path: &Path, options: &ParseOptions) -> Result<Png> {
+    let bar = progress_bar(fs::metadata(path).map_or(0, |m| m.len()));
+    let png = Png::from_file_with_progress(path, options, &mut |processed, _| {
+        bar.set_position(processed)
+    });
+    bar.finish_and_clear();
+    png
+}
+
+/// Where the edited copy of `input` goes: the `--output` path, a file of the
+/// same name inside it if it is a directory, or `input` itself.
+fn output_path(input: &Path, write: &WriteArgs) -> Result<PathBuf> {
+    let output = match &write.output {
+        Some(dir) if dir.is_dir() => dir.join(input.file_name().ok_or("input is not a file path")?),
+        Some(path) => path.clone(),
+        None => return Ok(input.to_path_buf()),
+    };
+    if !same_file(&output, input) {
+        check_overwrite(&output, write.force)?;
+    }
+    Ok(output)
+}
+
+fn check_overwrite(output: &Path, force: bool) -> Result<()> {
+    if !force && output.exists() {
+        return Err(format!(
+            "{} already exists; use --force to overwrite it",
+            output.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Writes the edited `png` out, or under `--dry-run` reports how the size of
+/// `input` would change instead.
+pub(crate) fn write_png(png: &Png, input: &Path, write: &WriteArgs) -> Result<()> {
+    let output = output_path(input, write)?;
+    let bytes = png.as_bytes();
+    if write.dry_run {
+        let before = fs::metadata(input)?.len() as i64;
+        let after = bytes.len() as i64;
+        println!(
+            "would write {} ({} bytes, {:+})",
+            output.display(),
+            after,
+            after - before
+        );
+        return Ok(());
+    }
+    write_atomic(&output, &bytes, write.backup.as_deref())?;
+    debug!(output = %output.display(), "wrote PNG");
+    Ok(())
+}
+
+/// Like [`write_png`], but streams the edited `png` straight from its source
+/// to the output.
+fn write_lazy<R: Read + Seek>(png: &mut LazyPng<R>, input: &Path, write: &WriteArgs) -> Result<()> {
+    let output = output_path(input, write)?;
+    if write.dry_run {
+        let before = fs::metadata(input)?.len() as i64;
+        let after = png.output_len() as i64;
+        println!(
+            "would write {} ({} bytes, {:+})",
+            output.display(),
+            after,
+            after - before
+        );
+        return Ok(());
+    }
+    write_atomic_with(&output, write.backup.as_deref(), |file| {
+        let mut out = BufWriter::new(file);
+        png.write_to(&mut out)?;
+        Ok(out.flush()?)
+    })?;
+    debug!(output = %output.display(), "streamed PNG");
+    Ok(())
+}
+
+/// Replaces `path` with `bytes` so that a crash never leaves it half written:
+/// the data goes to a temporary file beside it, is synced, and is renamed
+/// over the original. With a `backup` suffix the original is copied aside
+/// first.
+fn write_atomic(path: &Path, bytes: &[u8], backup: Option<&OsStr>) -> Result<()> {
+    write_atomic_with(path, backup, |file| Ok(file.write_all(bytes)?))
+}
+
+/// Like [`write_atomic`], with `fill` writing the new contents.
+fn write_atomic_with(
+    path: &Path,
+    backup: Option<&OsStr>,
+    fill: impl FnOnce(&mut File) -> Result<()>,
+) -> Result<()> {
+    let name = path.file_name().ok_or("output is not a file path")?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut temp_name = OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp = dir.join(temp_name);
+
+    let result = (|| -> Result<()> {
+        let mut file = File::create_new(&temp)?;
+        fill(&mut file)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        if let Some(suffix) = backup
+            && path.exists()
+        {
+            let mut backup_name = name.to_os_string();
+            backup_name.push(suffix);
+            fs::copy(path, dir.join(backup_name))?;
+        }
+        fs::rename(&temp, path)?;
+        // The rename is only durable once the directory itself is synced.
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Runs `f` over the contents of the file, memory-mapping it when the `mmap`
+/// feature is enabled so large files are never read into RAM.
+#[cfg(feature = "mmap")]
+fn with_bytes<T>(path: &Path, f: impl FnOnce(&[u8]) -> Result<T>) -> Result<T> {
+    let file = File::open(path)?;
+    // SAFETY: the mapping is read-only and only lives for this call.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    f(&map)
+}
+
+#[cfg(not(feature = "mmap"))]
+fn with_bytes<T>(path: &Path, f: impl FnOnce(&[u8]) -> Result<T>) -> Result<T> {
+    f(&fs::read(path)?)
+}
+
+/// Parses the `stream`th of the PNGs stored back to back in `bytes`. The
+/// first is parsed on its own, so anything after its IEND stays trailing
+/// data as usual.
+fn parse_stream<'a>(bytes: &'a [u8], options: &ParseOptions, stream: usize) -> Result<PngRef<'a>> {
+    if stream == 0 {
+        return Ok(with_progress(bytes, |p| {
+            PngRef::parse_with_progress(bytes, options, p)
+        })?);
+    }
+    let mut streams = PngRef::parse_all(bytes, options)?;
+    if stream >= streams.len() {
+        return Err(format!("no stream {}; the file holds {}", stream, streams.len()).into());
+    }
+    Ok(streams.swap_remove(stream))
+}
+
+/// Where the `stream`th of the PNGs stored back to back in `bytes` begins,
+/// for commands that work on raw bytes. They see that stream and whatever
+/// follows it, as they would see the first stream of the file.
+fn stream_offset(bytes: &[u8], options: &ParseOptions, stream: usize) -> Result<usize> {
+    if stream == 0 {
+        return Ok(0);
+    }
+    let streams = PngRef::parse_all(bytes, options)?;
+    if stream >= streams.len() {
+        return Err(format!("no stream {}; the file holds {}", stream, streams.len()).into());
+    }
+    let len = |png: &PngRef| -> usize {
+        let chunks: usize = png.chunks().iter().map(|c| 12 + c.length() as usize).sum();
+        Png::STANDARD_HEADER.len() + chunks
+    };
+    Ok(streams[..stream].iter().map(len).sum())
+}
+
+/// Runs `f` over the bytes of the `stream`th PNG in the file and those
+/// after it.
+fn with_stream_bytes<T>(
+    path: &Path,
+    options: &ParseOptions,
+    stream: usize,
+    f: impl FnOnce(&[u8]) -> Result<T>,
+) -> Result<T> {
+    with_bytes(path, |bytes| {
+        f(&bytes[stream_offset(bytes, options, stream)?..])
+    })
+}
+
+/// Runs `f` over a borrowed view of the `stream`th PNG in the file.
+fn with_png_ref<T>(
+    path: &Path,
+    options: &ParseOptions,
+    stream: usize,
+    f: impl FnOnce(&PngRef) -> Result<T>,
+) -> Result<T> {
+    with_bytes(path, |bytes| f(&parse_stream(bytes, options, stream)?))
+}
+
+/// Renders the bytes of `path` around `offset` as a hex dump with a caret
+/// under the byte at `offset`.
+pub fn snippet(path: &Path, offset: u64) -> Result<String> {
+    const WIDTH: u64 = 16;
+
+    let row = offset - offset % WIDTH;
+    let start = row.saturating_sub(WIDTH);
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    file.take(row + WIDTH - start).read_to_end(&mut bytes)?;
+
+    let mut out = String::new();
+    for (i, line) in bytes.chunks(WIDTH as usize).enumerate() {
+        let hex: Vec<_> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        writeln!(out, "  {:08x}  {}", start + i as u64 * WIDTH, hex.join(" "))?;
+    }
+    let column = (offset - row) as usize;
+    writeln!(out, "  {:8}  {}^^", "", " ".repeat(column * 3))?;
+    Ok(out)
+}
+
+/// Reads a key file written by keygen: the key in base64 on one line.
+fn read_key(path: &Path) -> Result<[u8; KEY_LEN]> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let bytes = BASE64_STANDARD
+        .decode(text.trim())
+        .map_err(|e| format!("{}: invalid key: {}", path.display(), e))?;
+    bytes
+        .try_into()
+        .map_err(|_| format!("{}: a key must be {} bytes", path.display(), KEY_LEN).into())
+}
+
+/// Reads an HMAC key: the bytes of the file at `path`, whatever they are.
+fn read_hmac_key(path: &Path) -> Result<Vec<u8>> {
+    let key = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if key.is_empty() {
+        return Err(format!("{}: the HMAC key is empty", path.display()).into());
+    }
+    Ok(key)
+}
+
+fn keychain_entry(name: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(KEYCHAIN_SERVICE, name)
+        .map_err(|e| format!("cannot open the OS keychain: {}", e).into())
+}
+
+/// Reads the secret key stored under `name` by `key store`.
+fn keychain_key(name: &str) -> Result<[u8; KEY_LEN]> {
+    let secret = keychain_entry(name)?
+        .get_secret()
+        .map_err(|e| format!("keychain entry {}: {}", name, e))?;
+    secret
+        .try_into()
+        .map_err(|_| format!("keychain entry {} is not a {}-byte key", name, KEY_LEN).into())
+}
+
+/// Writes `key` in the format [`read_key`] reads, readable only by the owner
+/// when `secret`.
+fn write_key(path: &Path, key: &[u8; KEY_LEN], secret: bool, force: bool) -> Result<()> {
+    check_overwrite(path, force)?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if secret {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // The mode above only applies to a new file; one being overwritten keeps
+    // its permissions unless they are set too, before the key is written.
+    #[cfg(unix)]
+    if secret {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    writeln!(file, "{}", BASE64_STANDARD.encode(key))?;
+    Ok(())
+}
+
+/// Parses hex digits, ignoring whitespace, into bytes.
+pub(crate) fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    let digits: Vec<u8> = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()
+        .ok_or("not a hex digit")?;
+    if !digits.len().is_multiple_of(2) {
+        return Err("odd number of hex digits".into());
+    }
+    Ok(digits.chunks(2).map(|p| p[0] << 4 | p[1]).collect())
+}
+
+/// The bytes a payload given on the command line stands for.
+fn decode_payload(message: String, encoding: PayloadEncoding) -> Result<Vec<u8>> {
+    match encoding {
+        PayloadEncoding::Raw => Ok(message.into_bytes()),
+        PayloadEncoding::Base64 => Ok(BASE64_STANDARD
+            .decode(message.trim())
+            .map_err(|e| format!("invalid base64: {}", e))?),
+        PayloadEncoding::Hex => {
+            parse_hex(&message).map_err(|e| format!("invalid hex: {}", e).into())
+        }
+    }
+}
+
+/// `data` written out as text in `encoding`, which must not be raw.
+fn encode_payload(data: &[u8], encoding: PayloadEncoding) -> String {
+    match encoding {
+        PayloadEncoding::Raw => unreachable!("raw payloads are printed as they are"),
+        PayloadEncoding::Base64 => BASE64_STANDARD.encode(data),
+        PayloadEncoding::Hex => data.iter().map(|b| format!("{:02x}", b)).collect(),
+    }
+}
+
+#[tracing::instrument(skip_all, fields(file = %args.file.display()))]
+pub fn encode(args: EncodeArgs, options: &ParseOptions) -> Result<()> {
+    let mut png = LazyPng::with_options(BufReader::new(File::open(&args.file)?), options)?;
+    let chunk_type = ChunkType::from_str(&args.chunk_type).map_err(|_| "invalid chunk type")?;
+    let message = decode_payload(args.message, args.input_encoding)?;
+    let mut recipients = Vec::new();
+    for path in args.recipients.iter() {
+        recipients.push(PublicKey::from_bytes(read_key(path)?));
+    }
+    if let Some(name) = &args.key_from_keychain {
+        recipients.push(SecretKey::from_bytes(keychain_key(name)?).public_key());
+    }
+    let sign_key = args
+        .sign_key
+        .as_deref()
+        .map(|path| read_key(path).map(SigningKey::from_bytes))
+        .transpose()?;
+    let hmac_key = args.hmac_key.as_deref().map(read_hmac_key).transpose()?;
+    let build = |compression: Option<Compression>| -> Result<Chunk> {
+        let mut data = message.clone();
+        // Compress first: sealed data looks random and would not shrink.
+        if let Some(compression) = compression {
+            data = payload::compress(&data, compression)?;
+        }
+        if !recipients.is_empty() {
+            data = crypto::seal(&recipients, &data)?;
+        }
+        // Outermost, so verify can check them without the decryption key.
+        if let Some(key) = &hmac_key {
+            data = crypto::authenticate(key, &data)?;
+        }
+        if let Some(key) = &sign_key {
+            data = crypto::sign(key, &data);
+        }
+        match &args.as_text {
+            Some(keyword) => text_payload(chunk_type, keyword, data, args.text_encoding),
+            None => Ok(Chunk::new(chunk_type, data)),
+        }
+    };
+    let chunk = match args.max_size {
+        None => build(args.compress)?,
+        Some(max_size) => {
+            let limit = max_size.limit(fs::metadata(&args.file)?.len());
+            let size = |chunk: &Chunk| png.output_len() + 12 + chunk.length() as u64;
+            build_within(build, args.compress, size, limit)?
+        }
+    };
+    let length = chunk.length();
+    png.insert_chunk(chunk, args.placement.into())?;
+    debug!(%chunk_type, "inserted chunk");
+
+    write_lazy(&mut png, &args.file, &args.write)?;
+    if args.write.dry_run {
+        println!("would add {} chunk ({} bytes)", chunk_type, length);
+    }
+    Ok(())
+}
+
+/// Builds the payload chunk with `compression` and, if the output would then
+/// be over `limit` bytes, once more at the method's strongest level. Fails if
+/// that is still over.
+fn build_within(
+    build: impl Fn(Option<Compression>) -> Result<Chunk>,
+    compression: Option<Compression>,
+    size: impl Fn(&Chunk) -> u64,
+    limit: u64,
+) -> Result<Chunk> {
+    let mut chunk = build(compression)?;
+    if size(&chunk) > limit
+        && let Some(compression) = compression
+    {
+        debug!(
+            size = size(&chunk),
+            limit, "over budget, escalating compression"
+        );
+        chunk = build(Some(compression.strongest()))?;
+    }
+    check_max_size(size(&chunk), limit)?;
+    Ok(chunk)
+}
+
+/// Fails if an output of `size` bytes is over `limit`.
+fn check_max_size(size: u64, limit: u64) -> Result<()> {
+    if size > limit {
+        return Err(format!(
+            "output would be {} bytes, over the limit of {}",
+            size, limit
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Wraps `data` in a standard text chunk of `chunk_type` under `keyword`,
+/// written as text in `encoding`.
+fn text_payload(
+    chunk_type: ChunkType,
+    keyword: &str,
+    data: Vec<u8>,
+    encoding: PayloadEncoding,
+) -> Result<Chunk> {
+    let format =
+        TextFormat::of(&chunk_type).ok_or("--as-text needs a tEXt, zTXt or iTXt chunk type")?;
+    let text = match encoding {
+        PayloadEncoding::Raw => String::from_utf8(data)
+            .map_err(|_| "payload is not text; use --text-encoding base64 or hex")?,
+        encoding => encode_payload(&data, encoding),
+    };
+    TextChunk::new(format, keyword, &text).encode()
+}
+
+/// Whether `data` is UTF-8 without control characters other than
+/// whitespace, and so safe to print to a terminal.
+fn looks_like_text(data: &[u8]) -> bool {
+    std::str::from_utf8(data).is_ok_and(|text| {
+        text.chars()
+            .all(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+    })
+}
+
+/// Names the format of `data` from the magic bytes at its start.
+fn sniff(data: &[u8]) -> Option<&'static str> {
+    const MAGIC: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "PNG image"),
+        (b"\xff\xd8\xff", "JPEG image"),
+        (b"GIF87a", "GIF image"),
+        (b"GIF89a", "GIF image"),
+        (b"%PDF-", "PDF document"),
+        (b"PK\x03\x04", "ZIP archive"),
+        (b"PK\x05\x06", "ZIP archive"),
+        (b"\x1f\x8b", "gzip data"),
+        (b"BZh", "bzip2 data"),
+        (b"\xfd7zXZ\x00", "xz data"),
+        (b"7z\xbc\xaf\x27\x1c", "7-Zip archive"),
+        (b"\x28\xb5\x2f\xfd", "zstd data"),
+        (b"\x7fELF", "ELF executable"),
+        (b"MZ", "Windows executable"),
+        (b"OggS", "Ogg media"),
+        (b"SQLite format 3\x00", "SQLite database"),
+    ];
+    if let Some((_, name)) = MAGIC.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return Some(name);
+    }
+    // Formats whose magic is not at the very start.
+    let at = |range: core::ops::Range<usize>, magic: &[u8]| data.get(range) == Some(magic);
+    if at(0..4, b"RIFF") && at(8..12, b"WEBP") {
+        Some("WebP image")
+    } else if at(0..4, b"RIFF") && at(8..12, b"WAVE") {
+        Some("WAV audio")
+    } else if at(4..8, b"ftyp") {
+        Some("MP4 media")
+    } else if at(257..262, b"ustar") {
+        Some("tar archive")
+    } else {
+        None
+    }
+}
+
+#[tracing::instrument(skip_all, fields(file = %args.file.display()))]
+pub fn decode(args: DecodeArgs, options: &ParseOptions) -> Result<()> {
+    with_png_ref(&args.file, options, args.stream.stream_index, |png| {
+        // The payload may have been placed anywhere, including after IEND.
+        let trailing = png.trailing_chunks();
+        let mut chunks = png.chunks().iter().chain(trailing.iter());
+        let (text, opened);
+        let mut data = match &args.as_text {
+            Some(keyword) => {
+                let found = chunks
+                    .filter(|c| TextFormat::of(c.chunk_type()).is_some())
+                    .filter_map(|c| TextChunk::decode(c.chunk_type(), c.data()).ok())
+                    .find(|t| t.keyword == *keyword)
+                    .ok_or("text chunk not found")?;
+                debug!(format = %found.format, length = found.text.len(), "found text");
+                text = decode_payload(found.text, args.text_encoding)?;
+                &text[..]
+            }
+            None => {
+                let chunk_type = args.chunk_type.as_deref().ok_or(NO_CHUNK_TYPE)?;
+                let chunk = chunks
+                    .find(|c| c.chunk_type().matches(chunk_type))
+                    .ok_or("chunk not found")?;
+                debug!(chunk_type = %chunk.chunk_type(), length = chunk.length(), "found chunk");
+                chunk.data()
+            }
+        };
+        // Signatures and HMACs are checked by verify; decode only needs
+        // what they wrap.
+        data = crypto::unwrap_unchecked(data);
+        let identity = match (&args.identity, &args.key_from_keychain) {
+            (Some(path), _) => Some(read_key(path)?),
+            (None, Some(name)) => Some(keychain_key(name)?),
+            (None, None) => None,
+        };
+        if let Some(identity) = identity {
+            let identity = SecretKey::from_bytes(identity);
+            opened = crypto::open(&identity, data)?;
+            data = &opened;
+        }
+        let decompressed;
+        if !args.no_decompress && payload::is_compressed(data) {
+            decompressed = payload::decompress(data)?;
+            data = &decompressed;
+        }
+        match args.output_encoding {
+            PayloadEncoding::Raw if args.raw => std::io::stdout().write_all(data)?,
+            PayloadEncoding::Raw if looks_like_text(data) => {
+                println!("{}", String::from_utf8_lossy(data))
+            }
+            // Piped or redirected, binary is what the reader asked for; only
+            // a terminal needs protecting from it.
+            PayloadEncoding::Raw if !std::io::stdout().is_terminal() => {
+                std::io::stdout().write_all(data)?
+            }
+            PayloadEncoding::Raw => {
+                let digest = Sha256::digest(data);
+                println!(
+                    "binary payload: {}, {} bytes, sha256 {}",
+                    sniff(data).unwrap_or("unknown type"),
+                    data.len(),
+                    encode_payload(&digest, PayloadEncoding::Hex)
+                );
+                println!("use --raw or --output-encoding to print it");
+            }
+            encoding => println!("{}", encode_payload(data, encoding)),
+        }
+        Ok(())
+    })
+}
+
+pub fn remove(args: RemoveArgs, options: &ParseOptions) -> Result<()> {
+    let chunk_type = args.chunk_type.ok_or(NO_CHUNK_TYPE)?;
+    let mut png = LazyPng::with_options(BufReader::new(File::open(&args.file)?), options)?;
+    let which = match (args.all, args.index) {
+        (true, _) => Removal::All,
+        (false, Some(n)) => Removal::Nth(n),
+        (false, None) => Removal::First,
+    };
+    let removed = png.remove_chunk(&chunk_type, which)?;
+    write_lazy(&mut png, &args.file, &args.write)?;
+    let verb = if args.write.dry_run {
+        "would remove"
+    } else {
+        "removed"
+    };
+    println!("{} {} {} chunk(s)", verb, removed, chunk_type);
+    Ok(())
+}
+
+/// Style for a row of the `print` table: critical chunks stand out, unknown
+/// private chunks are flagged, and standard ancillary chunks stay quiet.
+fn row_style(chunk_type: &ChunkType) -> Style {
+    if chunk_type.is_critical() {
+        Style::new().bold()
+    } else if !chunk_type.is_public() && !chunk_type.is_standard() {
+        AnsiColor::Yellow.on_default()
+    } else {
+        AnsiColor::Green.on_default()
+    }
+}
+
+fn flags(chunk_type: &ChunkType) -> String {
+    let mut flags = vec![if chunk_type.is_critical() {
+        "critical"
+    } else {
+        "ancillary"
+    }];
+    if !chunk_type.is_public() {
+        flags.push("private");
+    }
+    if chunk_type.is_safe_to_copy() {
+        flags.push("safe-to-copy");
+    }
+    flags.join(",")
+}
+
+/// The chunk handlers `print`, `validate` and `verify` use. Register
+/// handlers for proprietary chunks here to have them decoded and checked.
+fn handlers() -> Registry {
+    Registry::standard()
+}
+
+/// The data of an ancillary chunk as `print` shows it: decoded for the
+/// chunk types `registry` handles, as text or a hex preview otherwise.
+fn describe(registry: &Registry, chunk: &Chunk) -> String {
+    match registry.describe(chunk.chunk_type(), chunk.data()) {
+        Some(decoded) => decoded.unwrap_or_else(|e| format!("<invalid: {}>", e)),
+        None => chunk.to_string(),
+    }
+}
+
+/// Lists chunk headers without reading critical chunk data, so even huge
+/// files print immediately. Only ancillary chunk bodies are loaded.
+pub fn print(args: PrintArgs, options: &ParseOptions) -> Result<()> {
+    match args.stream.stream_index {
+        0 => print_chunks(LazyPng::with_options(
+            BufReader::new(File::open(&args.file)?),
+            options,
+        )?),
+        stream => with_stream_bytes(&args.file, options, stream, |bytes| {
+            print_chunks(LazyPng::with_options(Cursor::new(bytes), options)?)
+        }),
+    }
+}
+
+fn print_chunks<R: Read + Seek>(mut png: LazyPng<R>) -> Result<()> {
+    let registry = handlers();
+    let mut rows = vec![[
+        "#".to_string(),
+        "TYPE".to_string(),
+        "OFFSET".to_string(),
+        "LENGTH".to_string(),
+        "CRC".to_string(),
+        "FLAGS".to_string(),
+        "DATA".to_string(),
+    ]];
+    for i in 0..png.headers().len() {
+        let header = &png.headers()[i];
+        let mut row = [
+            i.to_string(),
+            header.chunk_type().to_string(),
+            header.offset().to_string(),
+            header.length().to_string(),
+            format!("{:08x}", header.crc()),
+            flags(header.chunk_type()),
+            String::new(),
+        ];
+        // Critical chunk data is image data, too large and opaque to show.
+        if !header.chunk_type().is_critical() {
+            row[6] = describe(&registry, &png.load(i)?);
+        }
+        rows.push(row);
+    }
+    print_table(&rows, |i| row_style(png.headers()[i].chunk_type()))
+}
+
+pub fn info(args: InfoArgs, options: &ParseOptions) -> Result<()> {
+    let info = with_png_ref(&args.file, options, args.stream.stream_index, |png| {
+        png.info()
+    })?;
+    let header = &info.header;
+    let yes_no = |b: bool| if b { "yes" } else { "no" }.to_string();
+    let rows = [
+        (
+            "dimensions",
+            format!("{} x {}", header.width, header.height),
+        ),
+        ("bit depth", header.bit_depth.to_string()),
+        (
+            "color type",
+            format!("{} ({})", header.color_type_name(), header.color_type),
+        ),
+        (
+            "interlace",
+            if header.interlaced { "Adam7" } else { "none" }.to_string(),
+        ),
+        (
+            "palette",
+            info.palette_len
+                .map_or("none".to_string(), |n| format!("{} entries", n)),
+        ),
+        (
+            "IDAT chunks",
+            format!("{} ({} bytes)", info.idat_chunks, info.idat_bytes),
+        ),
+        ("transparency", yes_no(info.transparency)),
+        ("ICC profile", yes_no(info.icc_profile)),
+        ("text chunks", info.text_chunks.to_string()),
+        (
+            "animation",
+            match info.animation {
+                None => "no".to_string(),
+                Some(a) if a.plays == 0 => format!("{} frames, looping forever", a.frames),
+                Some(a) => format!("{} frames, {} plays", a.frames, a.plays),
+            },
+        ),
+        ("ancillary", format!("{} bytes", info.ancillary_bytes)),
+    ];
+    let extensions = [
+        ("offset", info.offset.map(|o| o.to_string())),
+        ("pixel size", info.scale.map(|s| s.to_string())),
+        ("calibration", info.calibration.map(|c| c.to_string())),
+    ];
+    let rows: Vec<_> = rows
+        .into_iter()
+        .chain(
+            extensions
+                .into_iter()
+                .filter_map(|(name, v)| Some((name, v?))),
+        )
+        .collect();
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, value) in rows.iter() {
+        println!("{:<width$}  {}", name, value);
+    }
+    Ok(())
+}
+
+/// Lists the PLTE entries with their tRNS alpha and hIST frequency, where
+/// the image has those chunks.
+pub fn palette(args: PaletteArgs, options: &ParseOptions) -> Result<()> {
+    with_png_ref(&args.file, options, args.stream.stream_index, |png| {
+        let plte = png.chunk_by_type("PLTE").ok_or("image has no palette")?;
+        let alpha = png.chunk_by_type("tRNS").map(|c| c.data());
+        let histogram = match png.chunk_by_type("hIST") {
+            Some(chunk) => {
+                let histogram = Histogram::decode(chunk.data())?;
+                histogram.check(plte.data().len() / 3)?;
+                Some(histogram)
+            }
+            None => None,
+        };
+
+        // The frequency column stays empty, and so is trimmed away, without
+        // a histogram.
+        let mut rows = vec![[
+            "#".to_string(),
+            "COLOR".to_string(),
+            "ALPHA".to_string(),
+            match histogram {
+                Some(_) => "FREQUENCY".to_string(),
+                None => String::new(),
+            },
+        ]];
+        for (i, rgb) in plte.data().chunks_exact(3).enumerate() {
+            // Entries past the end of tRNS are fully opaque.
+            let alpha = alpha.map_or(255, |a| a.get(i).copied().unwrap_or(255));
+            rows.push([
+                i.to_string(),
+                format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2]),
+                alpha.to_string(),
+                histogram
+                    .as_ref()
+                    .map_or(String::new(), |h| h.frequencies[i].to_string()),
+            ]);
+        }
+        print_table(&rows, |_| Style::new())
+    })
+}
+
+/// Prints `rows` as a table under an underlined header row, each column as
+/// wide as its widest cell. Every other row is printed in `style`, given the
+/// row's index below the header.
+fn print_table<const N: usize>(rows: &[[String; N]], style: impl Fn(usize) -> Style) -> Result<()> {
+    let mut widths = [0; N];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for (i, row) in rows.iter().enumerate() {
+        let mut line = String::new();
+        for (cell, width) in row.iter().zip(widths) {
+            write!(line, "{:<width$}  ", cell)?;
+        }
+        let style = match i {
+            0 => Style::new().underline(),
+            _ => style(i - 1),
+        };
+        anstream::println!("{style}{}{style:#}", line.trim_end());
+    }
+    Ok(())
+}
+
+pub fn stats(args: StatsArgs, options: &ParseOptions) -> Result<()> {
+    let stats = with_png_ref(&args.file, options, args.stream.stream_index, |png| {
+        Ok(png.stats())
+    })?;
+    let share = |bytes: u64| format!("{} bytes ({:.1}%)", bytes, stats.percent(bytes));
+    println!("file       {} bytes", stats.file_size);
+    println!("IDAT       {}", share(stats.idat_bytes));
+    println!("ancillary  {}", share(stats.ancillary_bytes));
+    if stats.trailing_bytes > 0 {
+        println!("trailing   {}", share(stats.trailing_bytes));
+    }
+
+    println!();
+    let mut rows = vec![[
+        "TYPE".to_string(),
+        "COUNT".to_string(),
+        "TOTAL".to_string(),
+        "AVERAGE".to_string(),
+        "SHARE".to_string(),
+    ]];
+    for t in stats.types.iter() {
+        rows.push([
+            t.chunk_type.to_string(),
+            t.count.to_string(),
+            t.bytes.to_string(),
+            format!("{:.1}", t.average()),
+            format!("{:.1}%", stats.percent(t.bytes)),
+        ]);
+    }
+    print_table(&rows, |_| Style::new())?;
+
+    if args.top == 0 {
+        return Ok(());
+    }
+    println!();
+    let mut rows = vec![[
+        "#".to_string(),
+        "TYPE".to_string(),
+        "LENGTH".to_string(),
+        "ENTROPY".to_string(),
+    ]];
+    for c in stats.largest(args.top) {
+        rows.push([
+            c.index.to_string(),
+            c.chunk_type.to_string(),
+            c.length.to_string(),
+            format!("{:.2}", c.entropy),
+        ]);
+    }
+    print_table(&rows, |_| Style::new())
+}
+
+pub fn capacity(args: CapacityArgs, options: &ParseOptions) -> Result<()> {
+    let CapacityMode::Lsb = args.mode;
+    let carrier = with_png_ref(&args.file, options, args.stream.stream_index, |png| {
+        png.to_png().lsb_carrier()
+    })?;
+    let header = carrier.header();
+    let channels = match header.color_type {
+        0 => "grey",
+        2 => "red, green, blue",
+        4 => "grey, alpha",
+        _ => "red, green, blue, alpha",
+    };
+    println!(
+        "channels  {} ({} bits each, up to {} usable)",
+        channels,
+        header.bit_depth,
+        carrier.max_bits()
+    );
+    println!("samples   {}", carrier.samples());
+    if carrier.max_bits() == 0 {
+        return Err("samples are too shallow to hide bits in".into());
+    }
+
+    // The PSNR column stays empty, and so is trimmed away, without a
+    // payload size.
+    let mut rows = vec![[
+        "BITS".to_string(),
+        "CAPACITY".to_string(),
+        args.payload_size
+            .map_or(String::new(), |size| format!("PSNR ({} bytes)", size)),
+    ]];
+    for bits in [1, 2, 4] {
+        if let Some(capacity) = carrier.capacity(bits) {
+            let psnr = match args.payload_size.map(|size| carrier.psnr(bits, size)) {
+                None => String::new(),
+                Some(None) => "does not fit".to_string(),
+                Some(Some(psnr)) if psnr.is_infinite() => "lossless".to_string(),
+                Some(Some(psnr)) => format!("{:.1} dB", psnr),
+            };
+            rows.push([bits.to_string(), format!("{} bytes", capacity), psnr]);
+        }
+    }
+    println!();
+    print_table(&rows, |_| Style::new())
+}
+
+pub fn validate(args: ValidateArgs) -> Result<()> {
+    let lenient = ParseOptions::lenient();
+    let report = with_stream_bytes(&args.file, &lenient, args.stream.stream_index, |bytes| {
+        Ok(with_progress(bytes, |p| {
+            Png::check_all_with_registry(bytes, &handlers(), p)
+        }))
+    })?;
+    println!("{}", report);
+    if report.has_errors() {
+        return Err(format!("{} is not a valid PNG file", args.file.display()).into());
+    }
+    Ok(())
+}
+
+/// One chunk, as `scan --json` prints it.
+#[derive(Serialize)]
+struct ScanEntry {
+    offset: u64,
+    #[serde(rename = "type")]
+    chunk_type: String,
+    length: u32,
+    class: String,
+    /// Bits per byte, rounded to two decimals.
+    entropy: f64,
+    suspicions: Vec<String>,
+}
+
+/// The whole of `scan --json`.
+#[derive(Serialize)]
+struct ScanOutput {
+    chunks: Vec<ScanEntry>,
+    /// Offset and length of any bytes after IEND.
+    trailing_data: Option<(u64, u64)>,
+    error: Option<String>,
+}
+
+pub fn scan(args: ScanArgs) -> Result<()> {
+    let lenient = ParseOptions::lenient();
+    let scan = with_stream_bytes(&args.file, &lenient, args.stream.stream_index, |bytes| {
+        Ok(Png::scan(bytes))
+    })?;
+    if args.json {
+        let output = ScanOutput {
+            chunks: scan
+                .chunks
+                .iter()
+                .map(|c| ScanEntry {
+                    offset: c.offset,
+                    chunk_type: c.chunk_type.to_string(),
+                    length: c.length,
+                    class: c.class.to_string(),
+                    entropy: (c.entropy * 100.0).round() / 100.0,
+                    suspicions: c.suspicions.iter().map(|s| s.to_string()).collect(),
+                })
+                .collect(),
+            trailing_data: scan.trailing_data,
+            error: scan.error.as_ref().map(|e| e.to_string()),
+        };
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return match scan.error {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        };
+    }
+    let mut rows = vec![[
+        "#".to_string(),
+        "TYPE".to_string(),
+        "OFFSET".to_string(),
+        "LENGTH".to_string(),
+        "CLASS".to_string(),
+        "NOTES".to_string(),
+    ]];
+    for (i, chunk) in scan.chunks.iter().enumerate() {
+        let notes: Vec<_> = chunk.suspicions.iter().map(|s| s.to_string()).collect();
+        rows.push([
+            i.to_string(),
+            chunk.chunk_type.to_string(),
+            chunk.offset.to_string(),
+            chunk.length.to_string(),
+            chunk.class.to_string(),
+            notes.join(", "),
+        ]);
+    }
+    print_table(&rows, |i| match &scan.chunks[i] {
+        chunk if !chunk.suspicions.is_empty() => AnsiColor::Red.on_default(),
+        chunk => row_style(&chunk.chunk_type),
+    })?;
+
+    if let Some((offset, length)) = scan.trailing_data {
+        let style = AnsiColor::Red.on_default();
+        anstream::println!("{style}{length} bytes after IEND at offset {offset}{style:#}");
+    }
+    match scan.error {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+pub fn meta(args: MetaArgs, options: &ParseOptions) -> Result<()> {
+    let mut png = match args.set {
+        Some(_) => read_png(&args.file, options)?,
+        None => with_png_ref(&args.file, options, args.stream.stream_index, |png| {
+            Ok(png.to_png())
+        })?,
+    };
+    if let Some([keyword, value]) = args.set.as_deref() {
+        text::check_keyword(keyword)?;
+        if !text::is_registered_keyword(keyword) {
+            warn!("{} is not a registered keyword", keyword);
+        }
+        png.set_text(keyword, value)?;
+        write_png(&png, &args.file, &args.write)?;
+        if args.write.dry_run {
+            println!("would set {}", keyword);
+        }
+        return Ok(());
+    }
+
+    let mut texts = Vec::new();
+    for text in png.text_chunks() {
+        match text {
+            Ok(text) => texts.push(text),
+            Err(e) => warn!("skipping unreadable text chunk: {}", e),
+        }
+    }
+    if let Some(keyword) = &args.get {
+        let mut found = false;
+        for text in texts.iter().filter(|t| t.keyword == *keyword) {
+            println!("{}", text.text);
+            found = true;
+        }
+        if !found {
+            return Err(format!("no text stored under {}", keyword).into());
+        }
+        return Ok(());
+    }
+
+    let width = texts.iter().map(|t| t.keyword.chars().count()).max();
+    for text in texts.iter() {
+        println!(
+            "{:<width$}  {}",
+            text.keyword,
+            text.text,
+            width = width.unwrap_or(0)
+        );
+    }
+    for chunk in png.chunks_of_type("sPLT") {
+        match SuggestedPalette::decode(chunk.data()) {
+            Ok(palette) => println!("{}", palette),
+            Err(e) => warn!("skipping unreadable sPLT chunk: {}", e),
+        }
+    }
+    Ok(())
+}
+
+pub fn convert_text(args: ConvertArgs, options: &ParseOptions) -> Result<()> {
+    let mut png = read_png(&args.file, options)?;
+    let format = TextFormat::from(args.to);
+    let converted = png.convert_text(format)?;
+    write_png(&png, &args.file, &args.write)?;
+    let verb = if args.write.dry_run {
+        "would convert"
+    } else {
+        "converted"
+    };
+    println!("{} {} chunk(s) to {}", verb, converted, format);
+    Ok(())
+}
+
+pub fn ext(command: ExtCommand, options: &ParseOptions) -> Result<()> {
+    let (file, write) = (command.file(), command.write());
+    let mut png = read_png(file, options)?;
+    let changed = match &command {
+        ExtCommand::Offset(args) => {
+            png.set_image_offset(&ImageOffset {
+                x: args.x,
+                y: args.y,
+                unit: args.unit.into(),
+            })?;
+            OFFS
+        }
+        ExtCommand::Scale(args) => {
+            png.set_physical_scale(&PhysicalScale {
+                unit: args.unit.into(),
+                pixel_width: args.width.clone(),
+                pixel_height: args.height.clone(),
+            })?;
+            SCAL
+        }
+        ExtCommand::Calibration(args) => {
+            png.set_pixel_calibration(&PixelCalibration {
+                name: args.name.clone(),
+                x0: args.x0,
+                x1: args.x1,
+                equation: args.equation.into(),
+                unit: args.unit.clone(),
+                parameters: args.parameters.clone(),
+            })?;
+            PCAL
+        }
+        ExtCommand::Remove(args) => {
+            let chunk_type = match args.extension {
+                ExtensionArg::Offset => OFFS,
+                ExtensionArg::Scale => SCAL,
+                ExtensionArg::Calibration => PCAL,
+            };
+            png.remove_chunk(&chunk_type.to_string(), Removal::All)?;
+            write_png(&png, file, write)?;
+            let verb = if write.dry_run {
+                "would remove"
+            } else {
+                "removed"
+            };
+            println!("{} {}", verb, chunk_type);
+            return Ok(());
+        }
+    };
+    write_png(&png, file, write)?;
+    let verb = if write.dry_run { "would set" } else { "set" };
+    println!("{} {}", verb, changed);
+    Ok(())
+}
+
+pub fn xmp(command: XmpCommand, options: &ParseOptions) -> Result<()> {
+    let mut png = match &command {
+        XmpCommand::Get(args) => {
+            with_png_ref(&args.file, options, args.stream.stream_index, |png| {
+                Ok(png.to_png())
+            })?
+        }
+        _ => read_png(command.file(), options)?,
+    };
+    match command {
+        XmpCommand::Get(args) => {
+            let packet = png
+                .xmp()?
+                .ok_or_else(|| format!("no XMP packet in {}", args.file.display()))?;
+            // Packets usually end in a newline already.
+            match packet.ends_with('\n') {
+                true => print!("{}", packet),
+                false => println!("{}", packet),
+            }
+        }
+        XmpCommand::Set(args) => {
+            let mut packet = String::new();
+            if args.packet.as_os_str() == "-" {
+                std::io::stdin().read_to_string(&mut packet)?;
+            } else {
+                packet = fs::read_to_string(&args.packet)
+                    .map_err(|e| format!("cannot read {}: {}", args.packet.display(), e))?;
+            }
+            png.set_xmp(&packet)?;
+            write_png(&png, &args.file, &args.write)?;
+            let verb = if args.write.dry_run {
+                "would set"
+            } else {
+                "set"
+            };
+            println!("{} XMP packet ({} bytes)", verb, packet.len());
+        }
+        XmpCommand::Remove(args) => {
+            if png.remove_xmp() == 0 {
+                return Err(format!("no XMP packet in {}", args.file.display()).into());
+            }
+            write_png(&png, &args.file, &args.write)?;
+            let verb = if args.write.dry_run {
+                "would remove"
+            } else {
+                "removed"
+            };
+            println!("{} XMP packet", verb);
+        }
+    }
+    Ok(())
+}
+
+pub fn reorder(args: ReorderArgs, options: &ParseOptions) -> Result<()> {
+    let mut png = read_png(&args.file, options)?;
+    let moves = png.reorder();
+    write_png(&png, &args.file, &args.write)?;
+    let verb = if args.write.dry_run {
+        "would move"
+    } else {
+        "moved"
+    };
+    for m in moves.iter() {
+        println!("{} {} from {} to {}", verb, m.chunk_type, m.from, m.to);
+    }
+    Ok(())
+}
+
+pub fn canonicalize(args: CanonicalizeArgs, options: &ParseOptions) -> Result<()> {
+    let mut png = read_png(&args.file, options)?;
+    png.canonicalize()?;
+    if let Some(max_size) = args.max_size {
+        let limit = max_size.limit(fs::metadata(&args.file)?.len());
+        check_max_size(png.as_bytes().len() as u64, limit)?;
+    }
+    write_png(&png, &args.file, &args.write)
+}
+
+pub fn dedup(args: DedupArgs, options: &ParseOptions) -> Result<()> {
+    let mut png = read_png(&args.file, options)?;
+    for d in png.find_duplicates().iter().filter(|d| !d.identical) {
+        warn!(
+            "{} #{} differs from #{}, but only one is allowed",
+            d.chunk_type, d.index, d.original
+        );
+    }
+    let removed = png.dedup();
+    if removed.is_empty() {
+        println!("no identical copies to remove");
+        return Ok(());
+    }
+    write_png(&png, &args.file, &args.write)?;
+    let verb = if args.write.dry_run {
+        "would remove"
+    } else {
+        "removed"
+    };
+    for d in removed.iter() {
+        println!(
+            "{} {} #{} (copy of #{})",
+            verb, d.chunk_type, d.index, d.original
+        );
+    }
+    Ok(())
+}
+
+pub fn trim(args: TrimArgs, options: &ParseOptions) -> Result<()> {
+    let mut png = LazyPng::with_options(BufReader::new(File::open(&args.file)?), options)?;
+    let trimmed = png.trim();
+    if trimmed == 0 {
+        println!("no data after IEND");
+        return Ok(());
+    }
+    write_lazy(&mut png, &args.file, &args.write)?;
+    let verb = if args.write.dry_run {
+        "would remove"
+    } else {
+        "removed"
+    };
+    println!("{} {} bytes after IEND", verb, trimmed);
+    Ok(())
+}
+
+/// One failed check, as `verify` prints it.
+#[derive(Serialize)]
+struct Failure {
+    severity: String,
+    offset: u64,
+    chunk_index: Option<usize>,
+    chunk_type: Option<String>,
+    message: String,
+}
+
+/// Checks the signature and HMAC of every payload chunk in `png` that has
+/// them, with whichever keys were given. A key given with no payload to
+/// check is a failure too, so a stripped signature does not pass.
+fn check_payloads(
+    png: &PngRef,
+    pubkey: Option<&VerifyingKey>,
+    hmac_key: Option<&[u8]>,
+) -> Vec<Failure> {
+    let failure = |offset, index, chunk_type: Option<&ChunkType>, message: String| Failure {
+        severity: Severity::Error.to_string(),
+        offset,
+        chunk_index: index,
+        chunk_type: chunk_type.map(|t| t.to_string()),
+        message,
+    };
+    let mut failures = Vec::new();
+    let (mut signed, mut tagged) = (0, 0);
+    let trailing = png.trailing_chunks();
+    let mut offset = Png::STANDARD_HEADER.len() as u64;
+    for (index, chunk) in png.chunks().iter().chain(trailing.iter()).enumerate() {
+        let at = offset;
+        offset += chunk.length() as u64 + 12;
+        let mut data = chunk.data();
+        if crypto::is_signed(data) {
+            signed += 1;
+            if let Some(key) = pubkey {
+                match crypto::verify_signature(key, data) {
+                    Ok(payload) => data = payload,
+                    Err(e) => {
+                        failures.push(failure(
+                            at,
+                            Some(index),
+                            Some(chunk.chunk_type()),
+                            e.to_string(),
+                        ));
+                        continue;
+                    }
+                }
+            } else {
+                data = crypto::unwrap_unchecked(data);
+            }
+        }
+        if crypto::is_authenticated(data) {
+            tagged += 1;
+            if let Some(key) = hmac_key
+                && let Err(e) = crypto::verify_mac(key, data)
+            {
+                failures.push(failure(
+                    at,
+                    Some(index),
+                    Some(chunk.chunk_type()),
+                    e.to_string(),
+                ));
+            }
+        }
+    }
+    if pubkey.is_some() && signed == 0 {
+        failures.push(failure(
+            0,
+            None,
+            None,
+            "no signed payload found".to_string(),
+        ));
+    }
+    if hmac_key.is_some() && tagged == 0 {
+        failures.push(failure(
+            0,
+            None,
+            None,
+            "no HMAC-tagged payload found".to_string(),
+        ));
+    }
+    failures
+}
+
+/// Runs every check `validate` does and prints the problems as JSON for
+/// scripts. Only errors make the command fail.
+pub fn verify(args: VerifyArgs) -> Result<()> {
+    let pubkey = args
+        .pubkey
+        .as_deref()
+        .map(|path| VerifyingKey::from_bytes(read_key(path)?))
+        .transpose()?;
+    let hmac_key = args.hmac_key.as_deref().map(read_hmac_key).transpose()?;
+    let lenient = ParseOptions::lenient();
+    let stream = args.stream.stream_index;
+    let (report, payload_failures) = with_stream_bytes(&args.file, &lenient, stream, |bytes| {
+        let report = with_progress(bytes, |p| {
+            Png::check_all_with_registry(bytes, &handlers(), p)
+        });
+        let payload_failures = match (&pubkey, &hmac_key) {
+            (None, None) => Vec::new(),
+            _ => {
+                match PngRef::parse(bytes, &lenient) {
+                    Ok(png) => check_payloads(&png, pubkey.as_ref(), hmac_key.as_deref()),
+                    // The report already says why the file cannot be read.
+                    Err(_) => Vec::new(),
+                }
+            }
+        };
+        Ok((report, payload_failures))
+    })?;
+    let mut failures: Vec<_> = report
+        .findings()
+        .iter()
+        .filter(|f| f.severity >= Severity::Warning)
+        .map(|f| Failure {
+            severity: f.severity.to_string(),
+            offset: f.offset,
+            chunk_index: f.chunk_index,
+            chunk_type: f.chunk_type.map(|t| t.to_string()),
+            message: f.message.clone(),
+        })
+        .collect();
+    let payloads_ok = payload_failures.is_empty();
+    failures.extend(payload_failures);
+    println!("{}", serde_json::to_string_pretty(&failures)?);
+    if report.has_errors() || !payloads_ok {
+        return Err(format!("{} failed verification", args.file.display()).into());
+    }
+    Ok(())
+}
+
+pub fn hash(args: HashArgs, options: &ParseOptions) -> Result<()> {
+    let png = with_png_ref(&args.file, options, args.stream.stream_index, |png| {
+        Ok(png.to_png())
+    })?;
+    let scope = match (args.pixels_only, args.chunks) {
+        (true, _) => Scope::Pixels,
+        (false, ChunkSelection::All) => Scope::AllChunks,
+        (false, ChunkSelection::Critical) => Scope::CriticalChunks,
+    };
+    let digest = png.fingerprint(scope)?;
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    println!("{}  {}", hex, args.file.display());
+    Ok(())
+}
+
+pub fn equal(args: EqualArgs, options: &ParseOptions) -> Result<()> {
+    let stream = args.stream.stream_index;
+    let first = with_png_ref(&args.first, options, stream, |png| Ok(png.to_png()))?;
+    let second = with_png_ref(&args.second, options, stream, |png| Ok(png.to_png()))?;
+    let scope = match (args.pixels, args.ignore_ancillary) {
+        (true, _) => Scope::Pixels,
+        (false, true) => Scope::CriticalChunks,
+        (false, false) => Scope::AllChunks,
+    };
+    if !first.semantic_eq(&second, scope)? {
+        return Err(format!(
+            "{} and {} differ",
+            args.first.display(),
+            args.second.display()
+        )
+        .into());
+    }
+    println!(
+        "{} and {} are equal",
+        args.first.display(),
+        args.second.display()
+    );
+    Ok(())
+}
+
+/// The file explode writes beside the chunks, listing them in file order.
+/// Implode follows it, so entries can be reordered, removed or added.
+const MANIFEST: &str = "manifest.toml";
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    /// The bytes after IEND, trailing chunks included, relative to the
+    /// manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trailing: Option<PathBuf>,
+    chunks: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestEntry {
+    #[serde(rename = "type")]
+    chunk_type: String,
+    /// The chunk data, relative to the manifest.
+    file: PathBuf,
+}
+
+pub fn explode(args: ExplodeArgs, options: &ParseOptions) -> Result<()> {
+    if !args.force && fs::read_dir(&args.dir).is_ok_and(|mut d| d.next().is_some()) {
+        return Err(format!(
+            "{} is not empty; use --force to write into it",
+            args.dir.display()
+        )
+        .into());
+    }
+    fs::create_dir_all(&args.dir)?;
+    with_png_ref(&args.file, options, args.stream.stream_index, |png| {
+        let mut manifest = Manifest {
+            trailing: None,
+            chunks: Vec::new(),
+        };
+        for (i, chunk) in png.chunks().iter().enumerate() {
+            let file = PathBuf::from(format!("{:03}_{}.bin", i, chunk.chunk_type()));
+            fs::write(args.dir.join(&file), chunk.data())?;
+            manifest.chunks.push(ManifestEntry {
+                chunk_type: chunk.chunk_type().to_string(),
+                file,
+            });
+        }
+        if !png.trailing_data().is_empty() {
+            let file = PathBuf::from("trailing.bin");
+            fs::write(args.dir.join(&file), png.trailing_data())?;
+            manifest.trailing = Some(file);
+        }
+        fs::write(args.dir.join(MANIFEST), toml::to_string(&manifest)?)?;
+        println!(
+            "wrote {} chunk(s) to {}",
+            manifest.chunks.len(),
+            args.dir.display()
+        );
+        Ok(())
+    })
+}
+
+/// Reads `file`, named in the manifest in `dir`. Only paths inside `dir` are
+/// allowed, so a manifest cannot pull in other files on the system.
+fn read_manifest_file(dir: &Path, file: &Path) -> Result<Vec<u8>> {
+    use std::path::Component;
+    if file.as_os_str().is_empty() || !file.components().all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(format!(
+            "{}: not a relative path inside {}",
+            file.display(),
+            dir.display()
+        )
+        .into());
+    }
+    Ok(fs::read(dir.join(file)).map_err(|e| format!("{}: {}", file.display(), e))?)
+}
+
+pub fn implode(args: ImplodeArgs) -> Result<()> {
+    let path = args.dir.join(MANIFEST);
+    let manifest: Manifest = toml::from_str(&fs::read_to_string(&path)?)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut chunks = Vec::new();
+    for entry in manifest.chunks {
+        let chunk_type = ChunkType::from_str(&entry.chunk_type)
+            .map_err(|_| format!("invalid chunk type {}", entry.chunk_type))?;
+        chunks.push(Chunk::new(
+            chunk_type,
+            read_manifest_file(&args.dir, &entry.file)?,
+        ));
+    }
+    let mut png = Png::from_chunks(chunks);
+    if let Some(file) = &manifest.trailing {
+        png.set_trailing_data(read_manifest_file(&args.dir, file)?);
+    }
+    check_overwrite(&args.output, args.force)?;
+    write_atomic(&args.output, &png.as_bytes(), None)?;
+    debug!(output = %args.output.display(), "wrote PNG");
+    Ok(())
+}
+
+/// How long to wait for a burst of filesystem events to settle, so that an
+/// editor saving a file triggers one encode rather than several.
+const SETTLE: Duration = Duration::from_millis(100);
+
+/// Adds the payload to a fresh copy of the carrier and writes the result.
+fn embed(args: &WatchArgs, chunk_type: ChunkType, options: &ParseOptions) -> Result<()> {
+    let mut png = read_png(&args.carrier, options)?;
+    let payload = fs::read(&args.payload)?;
+    let length = payload.len();
+    png.insert_chunk(Chunk::new(chunk_type, payload), Placement::BeforeIend)?;
+    write_atomic(&args.output, &png.as_bytes(), None)?;
+    println!(
+        "wrote {} ({} chunk, {} bytes)",
+        args.output.display(),
+        chunk_type,
+        length
+    );
+    Ok(())
+}
+
+/// The directory to watch for changes to `path`. Watching the directory
+/// rather than the file catches editors that save by renaming a new file
+/// over the old one.
+fn watch_dir(path: &Path) -> Result<PathBuf> {
+    let dir = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    Ok(dir.canonicalize()?)
+}
+
+pub fn watch(args: WatchArgs, options: &ParseOptions) -> Result<()> {
+    let chunk_type = args.chunk_type.as_deref().ok_or(NO_CHUNK_TYPE)?;
+    let chunk_type = ChunkType::from_str(chunk_type).map_err(|_| "invalid chunk type")?;
+    // Writing to a watched file would trigger another embed, adding the
+    // chunk again on every pass.
+    for (input, name) in [(&args.carrier, "carrier"), (&args.payload, "payload")] {
+        if same_file(&args.output, input) {
+            return Err(format!("the output cannot be the {} too", name).into());
+        }
+    }
+    check_overwrite(&args.output, args.force)?;
+    embed(&args, chunk_type, options)?;
+
+    let mut watched = Vec::new();
+    for path in [&args.payload, &args.carrier] {
+        let name = path.file_name().ok_or("not a file path")?;
+        watched.push(watch_dir(path)?.join(name));
+    }
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    let mut dirs: Vec<_> = watched.iter().filter_map(|p| p.parent()).collect();
+    dirs.dedup();
+    for dir in dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+    eprintln!("watching for changes; press Ctrl-C to stop");
+
+    let relevant = |event: notify::Result<notify::Event>| match event {
+        Ok(event) => {
+            !matches!(event.kind, EventKind::Access(_))
+                && event.paths.iter().any(|p| watched.contains(p))
+        }
+        Err(e) => {
+            warn!("watch error: {}", e);
+            false
+        }
+    };
+    for event in rx.iter() {
+        if !relevant(event) {
+            continue;
+        }
+        while rx.recv_timeout(SETTLE).is_ok() {}
+        // A file caught half written fails to parse; the next event retries.
+        if let Err(e) = embed(&args, chunk_type, options) {
+            warn!("not re-encoded: {}", e);
+        }
+    }
+    Ok(())
+}
+
+pub fn keygen(args: KeygenArgs) -> Result<()> {
+    let mut public_path = args.path.clone().into_os_string();
+    public_path.push(".pub");
+    let public_path = PathBuf::from(public_path);
+    check_overwrite(&public_path, args.force)?;
+
+    let (secret, public) = if args.signing {
+        let key = SigningKey::generate()?;
+        (key.to_bytes(), key.verifying_key().to_bytes())
+    } else {
+        let key = SecretKey::generate()?;
+        (key.to_bytes(), key.public_key().to_bytes())
+    };
+    write_key(&args.path, &secret, true, args.force)?;
+    write_key(&public_path, &public, false, args.force)?;
+    println!("secret key: {}", args.path.display());
+    println!("public key: {}", public_path.display());
+    Ok(())
+}
+
+pub fn key_store(args: KeyStoreArgs) -> Result<()> {
+    let key = read_key(&args.file)?;
+    keychain_entry(&args.name)?
+        .set_secret(&key)
+        .map_err(|e| format!("cannot store {}: {}", args.name, e))?;
+    println!("stored {} in the keychain", args.name);
+    Ok(())
+}
+
+pub fn gen_type(args: GenTypeArgs) -> Result<()> {
+    let chunk_type = ChunkType::random_private().with_safe_to_copy(args.safe_to_copy);
+    println!("{}", chunk_type);
+    Ok(())
+}
+
+pub fn completions(args: CompletionsArgs) -> Result<()> {
+    let mut command = args::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(args.shell, &mut command, name, &mut std::io::stdout());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert!(looks_like_text(b"line one\n\tline two\r\n"));
+        assert!(looks_like_text("caf\u{e9}".as_bytes()));
+        assert!(looks_like_text(b""));
+        assert!(!looks_like_text(b"bell\x07"));
+        assert!(!looks_like_text(b"\xff\xfe"));
+
+        assert_eq!(sniff(&Png::STANDARD_HEADER), Some("PNG image"));
+        assert_eq!(sniff(b"PK\x03\x04rest"), Some("ZIP archive"));
+        assert_eq!(sniff(b"%PDF-1.7"), Some("PDF document"));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some("WebP image"));
+        assert_eq!(sniff(b"\0\0\0\x18ftypisom"), Some("MP4 media"));
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff(&tar), Some("tar archive"));
+        assert_eq!(sniff(b"\x00\x01\x02"), None);
+    }
+
+    #[test]
+    fn test_payload_encodings() {
+        let data = b"\x00\xffbinary\n";
+        for encoding in [PayloadEncoding::Base64, PayloadEncoding::Hex] {
+            let text = encode_payload(data, encoding);
+            assert_eq!(decode_payload(text, encoding).unwrap(), data);
+        }
+        assert_eq!(encode_payload(b"\x01\xab", PayloadEncoding::Hex), "01ab");
+        assert_eq!(
+            decode_payload(String::from("00 ff\n1A"), PayloadEncoding::Hex).unwrap(),
+            [0x00, 0xff, 0x1a]
+        );
+        assert!(decode_payload(String::from("abc"), PayloadEncoding::Hex).is_err());
+        assert!(decode_payload(String::from("zz"), PayloadEncoding::Hex).is_err());
+        assert!(decode_payload(String::from("not base64!"), PayloadEncoding::Base64).is_err());
+    }
+
+    #[test]
+    fn test_text_payload() {
+        use pngme::chunk_type::consts::TEXT;
+        let chunk = text_payload(TEXT, "Comment", b"hello".to_vec(), PayloadEncoding::Raw).unwrap();
+        assert_eq!(chunk.data(), b"Comment\0hello");
+        let chunk = text_payload(TEXT, "Comment", vec![0xff, 0], PayloadEncoding::Base64).unwrap();
+        assert_eq!(chunk.data(), b"Comment\0/wA=");
+        assert!(text_payload(TEXT, "Comment", vec![0xff], PayloadEncoding::Raw).is_err());
+        let private = ChunkType::from_str("ruSt").unwrap();
+        assert!(text_payload(private, "Comment", b"hello".to_vec(), PayloadEncoding::Raw).is_err());
+    }
+
+    #[test]
+    fn test_check_payloads() {
+        use pngme::chunk_type::consts::{IEND, IHDR};
+        let key = SigningKey::generate().unwrap();
+        let payload = crypto::authenticate(b"secret", b"payload").unwrap();
+        let signed = Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            crypto::sign(&key, &payload),
+        );
+        let png = Png::from_chunks(vec![
+            Chunk::new(IHDR, vec![0; 13]),
+            signed,
+            Chunk::new(IEND, Vec::new()),
+        ]);
+        let bytes = png.as_bytes();
+        let png = PngRef::parse(&bytes, &ParseOptions::default()).unwrap();
+        let pubkey = key.verifying_key();
+        assert!(check_payloads(&png, Some(&pubkey), Some(b"secret")).is_empty());
+
+        let wrong = SigningKey::generate().unwrap().verifying_key();
+        let failures = check_payloads(&png, Some(&wrong), None);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].chunk_index, Some(1));
+        assert_eq!(failures[0].offset, 8 + 25);
+        assert_eq!(check_payloads(&png, None, Some(b"wrong")).len(), 1);
+
+        let bytes = Png::from_chunks(vec![Chunk::new(IEND, Vec::new())]).as_bytes();
+        let bare = PngRef::parse(&bytes, &ParseOptions::default()).unwrap();
+        let failures = check_payloads(&bare, Some(&pubkey), Some(b"secret"));
+        assert_eq!(failures.len(), 2);
+    }
+
+    #[test]
+    fn test_payload_round_trip_for_every_placement() {
+        use crate::args::{Cli, Command};
+        use clap::Parser;
+        use pngme::chunk_type::consts::{IDAT, IEND, IHDR};
+
+        let dir = std::env::temp_dir().join(format!("pngme-placement-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.png");
+        let path_arg = path.to_str().unwrap();
+        let image = Png::from_chunks(vec![
+            Chunk::new(IHDR, vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            Chunk::new(IDAT, Vec::new()),
+            Chunk::new(IEND, Vec::new()),
+        ]);
+        let run = |argv: &[&str]| {
+            let options = ParseOptions::default();
+            match Cli::try_parse_from([&["pngme"], argv].concat())
+                .unwrap()
+                .command
+            {
+                Command::Encode(args) => encode(args, &options),
+                Command::Decode(args) => decode(args, &options),
+                Command::Remove(args) => remove(args, &options),
+                _ => unreachable!(),
+            }
+        };
+        let payloads = |bytes: &[u8]| {
+            let png = PngRef::parse(bytes, &ParseOptions::default()).unwrap();
+            let trailing = png.trailing_chunks();
+            png.chunks()
+                .iter()
+                .chain(trailing.iter())
+                .filter(|c| c.chunk_type().to_string() == "ruSt")
+                .count()
+        };
+
+        for placement in ["before-iend", "after-iend", "after-ihdr"] {
+            fs::write(&path, image.as_bytes()).unwrap();
+            run(&[
+                "encode",
+                path_arg,
+                "ruSt",
+                "hidden",
+                "--placement",
+                placement,
+            ])
+            .unwrap();
+            assert_eq!(payloads(&fs::read(&path).unwrap()), 1, "{}", placement);
+            run(&["decode", path_arg, "ruSt"]).unwrap();
+            run(&["remove", path_arg, "ruSt"]).unwrap();
+            assert_eq!(fs::read(&path).unwrap(), image.as_bytes(), "{}", placement);
+            assert!(run(&["decode", path_arg, "ruSt"]).is_err());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_build_within() {
+        let message = vec![b'a'; 4096];
+        let build = |compression: Option<Compression>| -> Result<Chunk> {
+            let data = match compression {
+                Some(compression) => payload::compress(&message, compression)?,
+                None => message.clone(),
+            };
+            Ok(Chunk::new(ChunkType::from_str("ruSt").unwrap(), data))
+        };
+        let size = |chunk: &Chunk| chunk.length() as u64;
+        let fast = Compression::Deflate { level: 0 };
+        let fast_size = size(&build(Some(fast)).unwrap());
+        let strong_size = size(&build(Some(fast.strongest())).unwrap());
+        assert!(strong_size < fast_size);
+
+        // Within the limit, the requested level is kept.
+        let chunk = build_within(build, Some(fast), size, fast_size).unwrap();
+        assert_eq!(size(&chunk), fast_size);
+        // Over it, the strongest level is tried next.
+        let chunk = build_within(build, Some(fast), size, strong_size).unwrap();
+        assert_eq!(size(&chunk), strong_size);
+        // And if that is over too, it fails.
+        let error = build_within(build, Some(fast), size, strong_size - 1).unwrap_err();
+        assert!(error.to_string().contains("over the limit"));
+        // Without compression there is nothing to escalate.
+        assert!(build_within(build, None, size, strong_size).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_key_restricts_an_existing_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("pngme-key-{}", process::id()));
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_key(&path, &[7; KEY_LEN], true, true).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(read_key(&path).unwrap(), [7; KEY_LEN]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_explode_implode_round_trip() {
+        use crate::args::{Cli, Command};
+        use clap::Parser;
+        use pngme::chunk_type::consts::{IDAT, IEND, IHDR};
+
+        let dir = std::env::temp_dir().join(format!("pngme-explode-{}", process::id()));
+        let chunks = dir.join("chunks");
+        fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("in.png"), dir.join("out.png"));
+        let mut image = Png::from_chunks(vec![
+            Chunk::new(IHDR, vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            Chunk::new(
+                ChunkType::from_str("tEXt").unwrap(),
+                b"Title\0pngme".to_vec(),
+            ),
+            Chunk::new(IDAT, vec![0x78, 0x9c, 0x63, 0x60, 0, 0, 0, 2, 0, 1]),
+            Chunk::new(IEND, Vec::new()),
+        ]);
+        // Trailing chunks and data after IEND survive the round trip too.
+        image.set_trailing_data(b"after IEND".to_vec());
+        fs::write(&input, image.as_bytes()).unwrap();
+        let run = |argv: &[&str]| match Cli::try_parse_from([&["pngme"], argv].concat())
+            .unwrap()
+            .command
+        {
+            Command::Explode(args) => explode(args, &ParseOptions::default()),
+            Command::Implode(args) => implode(args),
+            _ => unreachable!(),
+        };
+        let (input, output, chunks) = (
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+            chunks.to_str().unwrap(),
+        );
+
+        run(&["explode", input, chunks]).unwrap();
+        run(&["implode", chunks, output]).unwrap();
+        assert_eq!(fs::read(output).unwrap(), fs::read(input).unwrap());
+
+        // A directory that already holds files is left alone without --force.
+        let error = run(&["explode", input, chunks]).unwrap_err();
+        assert!(error.to_string().contains("is not empty"));
+        run(&["explode", input, chunks, "--force"]).unwrap();
+        assert!(run(&["implode", chunks, output]).is_err());
+        run(&["implode", chunks, output, "--force"]).unwrap();
+        assert_eq!(fs::read(output).unwrap(), fs::read(input).unwrap());
+
+        // Entries may not point outside the directory.
+        let manifest = Path::new(chunks).join(MANIFEST);
+        for file in ["../in.png", "/etc/passwd"] {
+            let text = fs::read_to_string(&manifest).unwrap();
+            let text = text.replace("\"trailing.bin\"", &format!("{:?}", file));
+            fs::write(&manifest, &text).unwrap();
+            let error = run(&["implode", chunks, output, "--force"]).unwrap_err();
+            assert!(
+                error.to_string().contains("not a relative path"),
+                "{}",
+                error
+            );
+            fs::write(
+                &manifest,
+                text.replace(&format!("{:?}", file), "\"trailing.bin\""),
+            )
+            .unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stream_index_for_read_commands() {
+        use crate::args::{Cli, Command};
+        use clap::Parser;
+        use pngme::chunk_type::consts::{IDAT, IEND, IHDR};
+
+        let dir = std::env::temp_dir().join(format!("pngme-streams-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = |text: Option<&str>| {
+            let mut png = Png::from_chunks(vec![
+                Chunk::new(IHDR, vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+                Chunk::new(IDAT, vec![0x78, 0x9c, 0x63, 0x60, 0, 0, 0, 2, 0, 1]),
+                Chunk::new(IEND, Vec::new()),
+            ]);
+            if let Some(text) = text {
+                png.set_text("Comment", text).unwrap();
+                png.set_xmp("<x:xmpmeta/>").unwrap();
+            }
+            png.as_bytes()
+        };
+        // Only the second stream of each file has text and XMP.
+        let (first, second) = (dir.join("first.png"), dir.join("second.png"));
+        fs::write(&first, [image(None), image(Some("b"))].concat()).unwrap();
+        fs::write(&second, [image(Some("b")), image(Some("b"))].concat()).unwrap();
+        let run = |argv: &[&str]| {
+            let options = ParseOptions::default();
+            match Cli::try_parse_from([&["pngme"], argv].concat())
+                .unwrap()
+                .command
+            {
+                Command::Meta(args) => meta(args, &options),
+                Command::Xmp(command) => xmp(command, &options),
+                Command::Equal(args) => equal(args, &options),
+                _ => unreachable!(),
+            }
+        };
+        let (first, second) = (first.to_str().unwrap(), second.to_str().unwrap());
+
+        assert!(run(&["meta", first, "--get", "Comment"]).is_err());
+        run(&["meta", first, "--get", "Comment", "--stream-index", "1"]).unwrap();
+        assert!(run(&["xmp", "get", first]).is_err());
+        run(&["xmp", "get", first, "--stream-index", "1"]).unwrap();
+        assert!(run(&["equal", first, second]).is_err());
+        run(&["equal", first, second, "--stream-index", "1"]).unwrap();
+
+        // Writing back a single stream would drop the others.
+        let argv = [
+            "pngme",
+            "meta",
+            first,
+            "--set",
+            "a",
+            "b",
+            "--stream-index",
+            "1",
+        ];
+        assert!(Cli::try_parse_from(argv).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_atomic_with_backup() {
+        let dir = std::env::temp_dir().join(format!("pngme-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.png");
+        fs::write(&path, b"old").unwrap();
+
+        write_atomic(&path, b"new", Some(OsStr::new(".bak"))).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read(dir.join("image.png.bak")).unwrap(), b"old");
+
+        write_atomic(&path, b"newer", None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"newer");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
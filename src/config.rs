@@ -0,0 +1,211 @@
+use crate::args::{Cli, Command, ParseMode, TextCommand, parse_compression};
+use pngme::Result;
+use pngme::payload::Compression;
+use serde::{Deserialize, Deserializer, de};
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Defaults read from the user's config file. Anything given on the command
+/// line wins over the file.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Chunk type for decode, remove and watch when none is given.
+    pub chunk_type: Option<String>,
+    /// Directory edited files are written to when no output is given.
+    pub output_dir: Option<PathBuf>,
+    /// How strictly input files are parsed.
+    pub parse_mode: Option<ParseMode>,
+    /// Public keys encode encrypts to when no `--recipient` is given.
+    pub recipients: Vec<PathBuf>,
+    /// Compression for encode, written like `--compress`.
+    #[serde(deserialize_with = "deserialize_compression")]
+    pub compress: Option<Compression>,
+}
+
+fn deserialize_compression<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> core::result::Result<Option<Compression>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| parse_compression(&value).map_err(de::Error::custom))
+        .transpose()
+}
+
+/// `$XDG_CONFIG_HOME/pngme/config.toml`, falling back to `~/.config`.
+fn default_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".config")))?;
+    Some(dir.join("pngme").join("config.toml"))
+}
+
+impl Config {
+    /// Reads the file at `path`, or the default location when `path` is
+    /// `None`. A missing default file is not an error; a missing explicit one
+    /// is.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e).into()),
+            Err(e) if e.kind() == ErrorKind::NotFound && !required => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e).into()),
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Fills in whatever the command line left unset.
+    pub fn apply(&self, cli: &mut Cli) {
+        if cli.parse_mode.is_none() {
+            cli.parse_mode = self.parse_mode;
+        }
+        let (file, write) = match &mut cli.command {
+            Command::Encode(args) => {
+                args.compress = args.compress.or(self.compress);
+                if args.recipients.is_empty() {
+                    args.recipients = self.recipients.clone();
+                }
+                (&args.file, &mut args.write)
+            }
+            Command::Decode(args) => {
+                args.chunk_type = args.chunk_type.take().or_else(|| self.chunk_type.clone());
+                return;
+            }
+            Command::Remove(args) => {
+                args.chunk_type = args.chunk_type.take().or_else(|| self.chunk_type.clone());
+                (&args.file, &mut args.write)
+            }
+            Command::Watch(args) => {
+                args.chunk_type = args.chunk_type.take().or_else(|| self.chunk_type.clone());
+                return;
+            }
+            Command::Meta(args) => (&args.file, &mut args.write),
+            Command::Reorder(args) => (&args.file, &mut args.write),
+            Command::Canonicalize(args) => (&args.file, &mut args.write),
+            Command::Dedup(args) => (&args.file, &mut args.write),
+            Command::Trim(args) => (&args.file, &mut args.write),
+            Command::Text(TextCommand::Convert(args)) => (&args.file, &mut args.write),
+            Command::Ext(command) => command.write_args(),
+            Command::Xmp(command) => match command.write_args() {
+                Some(args) => args,
+                None => return,
+            },
+            #[cfg(feature = "tui")]
+            Command::Tui(args) => (&args.file, &mut args.write),
+            _ => return,
+        };
+        if write.output.is_none()
+            && let Some(dir) = &self.output_dir
+            && let Some(name) = file.file_name()
+        {
+            write.output = Some(dir.join(name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "chunk-type = \"ruSt\"\noutput-dir = \"/tmp/out\"\nparse-mode = \"strict\"\n\
+             recipients = [\"alice.pub\"]\ncompress = \"deflate:9\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                chunk_type: Some("ruSt".to_string()),
+                output_dir: Some(PathBuf::from("/tmp/out")),
+                parse_mode: Some(ParseMode::Strict),
+                recipients: vec![PathBuf::from("alice.pub")],
+                compress: Some(Compression::Deflate { level: 9 }),
+            }
+        );
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert!(Config::parse("colour = true").is_err());
+        assert!(Config::parse("compress = \"lzma\"").is_err());
+    }
+
+    #[test]
+    fn test_command_line_wins() {
+        let config = Config {
+            chunk_type: Some("ruSt".to_string()),
+            output_dir: Some(PathBuf::from("out")),
+            parse_mode: Some(ParseMode::Strict),
+            recipients: vec![PathBuf::from("alice.pub")],
+            compress: Some(Compression::Deflate { level: 9 }),
+        };
+
+        let mut cli = Cli::parse_from(["pngme", "decode", "a.png"]);
+        config.apply(&mut cli);
+        assert_eq!(cli.parse_mode, Some(ParseMode::Strict));
+        let Command::Decode(args) = &cli.command else {
+            unreachable!()
+        };
+        assert_eq!(args.chunk_type.as_deref(), Some("ruSt"));
+
+        let mut cli = Cli::parse_from([
+            "pngme",
+            "--parse-mode",
+            "lenient",
+            "remove",
+            "a.png",
+            "abCd",
+        ]);
+        config.apply(&mut cli);
+        assert_eq!(cli.parse_mode, Some(ParseMode::Lenient));
+        let Command::Remove(args) = &cli.command else {
+            unreachable!()
+        };
+        assert_eq!(args.chunk_type.as_deref(), Some("abCd"));
+
+        let mut cli = Cli::parse_from(["pngme", "encode", "dir/a.png", "ruSt", "hi"]);
+        config.apply(&mut cli);
+        let Command::Encode(args) = &cli.command else {
+            unreachable!()
+        };
+        assert_eq!(args.write.output, Some(Path::new("out").join("a.png")));
+        assert_eq!(args.compress, Some(Compression::Deflate { level: 9 }));
+        assert_eq!(args.recipients, [PathBuf::from("alice.pub")]);
+
+        let mut cli = Cli::parse_from([
+            "pngme",
+            "encode",
+            "a.png",
+            "ruSt",
+            "hi",
+            "--compress",
+            "deflate:1",
+            "--recipient",
+            "bob.pub",
+        ]);
+        config.apply(&mut cli);
+        let Command::Encode(args) = &cli.command else {
+            unreachable!()
+        };
+        assert_eq!(args.compress, Some(Compression::Deflate { level: 1 }));
+        assert_eq!(args.recipients, [PathBuf::from("bob.pub")]);
+
+        let mut cli = Cli::parse_from(["pngme", "remove", "a.png", "-o", "b.png"]);
+        config.apply(&mut cli);
+        let Command::Remove(args) = &cli.command else {
+            unreachable!()
+        };
+        assert_eq!(args.write.output, Some(PathBuf::from("b.png")));
+    }
+}
@@ -0,0 +1,402 @@
+use alloc::format;
+use alloc::vec::Vec;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::{Signature, Signer};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use x25519_dalek::StaticSecret;
+
+/// Bytes in a public or secret key.
+pub const KEY_LEN: usize = 32;
+
+/// The first byte of a payload sealed to one recipient, the only format
+/// before multiple recipients were supported. It is still opened.
+const VERSION_SINGLE: u8 = 1;
+
+/// The first byte of every payload sealed now.
+const VERSION: u8 = 2;
+
+/// Bytes in the content key once wrapped for a recipient, tag included.
+const WRAPPED_LEN: usize = KEY_LEN + 16;
+
+/// Mixed into the key derivation so keys are never shared with other uses
+/// of the same X25519 keys.
+const LABEL: &[u8] = b"pngme x25519 chacha20poly1305";
+
+/// Marks a payload wrapped by [`sign`]: followed by an Ed25519 signature
+/// over the rest.
+const SIGNED_MAGIC: &[u8; 4] = b"PMEs";
+
+/// Marks a payload wrapped by [`authenticate`]: followed by an
+/// HMAC-SHA256 tag over the rest.
+const MAC_MAGIC: &[u8; 4] = b"PMEh";
+
+const SIGNATURE_LEN: usize = 64;
+const TAG_LEN: usize = 32;
+
+/// An X25519 secret key, able to open payloads sealed to its public key.
+pub struct SecretKey(StaticSecret);
+
+/// An X25519 public key, to which anyone can seal a payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey(x25519_dalek::PublicKey);
+
+impl SecretKey {
+    /// A new key from the operating system's random number generator.
+    pub fn generate() -> crate::Result<Self> {
+        let mut bytes = [0u8; KEY_LEN];
+        getrandom::fill(&mut bytes).map_err(|e| format!("no randomness: {}", e))?;
+        Ok(Self::from_bytes(bytes))
+    }
+
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Self(StaticSecret::from(bytes))
+    }
+
+    pub fn to_bytes(&self) -> [u8; KEY_LEN] {
+        self.0.to_bytes()
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(x25519_dalek::PublicKey::from(&self.0))
+    }
+}
+
+impl PublicKey {
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Self(x25519_dalek::PublicKey::from(bytes))
+    }
+
+    pub fn to_bytes(&self) -> [u8; KEY_LEN] {
+        self.0.to_bytes()
+    }
+}
+
+/// An Ed25519 secret key, able to sign payloads.
+pub struct SigningKey(ed25519_dalek::SigningKey);
+
+/// An Ed25519 public key, against which signed payloads are verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyingKey(ed25519_dalek::VerifyingKey);
+
+impl SigningKey {
+    /// A new key from the operating system's random number generator.
+    pub fn generate() -> crate::Result<Self> {
+        let mut bytes = [0u8; KEY_LEN];
+        getrandom::fill(&mut bytes).map_err(|e| format!("no randomness: {}", e))?;
+        Ok(Self::from_bytes(bytes))
+    }
+
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Self(ed25519_dalek::SigningKey::from_bytes(&bytes))
+    }
+
+    pub fn to_bytes(&self) -> [u8; KEY_LEN] {
+        self.0.to_bytes()
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey(self.0.verifying_key())
+    }
+}
+
+impl VerifyingKey {
+    /// Fails if `bytes` is not a point on the curve.
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> crate::Result<Self> {
+        ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+            .map(Self)
+            .map_err(|_| "invalid Ed25519 public key".into())
+    }
+
+    pub fn to_bytes(&self) -> [u8; KEY_LEN] {
+        self.0.to_bytes()
+    }
+}
+
+/// The key wrapping the content key for one recipient. Every recipient
+/// has a fresh ephemeral key, so this is never reused and a fixed nonce is
+/// safe.
+fn wrapping_key(shared: &[u8; 32], ephemeral: &PublicKey, recipient: &PublicKey) -> Key {
+    let digest = Sha256::new()
+        .chain_update(LABEL)
+        .chain_update(shared)
+        .chain_update(ephemeral.to_bytes())
+        .chain_update(recipient.to_bytes())
+        .finalize();
+    Key::from(<[u8; 32]>::from(digest))
+}
+
+/// Encrypts `data` under `key` with a fixed nonce, which is safe only because
+/// no key here encrypts more than one message.
+fn encrypt(key: &Key, data: &[u8]) -> crate::Result<Vec<u8>> {
+    Ok(ChaCha20Poly1305::new(key)
+        .encrypt(&Nonce::default(), data)
+        .map_err(|_| "encryption failed")?)
+}
+
+fn decrypt(key: &Key, data: &[u8]) -> Option<Vec<u8>> {
+    ChaCha20Poly1305::new(key)
+        .decrypt(&Nonce::default(), data)
+        .ok()
+}
+
+/// Encrypts `plaintext` so that the holder of the secret key for any of
+/// `recipients` can read it, like age or OpenPGP session keys: the data is
+/// encrypted once under a random content key with ChaCha20-Poly1305, and
+/// that key is wrapped for each recipient through an X25519 exchange with a
+/// fresh ephemeral key.
+///
+/// The result holds a version byte, the number of recipients, an ephemeral
+/// public key and wrapped content key per recipient, and the ciphertext.
+pub fn seal(recipients: &[PublicKey], plaintext: &[u8]) -> crate::Result<Vec<u8>> {
+    let count = u8::try_from(recipients.len())
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or("a payload needs between 1 and 255 recipients")?;
+    let content_key = Key::from(SecretKey::generate()?.to_bytes());
+
+    let mut sealed = Vec::new();
+    sealed.extend([VERSION, count]);
+    for recipient in recipients {
+        let ephemeral = SecretKey::generate()?;
+        let ephemeral_public = ephemeral.public_key();
+        let shared = ephemeral.0.diffie_hellman(&recipient.0);
+        if !shared.was_contributory() {
+            return Err("recipient public key is invalid".into());
+        }
+        let key = wrapping_key(shared.as_bytes(), &ephemeral_public, recipient);
+        sealed.extend(ephemeral_public.to_bytes());
+        sealed.extend(encrypt(&key, &content_key)?);
+    }
+    sealed.extend(encrypt(&content_key, plaintext)?);
+    Ok(sealed)
+}
+
+/// Decrypts with `identity` the data in `stanza`, an ephemeral public key
+/// followed by the wrapped content key, or `None` if it is for someone else.
+fn unwrap_key(identity: &SecretKey, stanza: &[u8]) -> Option<Vec<u8>> {
+    let (ephemeral, wrapped) = stanza.split_first_chunk::<KEY_LEN>()?;
+    let ephemeral = PublicKey::from_bytes(*ephemeral);
+    let shared = identity.0.diffie_hellman(&ephemeral.0);
+    let key = wrapping_key(shared.as_bytes(), &ephemeral, &identity.public_key());
+    decrypt(&key, wrapped)
+}
+
+/// Decrypts a payload made by [`seal`], failing if `identity` is not one of
+/// its recipients or the payload has been altered.
+pub fn open(identity: &SecretKey, sealed: &[u8]) -> crate::Result<Vec<u8>> {
+    const WRONG_KEY: &str = "cannot decrypt: wrong key or corrupted payload";
+    let (&version, rest) = sealed.split_first().ok_or("sealed payload is empty")?;
+    match version {
+        // One recipient, whose wrapping key encrypts the data itself.
+        VERSION_SINGLE => Ok(unwrap_key(identity, rest).ok_or(WRONG_KEY)?),
+        VERSION => {
+            let (&count, rest) = rest.split_first().ok_or("sealed payload is truncated")?;
+            let stanzas_len = count as usize * (KEY_LEN + WRAPPED_LEN);
+            if rest.len() < stanzas_len {
+                return Err("sealed payload is truncated".into());
+            }
+            let (stanzas, ciphertext) = rest.split_at(stanzas_len);
+            let content_key = stanzas
+                .chunks_exact(KEY_LEN + WRAPPED_LEN)
+                .find_map(|stanza| unwrap_key(identity, stanza))
+                .ok_or(WRONG_KEY)?;
+            let content_key = Key::try_from(content_key.as_slice()).map_err(|_| WRONG_KEY)?;
+            Ok(decrypt(&content_key, ciphertext).ok_or(WRONG_KEY)?)
+        }
+        _ => Err(format!("unknown sealed payload version {}", version).into()),
+    }
+}
+
+/// Wraps `payload` with an Ed25519 signature over it, so anyone holding the
+/// public key can check who wrote it with [`verify_signature`].
+pub fn sign(key: &SigningKey, payload: &[u8]) -> Vec<u8> {
+    let signature = key.0.sign(payload);
+    let mut signed = Vec::with_capacity(SIGNED_MAGIC.len() + SIGNATURE_LEN + payload.len());
+    signed.extend_from_slice(SIGNED_MAGIC);
+    signed.extend(signature.to_bytes());
+    signed.extend_from_slice(payload);
+    signed
+}
+
+/// Whether `payload` starts with the header [`sign`] writes.
+pub fn is_signed(payload: &[u8]) -> bool {
+    payload.len() >= SIGNED_MAGIC.len() + SIGNATURE_LEN && payload.starts_with(SIGNED_MAGIC)
+}
+
+/// Checks a payload wrapped by [`sign`] against `key`, returning what was
+/// signed.
+pub fn verify_signature<'p>(key: &VerifyingKey, signed: &'p [u8]) -> crate::Result<&'p [u8]> {
+    if !is_signed(signed) {
+        return Err("payload is not signed".into());
+    }
+    let (signature, payload) = signed[SIGNED_MAGIC.len()..].split_at(SIGNATURE_LEN);
+    let signature = Signature::from_slice(signature).map_err(|_| "malformed signature")?;
+    key.0
+        .verify_strict(payload, &signature)
+        .map_err(|_| "signature does not match the payload")?;
+    Ok(payload)
+}
+
+fn mac(key: &[u8]) -> crate::Result<Hmac<Sha256>> {
+    if key.is_empty() {
+        return Err("the HMAC key is empty".into());
+    }
+    Ok(Hmac::<Sha256>::new_from_slice(key).map_err(|_| "invalid HMAC key")?)
+}
+
+/// Wraps `payload` with an HMAC-SHA256 tag over it, so anyone sharing `key`
+/// can check it is unaltered with [`verify_mac`].
+pub fn authenticate(key: &[u8], payload: &[u8]) -> crate::Result<Vec<u8>> {
+    let tag = mac(key)?.chain_update(payload).finalize().into_bytes();
+    let mut tagged = Vec::with_capacity(MAC_MAGIC.len() + TAG_LEN + payload.len());
+    tagged.extend_from_slice(MAC_MAGIC);
+    tagged.extend_from_slice(&tag);
+    tagged.extend_from_slice(payload);
+    Ok(tagged)
+}
+
+/// Whether `payload` starts with the header [`authenticate`] writes.
+pub fn is_authenticated(payload: &[u8]) -> bool {
+    payload.len() >= MAC_MAGIC.len() + TAG_LEN && payload.starts_with(MAC_MAGIC)
+}
+
+/// Checks a payload wrapped by [`authenticate`] against `key`, in constant
+/// time, returning what was tagged.
+pub fn verify_mac<'p>(key: &[u8], tagged: &'p [u8]) -> crate::Result<&'p [u8]> {
+    if !is_authenticated(tagged) {
+        return Err("payload has no HMAC".into());
+    }
+    let (tag, payload) = tagged[MAC_MAGIC.len()..].split_at(TAG_LEN);
+    mac(key)?
+        .chain_update(payload)
+        .verify_slice(tag)
+        .map_err(|_| "HMAC does not match the payload")?;
+    Ok(payload)
+}
+
+/// Removes any signature and HMAC wrapping from `payload` without checking
+/// them.
+pub fn unwrap_unchecked(mut payload: &[u8]) -> &[u8] {
+    loop {
+        if is_signed(payload) {
+            payload = &payload[SIGNED_MAGIC.len() + SIGNATURE_LEN..];
+        } else if is_authenticated(payload) {
+            payload = &payload[MAC_MAGIC.len() + TAG_LEN..];
+        } else {
+            return payload;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let identity = SecretKey::generate().unwrap();
+        let sealed = seal(&[identity.public_key()], b"for your eyes only").unwrap();
+        assert_eq!(sealed[..2], [VERSION, 1]);
+        assert_eq!(sealed.len(), 2 + KEY_LEN + WRAPPED_LEN + 18 + 16);
+        assert_eq!(open(&identity, &sealed).unwrap(), b"for your eyes only");
+
+        let again = seal(&[identity.public_key()], b"for your eyes only").unwrap();
+        assert_ne!(sealed, again);
+    }
+
+    #[test]
+    fn test_many_recipients() {
+        let team: Vec<_> = (0..3).map(|_| SecretKey::generate().unwrap()).collect();
+        let keys: Vec<_> = team.iter().map(|k| k.public_key()).collect();
+        let sealed = seal(&keys, b"team secret").unwrap();
+        assert_eq!(sealed[1], 3);
+        for member in team.iter() {
+            assert_eq!(open(member, &sealed).unwrap(), b"team secret");
+        }
+        assert!(open(&SecretKey::generate().unwrap(), &sealed).is_err());
+
+        assert!(seal(&[], b"nobody").is_err());
+        assert!(seal(&vec![keys[0]; 256], b"too many").is_err());
+    }
+
+    #[test]
+    fn test_opens_single_recipient_format() {
+        let identity = SecretKey::generate().unwrap();
+        let ephemeral = SecretKey::generate().unwrap();
+        let shared = ephemeral.0.diffie_hellman(&identity.public_key().0);
+        let key = wrapping_key(
+            shared.as_bytes(),
+            &ephemeral.public_key(),
+            &identity.public_key(),
+        );
+        let mut sealed = vec![VERSION_SINGLE];
+        sealed.extend(ephemeral.public_key().to_bytes());
+        sealed.extend(encrypt(&key, b"old format").unwrap());
+        assert_eq!(open(&identity, &sealed).unwrap(), b"old format");
+    }
+
+    #[test]
+    fn test_rejects_wrong_key_and_tampering() {
+        let identity = SecretKey::generate().unwrap();
+        let sealed = seal(&[identity.public_key()], b"secret").unwrap();
+
+        let stranger = SecretKey::generate().unwrap();
+        assert!(open(&stranger, &sealed).is_err());
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open(&identity, &tampered).is_err());
+        assert!(open(&identity, &sealed[..20]).is_err());
+        assert!(open(&identity, &[]).is_err());
+    }
+
+    #[test]
+    fn test_sign() {
+        let key = SigningKey::generate().unwrap();
+        let signed = sign(&key, b"from me");
+        assert!(is_signed(&signed));
+        assert_eq!(
+            verify_signature(&key.verifying_key(), &signed).unwrap(),
+            b"from me"
+        );
+        assert_eq!(unwrap_unchecked(&signed), b"from me");
+
+        let other = SigningKey::generate().unwrap().verifying_key();
+        assert!(verify_signature(&other, &signed).is_err());
+        let mut tampered = signed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(verify_signature(&key.verifying_key(), &tampered).is_err());
+        assert!(verify_signature(&key.verifying_key(), b"from me").is_err());
+
+        let copy = SigningKey::from_bytes(key.to_bytes());
+        let public = VerifyingKey::from_bytes(key.verifying_key().to_bytes()).unwrap();
+        assert_eq!(copy.verifying_key(), public);
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let tagged = authenticate(b"shared secret", b"unaltered").unwrap();
+        assert!(is_authenticated(&tagged));
+        assert_eq!(verify_mac(b"shared secret", &tagged).unwrap(), b"unaltered");
+        assert!(verify_mac(b"wrong secret", &tagged).is_err());
+        let mut tampered = tagged.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(verify_mac(b"shared secret", &tampered).is_err());
+        assert!(authenticate(b"", b"x").is_err());
+
+        let key = SigningKey::generate().unwrap();
+        let both = sign(&key, &tagged);
+        assert_eq!(unwrap_unchecked(&both), b"unaltered");
+    }
+
+    #[test]
+    fn test_key_bytes() {
+        let identity = SecretKey::generate().unwrap();
+        let copy = SecretKey::from_bytes(identity.to_bytes());
+        assert_eq!(copy.public_key(), identity.public_key());
+        let public = PublicKey::from_bytes(identity.public_key().to_bytes());
+        assert_eq!(public, identity.public_key());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::chunk_type::ChunkType;
+use alloc::string::String;
+use core::fmt::{Display, Formatter};
+
+/// What went wrong while parsing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    InvalidSignature,
+    /// The input ended in the middle of a chunk.
+    Truncated,
+    InvalidChunkType([u8; 4]),
+    CrcMismatch {
+        stored: u32,
+        computed: u32,
+    },
+    ChunkTooLong {
+        length: u32,
+        max: u32,
+    },
+    TooManyChunks {
+        max: usize,
+    },
+    FileTooLarge {
+        size: u64,
+        max: u64,
+    },
+    /// Bytes after a single chunk, or after IEND when trailing data is not
+    /// allowed.
+    TrailingData {
+        length: u64,
+    },
+    MissingIend,
+    UnknownCritical,
+    InvalidOrder(String),
+}
+
+/// A parse failure, located by absolute byte offset and, where known, the
+/// index and type of the chunk being parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    kind: ParseErrorKind,
+    offset: u64,
+    chunk_index: Option<usize>,
+    chunk_type: Option<ChunkType>,
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidSignature => write!(f, "invalid PNG signature"),
+            Self::Truncated => write!(f, "unexpected end of data"),
+            Self::InvalidChunkType(bytes) => write!(f, "invalid chunk type {:02x?}", bytes),
+            Self::CrcMismatch { stored, computed } => write!(
+                f,
+                "CRC mismatch: stored {:#010x}, computed {:#010x}",
+                stored, computed
+            ),
+            Self::ChunkTooLong { length, max } => {
+                write!(f, "chunk length {} exceeds the limit of {}", length, max)
+            }
+            Self::TooManyChunks { max } => write!(f, "more than {} chunks", max),
+            Self::FileTooLarge { size, max } => {
+                write!(f, "file size {} exceeds the limit of {}", size, max)
+            }
+            Self::TrailingData { length } => {
+                write!(f, "{} bytes of unexpected trailing data", length)
+            }
+            Self::MissingIend => write!(f, "file does not end with IEND"),
+            Self::UnknownCritical => write!(f, "unknown critical chunk"),
+            Self::InvalidOrder(message) => write!(f, "invalid chunk order: {}", message),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} at offset {}", self.kind, self.offset)?;
+        match (self.chunk_index, self.chunk_type) {
+            (Some(index), Some(typ)) => write!(f, " (chunk {}, {})", index, typ),
+            (Some(index), None) => write!(f, " (chunk {})", index),
+            (None, Some(typ)) => write!(f, " ({})", typ),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+impl ParseError {
+    pub(crate) fn new(kind: ParseErrorKind, offset: u64) -> Self {
+        Self {
+            kind,
+            offset,
+            chunk_index: None,
+            chunk_type: None,
+        }
+    }
+
+    pub(crate) fn in_chunk(mut self, index: Option<usize>, chunk_type: Option<ChunkType>) -> Self {
+        self.chunk_index = index;
+        self.chunk_type = chunk_type;
+        self
+    }
+
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+
+    /// Absolute byte offset of the field where the problem was found.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn chunk_index(&self) -> Option<usize> {
+        self.chunk_index
+    }
+
+    pub fn chunk_type(&self) -> Option<ChunkType> {
+        self.chunk_type
+    }
+}
+
+/// A failure to read, parse or write a file, naming the file. The
+/// underlying [`ParseError`] or [`std::io::Error`] is kept as the error's
+/// source.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct FileError {
+    path: std::path::PathBuf,
+    source: crate::Error,
+    writing: bool,
+}
+
+#[cfg(feature = "std")]
+impl FileError {
+    pub(crate) fn new(path: &std::path::Path, source: impl Into<crate::Error>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            source: source.into(),
+            writing: false,
+        }
+    }
+
+    pub(crate) fn writing(path: &std::path::Path, source: std::io::Error) -> Self {
+        Self {
+            writing: true,
+            ..Self::new(path, source)
+        }
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// The parse error, if the file was read but is not a valid PNG.
+    pub fn parse_error(&self) -> Option<&ParseError> {
+        self.source.downcast_ref()
+    }
+
+    /// The I/O error, if the file could not be read or written.
+    pub fn io_error(&self) -> Option<&std::io::Error> {
+        self.source.downcast_ref()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Display for FileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.io_error() {
+            Some(error) => {
+                let verb = if self.writing { "write" } else { "read" };
+                write!(f, "cannot {} {}: {}", verb, self.path.display(), error)
+            }
+            None => write!(f, "{}: {}", self.path.display(), self.source),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::error::Error for FileError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::str::FromStr;
+
+    #[test]
+    fn test_display() {
+        let error = ParseError::new(ParseErrorKind::InvalidSignature, 0);
+        assert_eq!(error.to_string(), "invalid PNG signature at offset 0");
+
+        let error = ParseError::new(
+            ParseErrorKind::CrcMismatch {
+                stored: 1,
+                computed: 2,
+            },
+            100,
+        )
+        .in_chunk(Some(3), Some(ChunkType::from_str("IDAT").unwrap()));
+        assert_eq!(
+            error.to_string(),
+            "CRC mismatch: stored 0x00000001, computed 0x00000002 at offset 100 (chunk 3, IDAT)"
+        );
+    }
+}
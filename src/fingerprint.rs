@@ -0,0 +1,235 @@
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::IDAT;
+use crate::image::{self, ImageHeader};
+use crate::png::Png;
+use alloc::vec::Vec;
+use sha2::{Digest, Sha256};
+
+/// Which parts of a file [`Png::fingerprint`] hashes and
+/// [`Png::semantic_eq`] compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// The type and data of every chunk, so only the CRCs, any trailing data
+    /// and the split of the IDAT stream are ignored.
+    AllChunks,
+    /// The type and data of critical chunks, ignoring all metadata.
+    CriticalChunks,
+    /// The image header, palette and unfiltered pixel rows, so the same image
+    /// compressed differently is treated as the same.
+    Pixels,
+}
+
+/// A chunk as the comparisons see it. A run of IDAT chunks is one part whose
+/// data is split across several slices.
+struct Part<'a> {
+    chunk_type: ChunkType,
+    data: Vec<&'a [u8]>,
+}
+
+impl Part<'_> {
+    fn len(&self) -> usize {
+        self.data.iter().map(|d| d.len()).sum()
+    }
+
+    fn bytes(&self) -> impl Iterator<Item = &u8> {
+        self.data.iter().flat_map(|d| d.iter())
+    }
+}
+
+/// Hashes the length of `data` followed by its bytes, so that parts cannot
+/// run into each other.
+fn hash_part(hasher: &mut Sha256, data: &[&[u8]]) {
+    let len: usize = data.iter().map(|d| d.len()).sum();
+    hasher.update((len as u64).to_be_bytes());
+    for slice in data {
+        hasher.update(slice);
+    }
+}
+
+impl Png {
+    /// The chunks in `scope`, which must not be [`Scope::Pixels`].
+    fn parts(&self, scope: Scope) -> Vec<Part<'_>> {
+        let mut parts: Vec<Part> = Vec::new();
+        for chunk in self.chunks() {
+            let chunk_type = *chunk.chunk_type();
+            if scope == Scope::CriticalChunks && !chunk_type.is_critical() {
+                continue;
+            }
+            match parts.last_mut() {
+                Some(last) if chunk_type == IDAT && last.chunk_type == IDAT => {
+                    last.data.push(chunk.data())
+                }
+                _ => parts.push(Part {
+                    chunk_type,
+                    data: Vec::from([chunk.data()]),
+                }),
+            }
+        }
+        parts
+    }
+
+    /// The IHDR data, the PLTE data (empty if there is none) and the
+    /// unfiltered pixel rows. Interlaced images are de-interlaced and their
+    /// IHDR is given the interlace method of a progressive one, so that only
+    /// the pixels count.
+    fn pixels(&self) -> crate::Result<(Vec<u8>, &[u8], Vec<u8>)> {
+        let mut ihdr = self
+            .chunk_by_type("IHDR")
+            .ok_or("no IHDR chunk")?
+            .data()
+            .to_vec();
+        let header = ImageHeader::parse(&ihdr)?;
+        ihdr[12] = 0;
+        let plte = self.chunk_by_type("PLTE").map_or(&[][..], |c| c.data());
+        let pixels = image::unfilter(&header, &self.image_data()?)?;
+        Ok((ihdr, plte, pixels))
+    }
+
+    /// A SHA-256 hash of the parts of the file chosen by `scope`.
+    pub fn fingerprint(&self, scope: Scope) -> crate::Result<[u8; 32]> {
+        let mut hasher = Sha256::new();
+        if scope == Scope::Pixels {
+            let (ihdr, plte, pixels) = self.pixels()?;
+            for part in [&ihdr, plte, &pixels] {
+                hash_part(&mut hasher, &[part]);
+            }
+        } else {
+            for part in self.parts(scope) {
+                hasher.update(part.chunk_type.bytes());
+                hash_part(&mut hasher, &part.data);
+            }
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// Whether the parts of the two files chosen by `scope` are the same.
+    /// Fails only for [`Scope::Pixels`] when either image cannot be decoded.
+    pub fn semantic_eq(&self, other: &Png, scope: Scope) -> crate::Result<bool> {
+        if scope == Scope::Pixels {
+            return Ok(self.pixels()? == other.pixels()?);
+        }
+        let (ours, theirs) = (self.parts(scope), other.parts(scope));
+        Ok(ours.len() == theirs.len()
+            && ours.iter().zip(theirs.iter()).all(|(a, b)| {
+                a.chunk_type == b.chunk_type && a.len() == b.len() && a.bytes().eq(b.bytes())
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::consts::{IHDR, PLTE};
+    use crate::png::{Placement, Removal};
+
+    fn ihdr() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(2u32.to_be_bytes());
+        data.extend(2u32.to_be_bytes());
+        data.extend([8, 0, 0, 0, 0]);
+        data
+    }
+
+    /// A 2x2 greyscale image, compressed at `level`, with `filter` on each row.
+    fn image(level: u8, filter: u8, comment: &str) -> Png {
+        let rows: &[u8] = match filter {
+            0 => &[0, 10, 20, 0, 30, 40],
+            _ => &[1, 10, 10, 1, 30, 10],
+        };
+        let idat = miniz_oxide::deflate::compress_to_vec_zlib(rows, level);
+        let (head, tail) = idat.split_at(idat.len() / 2);
+        PngBuilder::new(ihdr())
+            .unwrap()
+            .add_idat(head.to_vec())
+            .add_idat(tail.to_vec())
+            .add_text("Comment", comment)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_scopes() {
+        let png = image(6, 0, "one");
+        let relabelled = image(6, 0, "two");
+        let recompressed = image(1, 1, "one");
+        let all = |p: &Png| p.fingerprint(Scope::AllChunks).unwrap();
+        let critical = |p: &Png| p.fingerprint(Scope::CriticalChunks).unwrap();
+        let pixels = |p: &Png| p.fingerprint(Scope::Pixels).unwrap();
+
+        assert_ne!(all(&png), all(&relabelled));
+        assert_eq!(critical(&png), critical(&relabelled));
+        assert_ne!(critical(&png), critical(&recompressed));
+        assert_eq!(pixels(&png), pixels(&recompressed));
+
+        let mut merged = png.clone();
+        merged.canonicalize().unwrap();
+        assert_eq!(pixels(&merged), pixels(&png));
+        let mut single = png.clone();
+        let data = single.image_data().unwrap();
+        let idat = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+        single.remove_chunk("IDAT", Removal::All).unwrap();
+        single
+            .insert_chunk(Chunk::new(IDAT, idat), Placement::Index(1))
+            .unwrap();
+        assert_eq!(all(&single), all(&png));
+    }
+
+    #[test]
+    fn test_semantic_eq() {
+        let png = image(6, 0, "one");
+        let relabelled = image(6, 0, "two");
+        let recompressed = image(1, 1, "one");
+        let eq = |a: &Png, b: &Png, scope| a.semantic_eq(b, scope).unwrap();
+
+        assert!(eq(&png, &png.clone(), Scope::AllChunks));
+        assert!(!eq(&png, &relabelled, Scope::AllChunks));
+        assert!(eq(&png, &relabelled, Scope::CriticalChunks));
+        assert!(!eq(&png, &recompressed, Scope::CriticalChunks));
+        assert!(eq(&png, &recompressed, Scope::Pixels));
+
+        let joined: Vec<u8> = png
+            .chunks_of_type("IDAT")
+            .flat_map(|c| c.data().iter().copied())
+            .collect();
+        let mut single = png.clone();
+        single.remove_chunk("IDAT", Removal::All).unwrap();
+        single
+            .insert_chunk(Chunk::new(IDAT, joined), Placement::Index(1))
+            .unwrap();
+        assert!(eq(&png, &single, Scope::AllChunks));
+
+        let empty = Png::from_chunks(Vec::new());
+        assert!(!eq(&png, &empty, Scope::CriticalChunks));
+        assert!(png.semantic_eq(&empty, Scope::Pixels).is_err());
+    }
+
+    #[test]
+    fn test_interlaced_pixels() {
+        let mut header = ihdr();
+        header[12] = 1;
+        let rows = [0, 10, 0, 20, 0, 30, 40];
+        let idat = miniz_oxide::deflate::compress_to_vec_zlib(&rows, 6);
+        let interlaced = PngBuilder::new(header)
+            .unwrap()
+            .add_idat(idat)
+            .build()
+            .unwrap();
+        let png = image(6, 0, "one");
+        assert_eq!(
+            interlaced.fingerprint(Scope::Pixels).unwrap(),
+            png.fingerprint(Scope::Pixels).unwrap()
+        );
+        assert!(interlaced.semantic_eq(&png, Scope::Pixels).unwrap());
+    }
+
+    #[test]
+    fn test_pixels_needs_valid_image() {
+        let png = Png::from_chunks(vec![Chunk::new(PLTE, b"palette".to_vec())]);
+        assert!(png.fingerprint(Scope::Pixels).is_err());
+        assert!(png.fingerprint(Scope::AllChunks).is_ok());
+        let png = Png::from_chunks(vec![Chunk::new(IHDR, ihdr())]);
+        assert!(png.fingerprint(Scope::Pixels).is_err());
+    }
+}
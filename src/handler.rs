@@ -0,0 +1,241 @@
+use crate::ancillary::{Histogram, ImageOffset, PhysicalScale, PixelCalibration, SuggestedPalette};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::{HIST, OFFS, PCAL, SCAL, SPLT};
+use crate::png::Png;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt::Display;
+
+/// Teaches pngme about one chunk type, typically a proprietary one, so it
+/// can be decoded, described and validated without changes to the crate.
+pub trait ChunkHandler {
+    /// The chunk type handled.
+    fn chunk_type(&self) -> ChunkType;
+
+    /// Decodes the chunk's data, failing if it is invalid. Use
+    /// [`Registry::parse`] to get the value back with its concrete type.
+    fn parse(&self, data: &[u8]) -> crate::Result<Box<dyn Any>>;
+
+    /// The chunk's data as one line of text, as `print` shows it.
+    fn describe(&self, data: &[u8]) -> crate::Result<String>;
+}
+
+/// A handler built from a decoding function whose output is displayed as
+/// its description.
+struct Decoder<T> {
+    chunk_type: ChunkType,
+    decode: fn(&[u8]) -> crate::Result<T>,
+}
+
+impl<T: Display + 'static> ChunkHandler for Decoder<T> {
+    fn chunk_type(&self) -> ChunkType {
+        self.chunk_type
+    }
+
+    fn parse(&self, data: &[u8]) -> crate::Result<Box<dyn Any>> {
+        Ok(Box::new((self.decode)(data)?))
+    }
+
+    fn describe(&self, data: &[u8]) -> crate::Result<String> {
+        (self.decode)(data).map(|value| value.to_string())
+    }
+}
+
+/// The handlers known for each chunk type, at most one per type.
+#[derive(Default)]
+pub struct Registry {
+    handlers: Vec<Box<dyn ChunkHandler>>,
+}
+
+impl Registry {
+    /// A registry with no handlers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with handlers for the ancillary chunk types the library
+    /// decodes: sPLT, hIST, oFFs, sCAL and pCAL.
+    pub fn standard() -> Self {
+        let mut registry = Self::new();
+        registry.register(Decoder {
+            chunk_type: SPLT,
+            decode: SuggestedPalette::decode,
+        });
+        registry.register(Decoder {
+            chunk_type: HIST,
+            decode: Histogram::decode,
+        });
+        registry.register(Decoder {
+            chunk_type: OFFS,
+            decode: ImageOffset::decode,
+        });
+        registry.register(Decoder {
+            chunk_type: SCAL,
+            decode: PhysicalScale::decode,
+        });
+        registry.register(Decoder {
+            chunk_type: PCAL,
+            decode: PixelCalibration::decode,
+        });
+        registry
+    }
+
+    /// Adds `handler`, returning the one it replaces for the same type.
+    pub fn register(
+        &mut self,
+        handler: impl ChunkHandler + 'static,
+    ) -> Option<Box<dyn ChunkHandler>> {
+        let handler = Box::new(handler);
+        let chunk_type = handler.chunk_type();
+        match self
+            .handlers
+            .iter_mut()
+            .find(|h| h.chunk_type() == chunk_type)
+        {
+            Some(existing) => Some(core::mem::replace(existing, handler)),
+            None => {
+                self.handlers.push(handler);
+                None
+            }
+        }
+    }
+
+    /// The handler for `chunk_type`, if one is registered.
+    pub fn get(&self, chunk_type: &ChunkType) -> Option<&dyn ChunkHandler> {
+        self.handlers
+            .iter()
+            .find(|h| h.chunk_type() == *chunk_type)
+            .map(|h| h.as_ref())
+    }
+
+    /// The registered chunk types, in registration order.
+    pub fn chunk_types(&self) -> impl Iterator<Item = ChunkType> + '_ {
+        self.handlers.iter().map(|h| h.chunk_type())
+    }
+
+    /// Decodes `chunk` as a `T`, or `None` if no handler is registered for
+    /// its type. Fails if the data is invalid or the handler produces some
+    /// other type.
+    pub fn parse<T: 'static>(&self, chunk: &Chunk) -> Option<crate::Result<T>> {
+        let handler = self.get(chunk.chunk_type())?;
+        Some(handler.parse(chunk.data()).and_then(|value| {
+            value.downcast::<T>().map(|value| *value).map_err(|_| {
+                format!(
+                    "the {} handler does not produce {}",
+                    chunk.chunk_type(),
+                    core::any::type_name::<T>()
+                )
+                .into()
+            })
+        }))
+    }
+
+    /// Describes the data of a chunk of `chunk_type`, or `None` if no
+    /// handler is registered for it.
+    pub fn describe(&self, chunk_type: &ChunkType, data: &[u8]) -> Option<crate::Result<String>> {
+        self.get(chunk_type).map(|h| h.describe(data))
+    }
+}
+
+impl Png {
+    /// Decodes every chunk of `chunk_type` with its handler in `registry`,
+    /// in file order. Empty if no handler is registered for the type.
+    pub fn parse_with<T: 'static>(
+        &self,
+        registry: &Registry,
+        chunk_type: &ChunkType,
+    ) -> Vec<crate::Result<T>> {
+        self.chunks()
+            .filter(|c| c.chunk_type() == chunk_type)
+            .filter_map(|c| registry.parse(c))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::consts::{IDAT, IEND, IHDR};
+    use crate::report::Severity;
+    use core::str::FromStr;
+
+    /// A made-up private chunk holding a big-endian version number.
+    struct Version;
+
+    impl ChunkHandler for Version {
+        fn chunk_type(&self) -> ChunkType {
+            ChunkType::from_str("veRs").unwrap()
+        }
+
+        fn parse(&self, data: &[u8]) -> crate::Result<Box<dyn Any>> {
+            let bytes: [u8; 2] = data.try_into().map_err(|_| "version must be 2 bytes")?;
+            Ok(Box::new(u16::from_be_bytes(bytes)))
+        }
+
+        fn describe(&self, data: &[u8]) -> crate::Result<String> {
+            let version = self.parse(data)?.downcast::<u16>().unwrap();
+            Ok(format!("version {}", version))
+        }
+    }
+
+    fn png(version: &[u8]) -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(IHDR, vec![0; 13]),
+            Chunk::new(Version.chunk_type(), version.to_vec()),
+            Chunk::new(IDAT, Vec::new()),
+            Chunk::new(IEND, Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::standard();
+        assert!(registry.get(&HIST).is_some());
+        assert!(registry.register(Version).is_none());
+        assert!(registry.register(Version).is_some());
+        assert_eq!(registry.chunk_types().count(), 6);
+
+        let png = png(&[0, 3]);
+        let versions: Vec<u16> = png
+            .parse_with(&registry, &Version.chunk_type())
+            .into_iter()
+            .map(|v| v.unwrap())
+            .collect();
+        assert_eq!(versions, [3]);
+        let chunk = png.chunk_by_type("veRs").unwrap();
+        assert!(registry.parse::<u32>(chunk).unwrap().is_err());
+        assert!(Registry::new().parse::<u16>(chunk).is_none());
+        assert_eq!(
+            registry
+                .describe(chunk.chunk_type(), chunk.data())
+                .unwrap()
+                .unwrap(),
+            "version 3"
+        );
+        assert!(registry.describe(&IEND, &[]).is_none());
+
+        let hist = Chunk::new(HIST, vec![0, 1, 0, 2]);
+        let histogram: Histogram = registry.parse(&hist).unwrap().unwrap();
+        assert_eq!(histogram.frequencies, [1, 2]);
+    }
+
+    #[test]
+    fn test_check_with_registry() {
+        let mut registry = Registry::new();
+        registry.register(Version);
+        let bytes = png(&[1]).as_bytes();
+        let report = Png::check_all_with_registry(&bytes, &registry, &mut |_, _| {});
+        let finding = report
+            .findings()
+            .iter()
+            .find(|f| f.severity == Severity::Error)
+            .unwrap();
+        assert_eq!(finding.chunk_index, Some(1));
+        assert!(finding.message.contains("version must be 2 bytes"));
+        assert!(!Png::check_all(&bytes).has_errors());
+    }
+}
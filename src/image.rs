@@ -0,0 +1,299 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The fields of an IHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub interlaced: bool,
+}
+
+impl ImageHeader {
+    /// Parses the 13 bytes of IHDR data, checking the bit depth is allowed
+    /// for the colour type.
+    pub fn parse(data: &[u8]) -> crate::Result<Self> {
+        let data: &[u8; 13] = data.try_into().map_err(|_| "IHDR data must be 13 bytes")?;
+        let header = Self {
+            width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            bit_depth: data[8],
+            color_type: data[9],
+            interlaced: match data[12] {
+                0 => false,
+                1 => true,
+                _ => return Err("unknown interlace method".into()),
+            },
+        };
+        let depths: &[u8] = match header.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => return Err("unknown colour type".into()),
+        };
+        if !depths.contains(&header.bit_depth) {
+            return Err("bit depth not allowed for the colour type".into());
+        }
+        Ok(header)
+    }
+
+    /// The colour type as the specification names it.
+    pub fn color_type_name(&self) -> &'static str {
+        match self.color_type {
+            0 => "greyscale",
+            2 => "truecolour",
+            3 => "indexed-colour",
+            4 => "greyscale with alpha",
+            _ => "truecolour with alpha",
+        }
+    }
+
+    /// Samples per pixel.
+    pub fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    /// Bytes in one scanline, not counting the filter byte.
+    pub fn row_len(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Starting column, starting row, column step and row step of each of the
+/// seven Adam7 passes.
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Reverses the scanline filters on `data`, the decompressed IDAT stream,
+/// returning the rows back to back without their filter bytes. Adam7 images
+/// are de-interlaced, so the result is laid out the same either way.
+pub fn unfilter(header: &ImageHeader, data: &[u8]) -> crate::Result<Vec<u8>> {
+    let row_len = header.row_len();
+    let height = header.height as usize;
+    if !header.interlaced {
+        return unfilter_rows(data, row_len, height, header.bits_per_pixel());
+    }
+
+    let bits = header.bits_per_pixel();
+    let width = header.width as usize;
+    let too_short = || "image data is too short".into();
+    // Size every pass before allocating, so a header claiming a huge image
+    // over a few bytes of data fails rather than overflowing or exhausting
+    // memory.
+    let mut passes = Vec::new();
+    let mut total = 0usize;
+    for (x0, y0, dx, dy) in ADAM7 {
+        let pass_width = width.saturating_sub(x0).div_ceil(dx);
+        let pass_height = height.saturating_sub(y0).div_ceil(dy);
+        // Empty passes have no rows at all, not even filter bytes.
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+        let pass_row_len = pass_width
+            .checked_mul(bits)
+            .ok_or_else(too_short)?
+            .div_ceil(8);
+        let len = (pass_row_len + 1)
+            .checked_mul(pass_height)
+            .ok_or_else(too_short)?;
+        total = total.checked_add(len).ok_or_else(too_short)?;
+        passes.push((x0, y0, dx, dy, pass_width, pass_height, pass_row_len, len));
+    }
+    if data.len() < total {
+        return Err(too_short());
+    }
+
+    let mut pixels = vec![0u8; row_len.checked_mul(height).ok_or_else(too_short)?];
+    let mut remain = data;
+    for (x0, y0, dx, dy, pass_width, pass_height, pass_row_len, len) in passes {
+        let pass = unfilter_rows(&remain[..len], pass_row_len, pass_height, bits)?;
+        remain = &remain[len..];
+        for py in 0..pass_height {
+            let src = &pass[py * pass_row_len..(py + 1) * pass_row_len];
+            let y = y0 + py * dy;
+            let dst = &mut pixels[y * row_len..(y + 1) * row_len];
+            for px in 0..pass_width {
+                copy_pixel(src, px * bits, dst, (x0 + px * dx) * bits, bits);
+            }
+        }
+    }
+    Ok(pixels)
+}
+
+/// Copies the `bits`-bit pixel starting at bit `from` of `src` to bit `to`
+/// of `dst`. Pixels narrower than a byte are packed most significant first.
+fn copy_pixel(src: &[u8], from: usize, dst: &mut [u8], to: usize, bits: usize) {
+    if bits >= 8 {
+        let (from, to, len) = (from / 8, to / 8, bits / 8);
+        dst[to..to + len].copy_from_slice(&src[from..from + len]);
+        return;
+    }
+    let mask = (1u8 << bits) - 1;
+    let value = (src[from / 8] >> (8 - bits - from % 8)) & mask;
+    let shift = 8 - bits - to % 8;
+    dst[to / 8] = (dst[to / 8] & !(mask << shift)) | (value << shift);
+}
+
+/// Unfilters `height` rows of `row_len` bytes, each led by its filter byte.
+fn unfilter_rows(
+    data: &[u8],
+    row_len: usize,
+    height: usize,
+    bits: usize,
+) -> crate::Result<Vec<u8>> {
+    if height
+        .checked_mul(row_len + 1)
+        .is_none_or(|len| data.len() < len)
+    {
+        return Err("image data is too short".into());
+    }
+    let bpp = bits.div_ceil(8);
+    let mut pixels = vec![0u8; row_len * height];
+    let mut previous = vec![0u8; row_len];
+    for (y, line) in data.chunks_exact(row_len + 1).take(height).enumerate() {
+        let (filter, line) = (line[0], &line[1..]);
+        let row = &mut pixels[y * row_len..(y + 1) * row_len];
+        for x in 0..row_len {
+            let a = if x >= bpp { row[x - bpp] } else { 0 };
+            let b = previous[x];
+            let c = if x >= bpp { previous[x - bpp] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err("unknown filter type".into()),
+            };
+            row[x] = line[x].wrapping_add(predicted);
+        }
+        previous.copy_from_slice(row);
+    }
+    Ok(pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(width.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        data.extend([bit_depth, color_type, 0, 0, interlace]);
+        data
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let header = ImageHeader::parse(&ihdr(3, 2, 8, 2, 0)).unwrap();
+        assert_eq!((header.width, header.height), (3, 2));
+        assert_eq!(header.channels(), 3);
+        assert_eq!(header.row_len(), 9);
+        assert_eq!(
+            ImageHeader::parse(&ihdr(9, 1, 1, 0, 0)).unwrap().row_len(),
+            2
+        );
+
+        assert!(ImageHeader::parse(b"short").is_err());
+        assert!(ImageHeader::parse(&ihdr(1, 1, 16, 3, 0)).is_err());
+        assert!(ImageHeader::parse(&ihdr(1, 1, 8, 5, 0)).is_err());
+        assert!(ImageHeader::parse(&ihdr(1, 1, 8, 0, 2)).is_err());
+    }
+
+    #[test]
+    fn test_unfilter() {
+        let header = ImageHeader::parse(&ihdr(2, 5, 8, 0, 0)).unwrap();
+        #[rustfmt::skip]
+        let data = [
+            0, 10, 20,
+            1, 5, 5,
+            2, 1, 1,
+            3, 2, 2,
+            4, 1, 1,
+        ];
+        #[rustfmt::skip]
+        let expected = [
+            10, 20,
+            5, 10,
+            6, 11,
+            5, 10,
+            6, 11,
+        ];
+        assert_eq!(unfilter(&header, &data).unwrap(), expected);
+        assert!(unfilter(&header, &data[..10]).is_err());
+
+        let mut bad = data;
+        bad[3] = 5;
+        assert!(unfilter(&header, &bad).is_err());
+    }
+
+    #[test]
+    fn test_deinterlace() {
+        let header = ImageHeader::parse(&ihdr(3, 3, 8, 0, 1)).unwrap();
+        // Passes 2 and 3 are empty for a 3x3 image.
+        #[rustfmt::skip]
+        let data = [
+            0, 0,
+            0, 2,
+            0, 6, 8,
+            0, 1, 0, 7,
+            0, 3, 4, 5,
+        ];
+        let expected: Vec<u8> = (0..9).collect();
+        assert_eq!(unfilter(&header, &data).unwrap(), expected);
+        assert!(unfilter(&header, &data[..14]).is_err());
+
+        let header = ImageHeader::parse(&ihdr(3, 3, 1, 0, 1)).unwrap();
+        #[rustfmt::skip]
+        let data = [
+            0, 0x80,
+            0, 0x80,
+            0, 0xc0,
+            0, 0x00, 0, 0x80,
+            0, 0x40,
+        ];
+        assert_eq!(unfilter(&header, &data).unwrap(), [0xa0, 0x40, 0xe0]);
+
+        // A huge claimed size over a little data fails without allocating.
+        let max = (1 << 31) - 1;
+        for interlace in [0, 1] {
+            let header = ImageHeader::parse(&ihdr(max, max, 16, 6, interlace)).unwrap();
+            assert!(unfilter(&header, &[0; 64]).is_err());
+        }
+    }
+}
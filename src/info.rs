@@ -0,0 +1,171 @@
+use crate::ancillary::{ImageOffset, PhysicalScale, PixelCalibration};
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::{ACTL, ICCP, IDAT, IHDR, OFFS, PCAL, PLTE, SCAL, TRNS};
+use crate::image::ImageHeader;
+use crate::png::{Png, PngRef};
+use crate::text::TextFormat;
+
+/// The image an APNG animates, as its acTL chunk describes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Animation {
+    pub frames: u32,
+    /// Times the animation plays; zero means forever.
+    pub plays: u32,
+}
+
+/// An overview of a file: the header fields and what the other chunks add.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Info {
+    pub header: ImageHeader,
+    /// Entries in PLTE, if there is one.
+    pub palette_len: Option<usize>,
+    pub idat_chunks: usize,
+    /// Bytes of compressed image data across every IDAT.
+    pub idat_bytes: u64,
+    /// Whether pixels can be transparent, through an alpha channel or tRNS.
+    pub transparency: bool,
+    pub icc_profile: bool,
+    pub text_chunks: usize,
+    pub animation: Option<Animation>,
+    /// The oFFs, sCAL and pCAL extension chunks, if present and readable.
+    pub offset: Option<ImageOffset>,
+    pub scale: Option<PhysicalScale>,
+    pub calibration: Option<PixelCalibration>,
+    /// Bytes taken by ancillary chunks, headers and CRCs included.
+    pub ancillary_bytes: u64,
+}
+
+impl Info {
+    fn of<'c>(chunks: impl Iterator<Item = (&'c ChunkType, &'c [u8])>) -> crate::Result<Self> {
+        let mut header = None;
+        let mut palette_len = None;
+        let (mut idat_chunks, mut idat_bytes) = (0, 0);
+        let (mut trns, mut icc_profile) = (false, false);
+        let mut text_chunks = 0;
+        let mut animation = None;
+        let (mut offset, mut scale, mut calibration) = (None, None, None);
+        let mut ancillary_bytes = 0;
+        for (chunk_type, data) in chunks {
+            match *chunk_type {
+                IHDR if header.is_none() => header = Some(ImageHeader::parse(data)?),
+                PLTE => palette_len = Some(data.len() / 3),
+                IDAT => {
+                    idat_chunks += 1;
+                    idat_bytes += data.len() as u64;
+                }
+                TRNS => trns = true,
+                ICCP => icc_profile = true,
+                ACTL if data.len() == 8 => {
+                    animation = Some(Animation {
+                        frames: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+                        plays: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+                    })
+                }
+                OFFS => offset = ImageOffset::decode(data).ok(),
+                SCAL => scale = PhysicalScale::decode(data).ok(),
+                PCAL => calibration = PixelCalibration::decode(data).ok(),
+                _ => {}
+            }
+            if TextFormat::of(chunk_type).is_some() {
+                text_chunks += 1;
+            }
+            if !chunk_type.is_critical() {
+                ancillary_bytes += data.len() as u64 + 12;
+            }
+        }
+
+        let header = header.ok_or("no IHDR chunk")?;
+        Ok(Info {
+            header,
+            palette_len,
+            idat_chunks,
+            idat_bytes,
+            transparency: trns || matches!(header.color_type, 4 | 6),
+            icc_profile,
+            text_chunks,
+            animation,
+            offset,
+            scale,
+            calibration,
+            ancillary_bytes,
+        })
+    }
+}
+
+impl Png {
+    /// Summarizes the image from its header and the presence of other
+    /// chunks, without decompressing anything.
+    pub fn info(&self) -> crate::Result<Info> {
+        Info::of(self.chunks().map(|c| (c.chunk_type(), c.data())))
+    }
+}
+
+impl PngRef<'_> {
+    /// Like [`Png::info`].
+    pub fn info(&self) -> crate::Result<Info> {
+        Info::of(self.chunks().iter().map(|c| (c.chunk_type(), c.data())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::consts::{IEND, TEXT};
+
+    fn ihdr(color_type: u8) -> Chunk {
+        let mut data = Vec::new();
+        data.extend(640u32.to_be_bytes());
+        data.extend(480u32.to_be_bytes());
+        data.extend([8, color_type, 0, 0, 1]);
+        Chunk::new(IHDR, data)
+    }
+
+    #[test]
+    fn test_info() {
+        let png = Png::from_chunks(vec![
+            ihdr(3),
+            Chunk::new(PLTE, vec![0; 3 * 16]),
+            Chunk::new(TRNS, vec![0; 4]),
+            Chunk::new(TEXT, b"Title\0Test".to_vec()),
+            Chunk::new(IDAT, vec![0; 100]),
+            Chunk::new(IDAT, vec![0; 50]),
+            Chunk::new(IEND, Vec::new()),
+        ]);
+        let info = png.info().unwrap();
+        assert_eq!((info.header.width, info.header.height), (640, 480));
+        assert!(info.header.interlaced);
+        assert_eq!(info.palette_len, Some(16));
+        assert_eq!((info.idat_chunks, info.idat_bytes), (2, 150));
+        assert!(info.transparency);
+        assert!(!info.icc_profile);
+        assert_eq!(info.text_chunks, 1);
+        assert_eq!(info.animation, None);
+        assert_eq!(info.offset, None);
+        assert_eq!(info.ancillary_bytes, 16 + 22);
+
+        let bytes = png.as_bytes();
+        let png = PngRef::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.info().unwrap(), info);
+    }
+
+    #[test]
+    fn test_animation_and_alpha() {
+        let mut actl = Vec::new();
+        actl.extend(12u32.to_be_bytes());
+        actl.extend(0u32.to_be_bytes());
+        let png = Png::from_chunks(vec![ihdr(6), Chunk::new(ACTL, actl)]);
+        let info = png.info().unwrap();
+        assert_eq!(
+            info.animation,
+            Some(Animation {
+                frames: 12,
+                plays: 0
+            })
+        );
+        assert!(info.transparency);
+        assert_eq!(info.palette_len, None);
+
+        assert!(Png::from_chunks(Vec::new()).info().is_err());
+    }
+}
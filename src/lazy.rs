@@ -0,0 +1,566 @@
+use crate::chunk::{Checksum, Chunk};
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::IEND;
+use crate::error::{ParseError, ParseErrorKind};
+use crate::options::ParseOptions;
+use crate::png::{self, Placement, Png, Removal};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Bytes of chunk data [`LazyPng::write_to`] holds in memory at once.
+const COPY_BUFFER: usize = 64 * 1024;
+
+/// Where a chunk lives in its source, recorded without reading its data.
+pub struct ChunkHeader {
+    offset: u64,
+    length: u32,
+    typ: ChunkType,
+    crc: u32,
+}
+
+/// One chunk of the file [`LazyPng::write_to`] produces.
+enum Entry {
+    /// The chunk with this header index, copied from the source.
+    Source(usize),
+    /// The chunk with this index among those found after IEND.
+    Trailing(usize),
+    /// A chunk added by [`LazyPng::insert_chunk`].
+    New(Chunk),
+}
+
+/// A PNG whose chunk headers have been read up front and whose chunk data is
+/// only read from the source when asked for.
+///
+/// Chunks can be inserted and removed without loading anything; the edits
+/// only show in what [`LazyPng::write_to`] writes, while [`LazyPng::headers`]
+/// and [`LazyPng::load`] still describe the source.
+pub struct LazyPng<R> {
+    source: R,
+    headers: Vec<ChunkHeader>,
+    layout: Vec<Entry>,
+    /// Well-formed chunks after IEND, such as those placed with
+    /// [`Placement::AfterIend`], so they can be removed like any other.
+    trailing_chunks: Vec<ChunkHeader>,
+    /// Offset and length of the bytes after IEND and its trailing chunks.
+    trailing: (u64, u64),
+    verify_crc: bool,
+}
+
+impl ChunkHeader {
+    /// Offset of the chunk's length field from the start of the source.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.typ
+    }
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+    fn data_offset(&self) -> u64 {
+        self.offset + 8
+    }
+}
+
+impl<R: Read + Seek> LazyPng<R> {
+    pub fn new(source: R) -> crate::Result<Self> {
+        Self::with_options(source, &ParseOptions::default())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn with_options(mut source: R, options: &ParseOptions) -> crate::Result<Self> {
+        let start = source.stream_position()?;
+        let end = source.seek(SeekFrom::End(0))?;
+        options
+            .check_file_size(end - start)
+            .map_err(|kind| ParseError::new(kind, start))?;
+        source.seek(SeekFrom::Start(start))?;
+
+        let mut header = [0u8; 8];
+        if source.read_exact(&mut header).is_err() || header != Png::STANDARD_HEADER {
+            return Err(ParseError::new(ParseErrorKind::InvalidSignature, start).into());
+        }
+
+        let mut headers: Vec<ChunkHeader> = Vec::new();
+        let mut offset = start + header.len() as u64;
+        while offset < end && headers.last().is_none_or(|h| h.typ != IEND) {
+            let index = Some(headers.len());
+            let error =
+                |kind, at: u64, typ| ParseError::new(kind, offset + at).in_chunk(index, typ);
+            options
+                .check_total_chunks(headers.len() + 1)
+                .map_err(|kind| error(kind, 0, None))?;
+
+            let mut prefix = [0u8; 8];
+            source
+                .read_exact(&mut prefix)
+                .map_err(|_| error(ParseErrorKind::Truncated, 0, None))?;
+            let mut length = [0u8; 4];
+            length.copy_from_slice(&prefix[..4]);
+            let length = u32::from_be_bytes(length);
+            let mut typ = [0u8; 4];
+            typ.copy_from_slice(&prefix[4..]);
+            let typ = ChunkType::try_from(typ)
+                .map_err(|_| error(ParseErrorKind::InvalidChunkType(typ), 4, None))?;
+            options
+                .check_chunk_len(length)
+                .map_err(|kind| error(kind, 0, Some(typ)))?;
+            if offset + 12 + length as u64 > end {
+                return Err(error(ParseErrorKind::Truncated, 0, Some(typ)).into());
+            }
+
+            source.seek(SeekFrom::Current(length as i64))?;
+            let mut crc = [0u8; 4];
+            source.read_exact(&mut crc)?;
+
+            event!(trace, offset, chunk_type = %typ, length, "read chunk header");
+            headers.push(ChunkHeader {
+                offset,
+                length,
+                typ,
+                crc: u32::from_be_bytes(crc),
+            });
+            offset += 12 + length as u64;
+        }
+
+        event!(debug, chunks = headers.len(), "read chunk headers");
+        options
+            .check_trailing_data(end - offset)
+            .map_err(|kind| ParseError::new(kind, offset))?;
+        let mut trailing_chunks = Vec::new();
+        while let Some(header) = read_trailing_chunk(&mut source, offset, end)? {
+            offset += 12 + header.length as u64;
+            trailing_chunks.push(header);
+        }
+        let types: Vec<_> = headers.iter().map(|h| &h.typ).collect();
+        options.check_structure(&types).map_err(|(index, kind)| {
+            let header = headers.get(index);
+            let offset = header.map_or(end, |h| h.offset);
+            ParseError::new(kind, offset).in_chunk(Some(index), header.map(|h| h.typ))
+        })?;
+        Ok(Self {
+            source,
+            layout: (0..headers.len())
+                .map(Entry::Source)
+                .chain((0..trailing_chunks.len()).map(Entry::Trailing))
+                .collect(),
+            headers,
+            trailing_chunks,
+            trailing: (offset, end - offset),
+            verify_crc: options.verify_crc,
+        })
+    }
+
+    pub fn headers(&self) -> &[ChunkHeader] {
+        &self.headers
+    }
+
+    /// Reads the data of the chunk at `index` and checks it against the
+    /// recorded CRC.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(self)))]
+    pub fn load(&mut self, index: usize) -> crate::Result<Chunk> {
+        let header = self.headers.get(index).ok_or("chunk index out of range")?;
+        let mut data = vec![0u8; header.length as usize];
+        self.source.seek(SeekFrom::Start(header.data_offset()))?;
+        self.source.read_exact(&mut data)?;
+
+        let chunk = Chunk::new(header.typ, data);
+        if self.verify_crc && chunk.crc() != header.crc {
+            event!(
+                debug,
+                stored = header.crc,
+                computed = chunk.crc(),
+                "CRC mismatch"
+            );
+            let kind = ParseErrorKind::CrcMismatch {
+                stored: header.crc,
+                computed: chunk.crc(),
+            };
+            let offset = header.data_offset() + header.length as u64;
+            return Err(ParseError::new(kind, offset)
+                .in_chunk(Some(index), Some(header.typ))
+                .into());
+        }
+        Ok(chunk)
+    }
+
+    /// Reads every chunk, producing a fully loaded [`Png`].
+    pub fn into_png(mut self) -> crate::Result<Png> {
+        let chunks = (0..self.headers.len())
+            .map(|i| self.load(i))
+            .collect::<crate::Result<Vec<_>>>()?;
+        let offset = self
+            .trailing_chunks
+            .first()
+            .map_or(self.trailing.0, |h| h.offset);
+        let length = self.trailing.0 + self.trailing.1 - offset;
+        let mut trailing = vec![0u8; length as usize];
+        self.source.seek(SeekFrom::Start(offset))?;
+        self.source.read_exact(&mut trailing)?;
+        let mut png = Png::from_chunks(chunks);
+        png.set_trailing_data(trailing);
+        Ok(png)
+    }
+
+    /// Number of bytes after IEND, which [`LazyPng::write_to`] copies
+    /// through unless they are dropped with [`LazyPng::trim`]. Trailing
+    /// chunks already removed are not counted.
+    pub fn trailing_len(&self) -> u64 {
+        let chunks: u64 = self
+            .layout
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Trailing(i) => Some(12 + self.trailing_chunks[*i].length as u64),
+                _ => None,
+            })
+            .sum();
+        chunks + self.trailing.1
+    }
+
+    /// Drops the bytes after IEND from the output, trailing chunks included,
+    /// returning how many there were.
+    pub fn trim(&mut self) -> u64 {
+        let trimmed = self.trailing_len();
+        self.layout.retain(|e| !matches!(e, Entry::Trailing(_)));
+        self.trailing.1 = 0;
+        trimmed
+    }
+
+    fn entry_type<'a>(&'a self, entry: &'a Entry) -> &'a ChunkType {
+        match entry {
+            Entry::Source(i) => &self.headers[*i].typ,
+            Entry::Trailing(i) => &self.trailing_chunks[*i].typ,
+            Entry::New(chunk) => chunk.chunk_type(),
+        }
+    }
+
+    fn types(&self) -> Vec<&ChunkType> {
+        self.layout.iter().map(|e| self.entry_type(e)).collect()
+    }
+
+    /// Inserts `chunk` at `placement` under the same ordering rules as
+    /// [`Png::insert_chunk`].
+    pub fn insert_chunk(&mut self, chunk: Chunk, placement: Placement) -> crate::Result<()> {
+        let index = png::insertion_index(&self.types(), chunk.chunk_type(), placement)?;
+        self.layout.insert(index, Entry::New(chunk));
+        Ok(())
+    }
+
+    /// Removes the chunks [`Png::remove_chunk`] would, returning how many
+    /// there were. Chunks found after IEND count too. Their data is never
+    /// read.
+    pub fn remove_chunk(&mut self, chunk_type: &str, which: Removal) -> crate::Result<usize> {
+        let indices = png::removal_indices(&self.types(), chunk_type, which)?;
+        for &i in indices.iter().rev() {
+            self.layout.remove(i);
+        }
+        Ok(indices.len())
+    }
+
+    /// Size in bytes of the file [`LazyPng::write_to`] writes.
+    pub fn output_len(&self) -> u64 {
+        let data: u64 = self
+            .layout
+            .iter()
+            .map(|entry| match entry {
+                Entry::Source(i) => self.headers[*i].length as u64,
+                Entry::Trailing(i) => self.trailing_chunks[*i].length as u64,
+                Entry::New(chunk) => chunk.length() as u64,
+            })
+            .sum();
+        (Png::STANDARD_HEADER.len() + 12 * self.layout.len()) as u64 + data + self.trailing.1
+    }
+
+    /// Writes the edited file to `out` in a single pass over the source,
+    /// copying its chunks through a small buffer rather than loading them,
+    /// so even huge IDAT streams are never held in memory. CRCs are checked
+    /// on the way through, as [`LazyPng::load`] would.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn write_to<W: Write>(&mut self, mut out: W) -> crate::Result<()> {
+        out.write_all(&Png::STANDARD_HEADER)?;
+        let mut buffer = vec![0u8; COPY_BUFFER];
+        for entry in self.layout.iter() {
+            let (header, index) = match entry {
+                Entry::Source(index) => (&self.headers[*index], Some(*index)),
+                Entry::Trailing(index) => (&self.trailing_chunks[*index], None),
+                Entry::New(chunk) => {
+                    out.write_all(&chunk.as_bytes())?;
+                    continue;
+                }
+            };
+            self.source.seek(SeekFrom::Start(header.offset))?;
+            let mut prefix = [0u8; 8];
+            self.source.read_exact(&mut prefix)?;
+            out.write_all(&prefix)?;
+
+            let mut checksum = Checksum::new(&header.typ);
+            let mut remain = header.length as usize;
+            while remain > 0 {
+                let piece = &mut buffer[..remain.min(COPY_BUFFER)];
+                self.source.read_exact(piece)?;
+                checksum.update(piece);
+                out.write_all(piece)?;
+                remain -= piece.len();
+            }
+            let computed = checksum.finalize();
+            if self.verify_crc && computed != header.crc {
+                let kind = ParseErrorKind::CrcMismatch {
+                    stored: header.crc,
+                    computed,
+                };
+                let offset = header.data_offset() + header.length as u64;
+                return Err(ParseError::new(kind, offset)
+                    .in_chunk(index, Some(header.typ))
+                    .into());
+            }
+            out.write_all(&header.crc.to_be_bytes())?;
+        }
+        let (offset, length) = self.trailing;
+        self.source.seek(SeekFrom::Start(offset))?;
+        let copied = std::io::copy(&mut (&mut self.source).take(length), &mut out)?;
+        if copied != length {
+            return Err(ParseError::new(ParseErrorKind::Truncated, offset + copied).into());
+        }
+        event!(debug, chunks = self.layout.len(), "streamed chunks");
+        Ok(())
+    }
+}
+
+/// Reads the header of the chunk at `offset` in the bytes after IEND, or
+/// `None` if what is there is not a whole chunk with a valid CRC. Unlike the
+/// chunks before IEND, its data is read here to check the CRC, since the
+/// trailing bytes may be anything.
+fn read_trailing_chunk<R: Read + Seek>(
+    source: &mut R,
+    offset: u64,
+    end: u64,
+) -> crate::Result<Option<ChunkHeader>> {
+    if end - offset < 12 {
+        return Ok(None);
+    }
+    source.seek(SeekFrom::Start(offset))?;
+    let mut prefix = [0u8; 8];
+    source.read_exact(&mut prefix)?;
+    let length = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+    let Ok(typ) = ChunkType::try_from([prefix[4], prefix[5], prefix[6], prefix[7]]) else {
+        return Ok(None);
+    };
+    if offset + 12 + length as u64 > end {
+        return Ok(None);
+    }
+
+    let mut checksum = Checksum::new(&typ);
+    let mut buffer = vec![0u8; (length as usize).min(COPY_BUFFER)];
+    let mut remain = length as usize;
+    while remain > 0 {
+        let piece = &mut buffer[..remain.min(COPY_BUFFER)];
+        source.read_exact(piece)?;
+        checksum.update(piece);
+        remain -= piece.len();
+    }
+    let mut crc = [0u8; 4];
+    source.read_exact(&mut crc)?;
+    let crc = u32::from_be_bytes(crc);
+    if checksum.finalize() != crc {
+        return Ok(None);
+    }
+    Ok(Some(ChunkHeader {
+        offset,
+        length,
+        typ,
+        crc,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::str::FromStr;
+    use std::io::Cursor;
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"first".to_vec()),
+            Chunk::new(ChunkType::from_str("miDl").unwrap(), b"middle".to_vec()),
+            Chunk::new(ChunkType::from_str("LASt").unwrap(), Vec::new()),
+        ];
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_headers() {
+        let png = LazyPng::new(Cursor::new(testing_bytes())).unwrap();
+        let headers = png.headers();
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers[0].offset(), 8);
+        assert_eq!(headers[1].offset(), 8 + 12 + 5);
+        assert_eq!(headers[1].length(), 6);
+        assert_eq!(&headers[2].chunk_type().to_string(), "LASt");
+    }
+
+    #[test]
+    fn test_load() {
+        let mut png = LazyPng::new(Cursor::new(testing_bytes())).unwrap();
+        let chunk = png.load(1).unwrap();
+        assert_eq!(&chunk.data_as_string().unwrap(), "middle");
+        assert!(png.load(3).is_err());
+    }
+
+    #[test]
+    fn test_load_detects_bad_crc() {
+        let mut bytes = testing_bytes();
+        bytes[8 + 8] ^= 0xff;
+        let mut png = LazyPng::new(Cursor::new(bytes)).unwrap();
+        assert!(png.load(0).is_err());
+        assert!(png.load(1).is_ok());
+    }
+
+    #[test]
+    fn test_into_png() {
+        let bytes = testing_bytes();
+        let png = LazyPng::new(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(png.into_png().unwrap().as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_write_to_splices_chunks() {
+        let mut png = LazyPng::new(Cursor::new(testing_bytes())).unwrap();
+        let added = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"new".to_vec());
+        png.insert_chunk(added.clone(), Placement::Index(1))
+            .unwrap();
+        assert_eq!(png.remove_chunk("miDl", Removal::All).unwrap(), 1);
+        assert!(png.remove_chunk("miDl", Removal::First).is_err());
+
+        let mut expected = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"first".to_vec()),
+            Chunk::new(ChunkType::from_str("LASt").unwrap(), Vec::new()),
+        ]);
+        expected.insert_chunk(added, Placement::Index(1)).unwrap();
+        let mut out = Vec::new();
+        png.write_to(&mut out).unwrap();
+        assert_eq!(out, expected.as_bytes());
+        assert_eq!(png.output_len(), out.len() as u64);
+        assert_eq!(png.headers().len(), 3);
+    }
+
+    #[test]
+    fn test_write_to_checks_order_and_crc() {
+        let bytes = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), b"header".to_vec()),
+            Chunk::new(
+                ChunkType::from_str("IDAT").unwrap(),
+                vec![0; 3 * COPY_BUFFER],
+            ),
+            Chunk::new(IEND, Vec::new()),
+        ])
+        .as_bytes();
+        let mut png = LazyPng::new(Cursor::new(bytes.clone())).unwrap();
+        let ihdr = Chunk::new(ChunkType::from_str("IHDR").unwrap(), b"again".to_vec());
+        assert!(png.insert_chunk(ihdr, Placement::BeforeIend).is_err());
+        let mut out = Vec::new();
+        png.write_to(&mut out).unwrap();
+        assert_eq!(out, bytes);
+
+        let mut corrupt = bytes;
+        corrupt[8 + 12 + 18 + 100] ^= 1;
+        let mut png = LazyPng::new(Cursor::new(corrupt)).unwrap();
+        let error = png.write_to(Vec::new()).err().unwrap();
+        let error = error.downcast_ref::<ParseError>().unwrap();
+        assert_eq!(error.chunk_index(), Some(1));
+    }
+
+    #[test]
+    fn test_limits() {
+        let options = ParseOptions {
+            max_total_chunks: Some(2),
+            ..ParseOptions::default()
+        };
+        assert!(LazyPng::with_options(Cursor::new(testing_bytes()), &options).is_err());
+
+        let mut bytes = testing_bytes();
+        bytes[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(LazyPng::new(Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut bytes = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), b"header".to_vec()),
+            Chunk::new(IEND, Vec::new()),
+        ])
+        .as_bytes();
+        bytes.extend(b"appended");
+
+        let mut png = LazyPng::new(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(png.headers().len(), 2);
+        assert_eq!(png.trailing_len(), 8);
+        let mut out = Vec::new();
+        png.write_to(&mut out).unwrap();
+        assert_eq!(out, bytes);
+
+        assert_eq!(png.trim(), 8);
+        assert_eq!(png.output_len(), bytes.len() as u64 - 8);
+        let mut out = Vec::new();
+        png.write_to(&mut out).unwrap();
+        assert_eq!(out, bytes[..bytes.len() - 8]);
+
+        let png = LazyPng::new(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(png.into_png().unwrap().trailing_data(), b"appended");
+        assert!(LazyPng::with_options(Cursor::new(bytes), &ParseOptions::strict()).is_err());
+    }
+
+    #[test]
+    fn test_trailing_chunks() {
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), b"header".to_vec()),
+            Chunk::new(IEND, Vec::new()),
+        ]);
+        let hidden = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hidden".to_vec());
+        png.insert_chunk(hidden, Placement::AfterIend).unwrap();
+        let clean = png.as_bytes().len();
+        let mut bytes = png.as_bytes();
+        bytes.extend(b"appended");
+
+        let png = LazyPng::new(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(png.headers().len(), 2);
+        assert_eq!(png.trailing_len(), 12 + 6 + 8);
+        assert_eq!(
+            png.into_png().unwrap().trailing_data(),
+            &bytes[clean - 18..]
+        );
+
+        let mut png = LazyPng::new(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(png.remove_chunk("ruSt", Removal::First).unwrap(), 1);
+        assert_eq!(png.trailing_len(), 8);
+        let mut out = Vec::new();
+        png.write_to(&mut out).unwrap();
+        assert_eq!(out, [&bytes[..clean - 18], b"appended"].concat());
+        assert_eq!(png.output_len(), out.len() as u64);
+
+        let mut png = LazyPng::new(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(png.trim(), 12 + 6 + 8);
+        let mut out = Vec::new();
+        png.write_to(&mut out).unwrap();
+        assert_eq!(out, bytes[..clean - 18]);
+    }
+
+    #[test]
+    fn test_error_location() {
+        let mut bytes = testing_bytes();
+        bytes.truncate(bytes.len() - 2);
+        let error = LazyPng::new(Cursor::new(bytes)).err().unwrap();
+        let error = error.downcast_ref::<ParseError>().unwrap();
+        assert_eq!(error.kind(), &ParseErrorKind::Truncated);
+        assert_eq!(error.chunk_index(), Some(2));
+        assert_eq!(error.offset(), 8 + 17 + 18);
+    }
+
+    #[test]
+    fn test_invalid_signature() {
+        let mut bytes = testing_bytes();
+        bytes[1] = 0;
+        assert!(LazyPng::new(Cursor::new(bytes)).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+//! Reading, editing and writing PNG files chunk by chunk.
+//!
+//! [`Png`] owns its chunks, [`PngRef`] borrows them from a buffer,
+//! `lazy::LazyPng` reads them from a file on demand and
+//! `reader::ChunkReader` yields them one by one from any reader. The `pngme`
+//! binary is a thin command line front end over this library; build with
+//! `default-features = false` (adding `std` if wanted) to depend on the
+//! library alone.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+/// Emits a `tracing` event at `$level` when the `tracing` feature is enabled,
+/// and nothing otherwise.
+macro_rules! event {
+    ($level:ident, $($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)*);
+    };
+}
+
+pub mod ancillary;
+pub mod builder;
+pub mod chunk;
+pub mod chunk_type;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+pub mod error;
+pub mod fingerprint;
+pub mod handler;
+pub mod image;
+pub mod info;
+#[cfg(feature = "std")]
+pub mod lazy;
+#[cfg(feature = "std")]
+pub mod lsb;
+pub mod options;
+mod order;
+pub mod payload;
+pub mod png;
+#[cfg(feature = "std")]
+pub mod probe;
+pub mod progress;
+#[cfg(feature = "std")]
+pub mod reader;
+pub mod report;
+pub mod scan;
+pub mod stats;
+pub mod text;
+
+pub use chunk::{Chunk, ChunkRef};
+pub use chunk_type::ChunkType;
+#[cfg(feature = "std")]
+pub use error::FileError;
+pub use error::{ParseError, ParseErrorKind};
+pub use options::ParseOptions;
+pub use png::{Png, PngRef};
+#[cfg(feature = "std")]
+pub use probe::{Probe, probe};
+pub use text::{TextChunk, TextFormat};
+
+pub type Error = alloc::boxed::Box<dyn core::error::Error>;
+pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,170 @@
+use crate::image::{self, ImageHeader};
+use crate::png::Png;
+
+/// The pixel samples of an image, seen as room to hide a payload in their
+/// least significant bits.
+///
+/// Payload bits are assumed to fill the samples in file order, every
+/// channel including alpha, the same number of low bits in each.
+pub struct LsbCarrier {
+    header: ImageHeader,
+    /// The unfiltered, de-interlaced rows.
+    pixels: Vec<u8>,
+}
+
+impl Png {
+    /// The image's pixels as an LSB carrier. Indexed-colour images are
+    /// refused: changing the low bits of a palette index picks an unrelated
+    /// colour.
+    pub fn lsb_carrier(&self) -> crate::Result<LsbCarrier> {
+        let ihdr = self.chunk_by_type("IHDR").ok_or("no IHDR chunk")?;
+        let header = ImageHeader::parse(ihdr.data())?;
+        if header.color_type == 3 {
+            return Err("indexed-colour images cannot carry LSB payloads".into());
+        }
+        let pixels = image::unfilter(&header, &self.image_data()?)?;
+        Ok(LsbCarrier { header, pixels })
+    }
+}
+
+impl LsbCarrier {
+    pub fn header(&self) -> &ImageHeader {
+        &self.header
+    }
+
+    /// Samples in the image: pixels times channels.
+    pub fn samples(&self) -> u64 {
+        self.header.width as u64 * self.header.height as u64 * self.header.channels() as u64
+    }
+
+    /// The most low bits of each sample worth using: half the bit depth, as
+    /// beyond that the payload outweighs the image.
+    pub fn max_bits(&self) -> u8 {
+        self.header.bit_depth / 2
+    }
+
+    /// Payload bytes that fit using `bits` low bits of every sample, or
+    /// `None` if `bits` is zero or more than [`LsbCarrier::max_bits`].
+    pub fn capacity(&self, bits: u8) -> Option<u64> {
+        if bits == 0 || bits > self.max_bits() {
+            return None;
+        }
+        Some(self.samples() * bits as u64 / 8)
+    }
+
+    /// The expected peak signal-to-noise ratio, in decibels, of the image
+    /// after hiding `payload_len` random-looking bytes in `bits` low bits
+    /// per sample, or `None` if the payload does not fit. The error of each
+    /// sample used is averaged over every value its low bits could take, so
+    /// the estimate reflects the actual pixels rather than a worst case.
+    pub fn psnr(&self, bits: u8, payload_len: u64) -> Option<f64> {
+        if payload_len > self.capacity(bits)? {
+            return None;
+        }
+        if payload_len == 0 {
+            return Some(f64::INFINITY);
+        }
+        let used = (payload_len * 8).div_ceil(bits as u64);
+        let levels = (1u32 << bits) as f64;
+        let mean = (levels - 1.0) / 2.0;
+        let variance = (levels * levels - 1.0) / 12.0;
+        let mask = (1u16 << bits) - 1;
+        let error: f64 = self
+            .sample_values()
+            .take(used as usize)
+            .map(|s| {
+                let low = (s & mask) as f64;
+                (low - mean) * (low - mean) + variance
+            })
+            .sum();
+        let mse = error / self.samples() as f64;
+        let max = ((1u32 << self.header.bit_depth) - 1) as f64;
+        Some(10.0 * (max * max / mse).log10())
+    }
+
+    /// Every sample value in file order, skipping the padding bits at the
+    /// end of rows of sub-byte samples.
+    fn sample_values(&self) -> impl Iterator<Item = u16> + '_ {
+        let depth = self.header.bit_depth as usize;
+        let per_row = self.header.width as usize * self.header.channels();
+        self.pixels
+            .chunks_exact(self.header.row_len())
+            .flat_map(move |row| {
+                (0..per_row).map(move |i| match depth {
+                    16 => u16::from_be_bytes([row[2 * i], row[2 * i + 1]]),
+                    8 => row[i] as u16,
+                    _ => {
+                        let bit = i * depth;
+                        let shift = 8 - depth - bit % 8;
+                        ((row[bit / 8] >> shift) & ((1 << depth) - 1)) as u16
+                    }
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::consts::{IDAT, IEND, IHDR};
+
+    fn png(width: u32, height: u32, bit_depth: u8, color_type: u8, rows: &[u8]) -> Png {
+        let mut ihdr = Vec::new();
+        ihdr.extend(width.to_be_bytes());
+        ihdr.extend(height.to_be_bytes());
+        ihdr.extend([bit_depth, color_type, 0, 0, 0]);
+        Png::from_chunks(vec![
+            Chunk::new(IHDR, ihdr),
+            Chunk::new(IDAT, miniz_oxide::deflate::compress_to_vec_zlib(rows, 6)),
+            Chunk::new(IEND, Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_capacity() {
+        // 4 x 2 RGB, 8 bits: 24 samples.
+        let rows = [[0u8; 13], [0u8; 13]].concat();
+        let carrier = png(4, 2, 8, 2, &rows).lsb_carrier().unwrap();
+        assert_eq!(carrier.samples(), 24);
+        assert_eq!(carrier.max_bits(), 4);
+        assert_eq!(carrier.capacity(1), Some(3));
+        assert_eq!(carrier.capacity(4), Some(12));
+        assert_eq!(carrier.capacity(5), None);
+        assert_eq!(carrier.capacity(0), None);
+
+        let carrier = png(3, 1, 2, 0, &[0, 0]).lsb_carrier().unwrap();
+        assert_eq!(carrier.max_bits(), 1);
+        assert_eq!(carrier.capacity(1), Some(0));
+
+        assert!(png(1, 1, 8, 3, &[0, 0]).lsb_carrier().is_err());
+    }
+
+    #[test]
+    fn test_psnr() {
+        // 8 x 1 greyscale: 8 samples, one byte at 1 bit per sample.
+        let rows = [0u8; 9];
+        let carrier = png(8, 1, 8, 0, &rows).lsb_carrier().unwrap();
+        assert_eq!(carrier.psnr(1, 0), Some(f64::INFINITY));
+        assert_eq!(carrier.psnr(1, 2), None);
+        // Each low bit of 0 becomes 1 half the time: MSE 1/2.
+        let psnr = carrier.psnr(1, 1).unwrap();
+        assert!((psnr - 10.0 * (255.0f64 * 255.0 * 2.0).log10()).abs() < 1e-9);
+        // Half as many samples change, but each by more.
+        let two_bits = carrier.psnr(2, 1).unwrap();
+        let expected = 10.0 * (255.0f64 * 255.0 / (3.5 * 4.0 / 8.0)).log10();
+        assert!((two_bits - expected).abs() < 1e-9);
+        assert!(two_bits < psnr);
+    }
+
+    #[test]
+    fn test_sample_values() {
+        let carrier = png(3, 1, 4, 0, &[0, 0x12, 0x30]).lsb_carrier().unwrap();
+        assert_eq!(carrier.sample_values().collect::<Vec<_>>(), [1, 2, 3]);
+        let carrier = png(1, 1, 16, 4, &[0, 1, 2, 3, 4]).lsb_carrier().unwrap();
+        assert_eq!(
+            carrier.sample_values().collect::<Vec<_>>(),
+            [0x0102, 0x0304]
+        );
+    }
+}
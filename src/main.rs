@@ -1,12 +1,96 @@
 mod args;
-mod chunk;
-mod chunk_type;
 mod commands;
-mod png;
+mod config;
+#[cfg(feature = "tui")]
+mod tui;
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+use args::{Cli, Command, KeyCommand, ParseMode, TextCommand};
+use clap::Parser;
+use config::Config;
+use pngme::Result;
+use pngme::error::{FileError, ParseError};
+use pngme::options::ParseOptions;
+use std::process::ExitCode;
+use tracing::Level;
 
-fn main() -> Result<()> {
-    todo!()
-}
\ No newline at end of file
+fn run(command: Command, options: &ParseOptions) -> Result<()> {
+    match command {
+        Command::Encode(args) => commands::encode(args, options),
+        Command::Decode(args) => commands::decode(args, options),
+        Command::Remove(args) => commands::remove(args, options),
+        Command::Print(args) => commands::print(args, options),
+        Command::Info(args) => commands::info(args, options),
+        Command::Palette(args) => commands::palette(args, options),
+        Command::Capacity(args) => commands::capacity(args, options),
+        Command::Stats(args) => commands::stats(args, options),
+        Command::Validate(args) => commands::validate(args),
+        Command::Scan(args) => commands::scan(args),
+        Command::Meta(args) => commands::meta(args, options),
+        Command::Reorder(args) => commands::reorder(args, options),
+        Command::Canonicalize(args) => commands::canonicalize(args, options),
+        Command::Dedup(args) => commands::dedup(args, options),
+        Command::Trim(args) => commands::trim(args, options),
+        Command::Verify(args) => commands::verify(args),
+        Command::Hash(args) => commands::hash(args, options),
+        Command::Equal(args) => commands::equal(args, options),
+        Command::Explode(args) => commands::explode(args, options),
+        Command::Implode(args) => commands::implode(args),
+        Command::Watch(args) => commands::watch(args, options),
+        Command::Text(TextCommand::Convert(args)) => commands::convert_text(args, options),
+        Command::Ext(command) => commands::ext(command, options),
+        Command::Xmp(command) => commands::xmp(command, options),
+        #[cfg(feature = "tui")]
+        Command::Tui(args) => tui::tui(args, options),
+        Command::Keygen(args) => commands::keygen(args),
+        Command::Key(KeyCommand::Store(args)) => commands::key_store(args),
+        Command::GenType(args) => commands::gen_type(args),
+        Command::Completions(args) => commands::completions(args),
+    }
+}
+
+/// Sends log output to stderr at a level chosen by the number of `-v` flags.
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => Level::WARN,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+fn main() -> ExitCode {
+    let mut cli = Cli::parse();
+    init_logging(cli.verbose);
+    if cli.no_color {
+        anstream::ColorChoice::Never.write_global();
+    }
+    match Config::load(cli.config.as_deref()) {
+        Ok(config) => config.apply(&mut cli),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+    let options = cli.parse_mode.unwrap_or(ParseMode::Default).options();
+    let file = cli.command.file().map(|f| f.to_path_buf());
+    match run(cli.command, &options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            let parse_error = e.downcast_ref::<ParseError>().or_else(|| {
+                e.downcast_ref::<FileError>()
+                    .and_then(FileError::parse_error)
+            });
+            if let Some(e) = parse_error
+                && let Some(file) = file
+                && let Ok(snippet) = commands::snippet(&file, e.offset())
+            {
+                eprint!("{}", snippet);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::IEND;
+use crate::error::ParseErrorKind;
+use crate::order;
+
+/// Limits and switches applied while parsing.
+///
+/// The default limits are safe for untrusted input: a file declaring absurd
+/// chunk lengths or millions of chunks is rejected before anything large is
+/// allocated. Use [`ParseOptions::unlimited`] to opt out.
+///
+/// The default switches accept anything a typical decoder would read. Use
+/// [`ParseOptions::strict`] to validate a file and [`ParseOptions::lenient`]
+/// to recover what you can from a damaged one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Largest chunk data length accepted, in bytes.
+    pub max_chunk_len: Option<u32>,
+    /// Largest number of chunks accepted in one file.
+    pub max_total_chunks: Option<usize>,
+    /// Largest input accepted, in bytes.
+    pub max_file_size: Option<u64>,
+    /// Reject chunks whose CRC does not match their type and data.
+    pub verify_crc: bool,
+    /// Accept bytes after the IEND chunk rather than failing.
+    pub allow_trailing_data: bool,
+    /// Accept critical chunks that are not in the PNG specification.
+    pub allow_unknown_critical: bool,
+    /// Fail if the file does not end with IEND.
+    pub require_iend: bool,
+    /// Fail if the chunks break the specification's ordering rules.
+    pub enforce_ordering: bool,
+}
+
+/// The PNG specification caps chunk lengths at 2^31 - 1.
+pub(crate) const SPEC_MAX_CHUNK_LEN: u32 = (1 << 31) - 1;
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_chunk_len: Some(256 << 20),
+            max_total_chunks: Some(1 << 20),
+            max_file_size: Some(4 << 30),
+            verify_crc: true,
+            allow_trailing_data: true,
+            allow_unknown_critical: true,
+            require_iend: false,
+            enforce_ordering: false,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// No limits beyond those of the PNG specification itself.
+    pub fn unlimited() -> Self {
+        Self {
+            max_chunk_len: None,
+            max_total_chunks: None,
+            max_file_size: None,
+            ..Self::default()
+        }
+    }
+
+    /// Every check on, as a validator wants.
+    pub fn strict() -> Self {
+        Self {
+            verify_crc: true,
+            allow_trailing_data: false,
+            allow_unknown_critical: false,
+            require_iend: true,
+            enforce_ordering: true,
+            ..Self::default()
+        }
+    }
+
+    /// Every check that can be skipped off, as a recovery tool wants. The
+    /// size limits still apply.
+    pub fn lenient() -> Self {
+        Self {
+            verify_crc: false,
+            allow_trailing_data: true,
+            allow_unknown_critical: true,
+            require_iend: false,
+            enforce_ordering: false,
+            ..Self::default()
+        }
+    }
+
+    /// Applies the checks that need the whole chunk list: IEND presence,
+    /// unknown critical chunks and ordering. Failures carry the index of the
+    /// offending chunk, or `types.len()` for a missing IEND.
+    pub(crate) fn check_structure(
+        &self,
+        types: &[&ChunkType],
+    ) -> Result<(), (usize, ParseErrorKind)> {
+        if self.require_iend && types.last().is_none_or(|t| **t != IEND) {
+            return Err((types.len(), ParseErrorKind::MissingIend));
+        }
+        if !self.allow_unknown_critical
+            && let Some(i) = types
+                .iter()
+                .position(|t| t.is_critical() && !t.is_standard())
+        {
+            return Err((i, ParseErrorKind::UnknownCritical));
+        }
+        if self.enforce_ordering
+            && let Some(v) = order::violations(types).into_iter().next()
+        {
+            return Err((v.index, ParseErrorKind::InvalidOrder(v.message)));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_trailing_data(&self, length: u64) -> Result<(), ParseErrorKind> {
+        if length > 0 && !self.allow_trailing_data {
+            return Err(ParseErrorKind::TrailingData { length });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_chunk_len(&self, length: u32) -> Result<(), ParseErrorKind> {
+        let max = self
+            .max_chunk_len
+            .unwrap_or(SPEC_MAX_CHUNK_LEN)
+            .min(SPEC_MAX_CHUNK_LEN);
+        if length > max {
+            return Err(ParseErrorKind::ChunkTooLong { length, max });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_total_chunks(&self, count: usize) -> Result<(), ParseErrorKind> {
+        match self.max_total_chunks {
+            Some(max) if count > max => Err(ParseErrorKind::TooManyChunks { max }),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_file_size(&self, size: u64) -> Result<(), ParseErrorKind> {
+        match self.max_file_size {
+            Some(max) if size > max => Err(ParseErrorKind::FileTooLarge { size, max }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_limits() {
+        let options = ParseOptions::default();
+        assert!(options.check_chunk_len(1 << 20).is_ok());
+        assert!(options.check_chunk_len(1 << 30).is_err());
+        assert!(options.check_total_chunks(1000).is_ok());
+        assert!(options.check_total_chunks(2 << 20).is_err());
+        assert!(options.check_file_size(1 << 30).is_ok());
+        assert!(options.check_file_size(8 << 30).is_err());
+    }
+
+    #[test]
+    fn test_check_structure() {
+        use crate::chunk_type::consts::*;
+        use core::str::FromStr;
+
+        let rust = ChunkType::from_str("RuSt").unwrap();
+        let valid = [&IHDR, &IDAT, &IEND];
+        let no_iend = [&IHDR, &IDAT];
+        let unknown = [&IHDR, &rust, &IDAT, &IEND];
+        let misordered = [&IHDR, &IDAT, &PLTE, &IEND];
+
+        for options in [ParseOptions::default(), ParseOptions::lenient()] {
+            for types in [&valid[..], &no_iend, &unknown, &misordered] {
+                assert!(options.check_structure(types).is_ok());
+            }
+            assert!(options.check_trailing_data(10).is_ok());
+        }
+
+        let strict = ParseOptions::strict();
+        assert!(strict.check_structure(&valid).is_ok());
+        assert_eq!(
+            strict.check_structure(&no_iend),
+            Err((2, ParseErrorKind::MissingIend))
+        );
+        assert_eq!(
+            strict.check_structure(&unknown),
+            Err((1, ParseErrorKind::UnknownCritical))
+        );
+        assert!(matches!(
+            strict.check_structure(&misordered),
+            Err((2, ParseErrorKind::InvalidOrder(_)))
+        ));
+        assert!(strict.check_trailing_data(0).is_ok());
+        assert!(strict.check_trailing_data(10).is_err());
+    }
+
+    #[test]
+    fn test_unlimited_still_applies_spec_limit() {
+        let options = ParseOptions::unlimited();
+        assert!(options.check_chunk_len(SPEC_MAX_CHUNK_LEN).is_ok());
+        assert!(options.check_chunk_len(u32::MAX).is_err());
+        assert!(options.check_total_chunks(usize::MAX).is_ok());
+        assert!(options.check_file_size(u64::MAX).is_ok());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::*;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Chunks that must come before both PLTE and the first IDAT.
+const BEFORE_PLTE: [ChunkType; 8] = [CHRM, GAMA, ICCP, SBIT, SRGB, CICP, MDCV, CLLI];
+/// Chunks that must come after PLTE (if any) and before the first IDAT.
+const AFTER_PLTE: [ChunkType; 3] = [TRNS, BKGD, HIST];
+/// Chunks that only need to come before the first IDAT.
+const BEFORE_IDAT: [ChunkType; 6] = [PHYS, SPLT, OFFS, PCAL, SCAL, ACTL];
+/// Chunks that may appear at most once.
+const SINGLETONS: [ChunkType; 21] = [
+    IHDR, PLTE, IEND, CHRM, GAMA, ICCP, SBIT, SRGB, CICP, MDCV, CLLI, TRNS, BKGD, HIST, PHYS, OFFS,
+    PCAL, SCAL, TIME, ACTL, EXIF,
+];
+
+/// Whether a file may hold at most one chunk of this type.
+pub(crate) fn is_singleton(chunk_type: &ChunkType) -> bool {
+    SINGLETONS.contains(chunk_type)
+}
+
+/// A broken ordering rule, pinned to the chunk that breaks it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Violation {
+    pub(crate) index: usize,
+    pub(crate) message: String,
+}
+
+/// Returns every way `types` breaks the PNG chunk ordering rules. Only chunks
+/// that are present are checked, so a partial chunk list (e.g. one still
+/// being built) is not an error in itself.
+pub(crate) fn violations(types: &[&ChunkType]) -> Vec<Violation> {
+    let mut found = Vec::new();
+    let mut push = |index: usize, message: String| found.push(Violation { index, message });
+    let position = |chunk_type: ChunkType| types.iter().position(|t| **t == chunk_type);
+    let first_idat = position(IDAT);
+    let plte = position(PLTE);
+
+    for name in SINGLETONS {
+        if let Some((i, _)) = types
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| ***t == name)
+            .nth(1)
+        {
+            push(i, format!("more than one {} chunk", name));
+        }
+    }
+
+    if let Some(i) = position(IHDR)
+        && i != 0
+    {
+        push(i, String::from("IHDR is not the first chunk"));
+    }
+    if let Some(i) = types.iter().rposition(|t| **t == IEND)
+        && i != types.len() - 1
+    {
+        push(i, String::from("IEND is not the last chunk"));
+    }
+
+    if let Some(first) = first_idat {
+        let last = types.iter().rposition(|t| **t == IDAT).unwrap();
+        if let Some(i) = types[first..=last].iter().position(|t| **t != IDAT) {
+            push(first + i, String::from("IDAT chunks are not consecutive"));
+        }
+    }
+
+    for (i, t) in types.iter().enumerate() {
+        let before_idat = first_idat.is_none_or(|idat| i < idat);
+        let before_plte = plte.is_none_or(|plte| i < plte);
+        let after_plte = plte.is_none_or(|plte| i > plte);
+
+        if let Some(name) = BEFORE_PLTE.iter().find(|n| *n == *t)
+            && !(before_plte && before_idat)
+        {
+            push(i, format!("{} must come before PLTE and IDAT", name));
+        }
+        if let Some(name) = AFTER_PLTE.iter().find(|n| *n == *t)
+            && !(after_plte && before_idat)
+        {
+            push(i, format!("{} must come after PLTE and before IDAT", name));
+        }
+        if let Some(name) = BEFORE_IDAT.iter().find(|n| *n == *t)
+            && !before_idat
+        {
+            push(i, format!("{} must come before IDAT", name));
+        }
+        if **t == PLTE && !before_idat {
+            push(i, String::from("PLTE must come before IDAT"));
+        }
+    }
+
+    found
+}
+
+/// Where a chunk sits relative to the critical chunks, in file order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Region {
+    Ihdr,
+    /// Before PLTE, or before IDAT when there is no PLTE.
+    BeforePlte,
+    Plte,
+    AfterPlte,
+    Idat,
+    AfterIdat,
+    Iend,
+}
+
+/// Returns a new order for `types`, as indices into it, in which every
+/// ancillary chunk with a placement rule is moved to a legal region and the
+/// IDAT run is made consecutive. Each index is paired with whether that chunk
+/// had to move; the others keep their relative order. Duplicate singletons
+/// are left for the caller to resolve.
+pub(crate) fn repair(types: &[&ChunkType]) -> Vec<(usize, bool)> {
+    let position = |chunk_type: ChunkType| types.iter().position(|t| **t == chunk_type);
+    let iend = position(IEND).unwrap_or(types.len());
+    let first_idat = position(IDAT).unwrap_or(iend);
+    let last_idat = types
+        .iter()
+        .rposition(|t| **t == IDAT)
+        .unwrap_or(first_idat);
+    let plte = position(PLTE);
+    // Without a PLTE the two regions before IDAT are one.
+    let after_plte = match plte {
+        Some(_) => Region::AfterPlte,
+        None => Region::BeforePlte,
+    };
+
+    let regions: Vec<(Region, Region)> = types
+        .iter()
+        .enumerate()
+        .map(|(i, t)| {
+            let current = match i {
+                _ if **t == IHDR => Region::Ihdr,
+                _ if **t == PLTE => Region::Plte,
+                _ if **t == IDAT => Region::Idat,
+                _ if **t == IEND => Region::Iend,
+                _ if i < first_idat && plte.is_none_or(|p| i < p) => Region::BeforePlte,
+                _ if i < first_idat => Region::AfterPlte,
+                _ if i < last_idat => Region::Idat,
+                _ => Region::AfterIdat,
+            };
+            let target = if BEFORE_PLTE.contains(t) {
+                current.min(Region::BeforePlte)
+            } else if AFTER_PLTE.contains(t) {
+                after_plte
+            } else if BEFORE_IDAT.contains(t) {
+                current.min(after_plte)
+            } else if current == Region::Idat && **t != IDAT {
+                Region::AfterIdat
+            } else {
+                current
+            };
+            (current, target)
+        })
+        .collect();
+
+    let mut order: Vec<usize> = (0..types.len()).collect();
+    order.sort_by_key(|&i| regions[i].1);
+    order
+        .into_iter()
+        .map(|i| (i, regions[i].0 != regions[i].1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::str::FromStr;
+
+    fn check(names: &[&str]) -> Vec<String> {
+        let types: Vec<ChunkType> = names
+            .iter()
+            .map(|n| ChunkType::from_str(n).unwrap())
+            .collect();
+        violations(&types.iter().collect::<Vec<_>>())
+            .into_iter()
+            .map(|v| v.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_order() {
+        assert!(
+            check(&[
+                "IHDR", "gAMA", "PLTE", "tRNS", "IDAT", "IDAT", "tEXt", "IEND"
+            ])
+            .is_empty()
+        );
+        assert!(check(&["tEXt", "ruSt"]).is_empty());
+    }
+
+    #[test]
+    fn test_violation_index() {
+        let types = [IHDR, IDAT, TEXT, IDAT, IEND];
+        let found = violations(&types.iter().collect::<Vec<_>>());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].index, 2);
+    }
+
+    fn repaired(names: &[&str]) -> Vec<String> {
+        let types: Vec<ChunkType> = names
+            .iter()
+            .map(|n| ChunkType::from_str(n).unwrap())
+            .collect();
+        let order = repair(&types.iter().collect::<Vec<_>>());
+        order
+            .into_iter()
+            .map(|(i, _)| names[i].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_repair() {
+        let valid = [
+            "IHDR", "gAMA", "PLTE", "tRNS", "IDAT", "IDAT", "tEXt", "IEND",
+        ];
+        assert_eq!(repaired(&valid), valid);
+
+        let fixed = repaired(&[
+            "IHDR", "tEXt", "PLTE", "iCCP", "tRNS", "IDAT", "zTXt", "IDAT", "pHYs", "IEND",
+        ]);
+        assert_eq!(
+            fixed,
+            [
+                "IHDR", "tEXt", "iCCP", "PLTE", "tRNS", "pHYs", "IDAT", "IDAT", "zTXt", "IEND"
+            ]
+        );
+        assert!(check(&fixed.iter().map(|s| s.as_str()).collect::<Vec<_>>()).is_empty());
+
+        assert_eq!(
+            repaired(&["IHDR", "bKGD", "gAMA", "IDAT", "sRGB", "IEND", "tIME"]),
+            ["IHDR", "bKGD", "gAMA", "sRGB", "IDAT", "tIME", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_order() {
+        assert_eq!(
+            check(&["IHDR", "IHDR", "IEND"]),
+            ["more than one IHDR chunk"]
+        );
+        assert_eq!(
+            check(&["IHDR", "IEND", "tEXt"]),
+            ["IEND is not the last chunk"]
+        );
+        assert_eq!(
+            check(&["IHDR", "IDAT", "tEXt", "IDAT"]),
+            ["IDAT chunks are not consecutive"]
+        );
+        assert_eq!(
+            check(&["IHDR", "PLTE", "iCCP", "IDAT"]),
+            ["iCCP must come before PLTE and IDAT"]
+        );
+        assert_eq!(
+            check(&["IHDR", "tRNS", "PLTE", "IDAT"]),
+            ["tRNS must come after PLTE and before IDAT"]
+        );
+        assert_eq!(
+            check(&["IHDR", "IDAT", "pHYs"]),
+            ["pHYs must come before IDAT"]
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use alloc::format;
+use alloc::vec::Vec;
+
+/// Marks a compressed payload. It is followed by the [`Compression`] method
+/// byte, the uncompressed length as a big-endian `u64` and the compressed
+/// data.
+const MAGIC: &[u8; 4] = b"PMEz";
+
+/// Bytes before the compressed data.
+const HEADER_LEN: usize = MAGIC.len() + 1 + 8;
+
+/// Most bytes a payload may decompress to, whatever its header claims, as
+/// for image data.
+const MAX_PAYLOAD_LEN: usize = 1 << 30;
+
+const METHOD_DEFLATE: u8 = 0;
+const METHOD_ZSTD: u8 = 1;
+
+/// How a payload is compressed before it is embedded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// zlib-wrapped DEFLATE, level 0 to 10.
+    Deflate { level: u8 },
+    /// Zstandard, level 1 to 22. Needs the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32 },
+}
+
+impl Compression {
+    pub const DEFAULT_DEFLATE_LEVEL: u8 = 6;
+    #[cfg(feature = "zstd")]
+    pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+    /// The same method at its highest level.
+    pub fn strongest(self) -> Self {
+        match self {
+            Compression::Deflate { .. } => Compression::Deflate { level: 10 },
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { .. } => Compression::Zstd { level: 22 },
+        }
+    }
+
+    /// Checks the level is in range for the method.
+    pub fn check(&self) -> crate::Result<()> {
+        match *self {
+            Compression::Deflate { level } if level > 10 => {
+                Err("deflate level must be 0 to 10".into())
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { level } if !(1..=22).contains(&level) => {
+                Err("zstd level must be 1 to 22".into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Compresses `data` and wraps it in the header [`decompress`] reads back.
+pub fn compress(data: &[u8], compression: Compression) -> crate::Result<Vec<u8>> {
+    compression.check()?;
+    let (method, compressed) = match compression {
+        Compression::Deflate { level } => (
+            METHOD_DEFLATE,
+            miniz_oxide::deflate::compress_to_vec_zlib(data, level),
+        ),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd { level } => (METHOD_ZSTD, zstd::bulk::compress(data, level)?),
+    };
+    let mut payload = Vec::with_capacity(HEADER_LEN + compressed.len());
+    payload.extend_from_slice(MAGIC);
+    payload.push(method);
+    payload.extend((data.len() as u64).to_be_bytes());
+    payload.extend(compressed);
+    Ok(payload)
+}
+
+/// Whether `payload` starts with the header [`compress`] writes.
+pub fn is_compressed(payload: &[u8]) -> bool {
+    payload.len() >= HEADER_LEN && payload.starts_with(MAGIC)
+}
+
+/// Unwraps and decompresses a payload written by [`compress`]. The output
+/// must match the length in the header exactly. That length comes from the
+/// file and may be forged, so nothing is allocated up front from it, and
+/// payloads claiming more than 1 GiB are refused.
+pub fn decompress(payload: &[u8]) -> crate::Result<Vec<u8>> {
+    decompress_with_limit(payload, MAX_PAYLOAD_LEN)
+}
+
+/// [`decompress`], refusing payloads longer than `max` bytes.
+fn decompress_with_limit(payload: &[u8], max: usize) -> crate::Result<Vec<u8>> {
+    if !is_compressed(payload) {
+        return Err("payload is not compressed".into());
+    }
+    let method = payload[MAGIC.len()];
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&payload[MAGIC.len() + 1..HEADER_LEN]);
+    let len = usize::try_from(u64::from_be_bytes(len))
+        .ok()
+        .filter(|&len| len <= max)
+        .ok_or("payload is too large")?;
+    let compressed = &payload[HEADER_LEN..];
+    let data = match method {
+        METHOD_DEFLATE => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(compressed, len)
+            .map_err(|e| format!("cannot decompress payload: {}", e))?,
+        #[cfg(feature = "zstd")]
+        METHOD_ZSTD => {
+            // `zstd::bulk` reserves the whole declared length before
+            // decoding, so read through a stream that stops just past it.
+            use std::io::Read;
+            let mut data = Vec::new();
+            zstd::stream::read::Decoder::new(compressed)
+                .and_then(|d| d.take(len as u64 + 1).read_to_end(&mut data))
+                .map_err(|e| format!("cannot decompress payload: {}", e))?;
+            data
+        }
+        #[cfg(not(feature = "zstd"))]
+        METHOD_ZSTD => return Err("zstd payloads need the zstd feature".into()),
+        _ => return Err(format!("unknown compression method {}", method).into()),
+    };
+    if data.len() != len {
+        return Err("decompressed payload has the wrong length".into());
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deflate() {
+        let data = b"squeeze me, squeeze me, squeeze me".repeat(10);
+        let payload = compress(&data, Compression::Deflate { level: 9 }).unwrap();
+        assert!(is_compressed(&payload));
+        assert!(payload.len() < data.len());
+        assert_eq!(&payload[..5], b"PMEz\0");
+        assert_eq!(decompress(&payload).unwrap(), data);
+
+        assert!(compress(&data, Compression::Deflate { level: 11 }).is_err());
+        let strongest = Compression::Deflate { level: 1 }.strongest();
+        assert!(strongest.check().is_ok());
+        assert_eq!(strongest, Compression::Deflate { level: 10 });
+        assert!(!is_compressed(&data));
+        assert!(decompress(&data).is_err());
+
+        let mut wrong_len = payload.clone();
+        wrong_len[12] += 1;
+        assert!(decompress(&wrong_len).is_err());
+        let mut unknown = payload;
+        unknown[4] = 9;
+        assert!(decompress(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_decompression_bomb() {
+        // 8 MiB of zeros squeezes into a few KiB.
+        let bomb = compress(&vec![0; 8 << 20], Compression::Deflate { level: 10 }).unwrap();
+        assert!(bomb.len() < 64 << 10);
+
+        let mut forged = bomb.clone();
+        forged[5..13].copy_from_slice(&(u64::MAX / 2).to_be_bytes());
+        let error = decompress(&forged).unwrap_err();
+        assert_eq!(error.to_string(), "payload is too large");
+
+        // Under a smaller cap the honest header is refused too, and one
+        // forged to fit stops inflating at the cap.
+        assert!(decompress_with_limit(&bomb, 1 << 20).is_err());
+        let mut fits = bomb;
+        fits[5..13].copy_from_slice(&(1u64 << 20).to_be_bytes());
+        assert!(decompress_with_limit(&fits, 1 << 20).is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        let data = b"squeeze me, squeeze me, squeeze me".repeat(10);
+        let payload = compress(&data, Compression::Zstd { level: 19 }).unwrap();
+        assert_eq!(payload[4], METHOD_ZSTD);
+        assert!(payload.len() < data.len());
+        assert_eq!(decompress(&payload).unwrap(), data);
+        assert!(compress(&data, Compression::Zstd { level: 0 }).is_err());
+
+        let mut short = payload;
+        short[12] -= 1;
+        assert!(decompress(&short).is_err());
+
+        // A forged length must not be reserved up front.
+        let mut forged = b"PMEz\x01".to_vec();
+        forged.extend((u64::MAX / 2).to_be_bytes());
+        forged.extend(b"garbage");
+        assert!(decompress(&forged).is_err());
+
+        let mut bomb = compress(&vec![0; 8 << 20], Compression::Zstd { level: 19 }).unwrap();
+        bomb[5..13].copy_from_slice(&(u64::MAX / 2).to_be_bytes());
+        assert_eq!(
+            decompress(&bomb).unwrap_err().to_string(),
+            "payload is too large"
+        );
+        bomb[5..13].copy_from_slice(&(1u64 << 20).to_be_bytes());
+        assert!(decompress_with_limit(&bomb, 1 << 20).is_err());
+    }
+}
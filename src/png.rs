@@ -0,0 +1,1773 @@
+use crate::chunk::{Chunk, ChunkRef, HexPreview};
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::{ACTL, IDAT, IEND, IHDR, ITXT};
+use crate::error::{ParseError, ParseErrorKind};
+use crate::options::ParseOptions;
+use crate::order;
+use crate::progress::Progress;
+use crate::text::{TextChunk, TextFormat, XMP_KEYWORD};
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Display, Formatter};
+
+/// zlib level [`Png::canonicalize`] recompresses image data with.
+const CANONICAL_LEVEL: u8 = 9;
+
+/// Largest decompressed image data accepted when the IDAT stream is read.
+const MAX_IMAGE_DATA: usize = 1 << 30;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Png {
+    chunks: Vec<Chunk>,
+    /// Bytes after IEND, kept so that writing the file back preserves them.
+    trailing: Vec<u8>,
+}
+
+/// Where [`Png::insert_chunk`] puts a new chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// Immediately before IEND, the usual place for ancillary chunks.
+    BeforeIend,
+    /// Immediately after IHDR.
+    AfterIhdr,
+    /// Immediately after IEND, where decoders and many optimizers treat it
+    /// as trailing data. The file no longer conforms to the specification,
+    /// so no ordering rules are checked.
+    AfterIend,
+    /// Immediately before the first IDAT.
+    BeforeFirstIdat,
+    /// At this index in the chunk list.
+    Index(usize),
+}
+
+/// Which matching chunks [`Png::remove_chunk`] removes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Removal {
+    /// Only the first chunk of the type.
+    First,
+    /// Every chunk of the type.
+    All,
+    /// The nth (zero-based) chunk of the type.
+    Nth(usize),
+}
+
+/// A chunk [`Png::reorder`] moved, with its positions before and after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move {
+    pub chunk_type: ChunkType,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// A chunk that repeats an earlier one, as found by [`Png::find_duplicates`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Duplicate {
+    pub chunk_type: ChunkType,
+    pub index: usize,
+    /// The earlier chunk it repeats.
+    pub original: usize,
+    /// Whether the data is byte-identical to the original's. If not, the
+    /// type is one that may appear only once, so the file is invalid.
+    pub identical: bool,
+}
+
+/// A parsed PNG whose chunks borrow from the input buffer until edited.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PngRef<'a> {
+    chunks: Vec<ChunkRef<'a>>,
+    /// Whatever follows IEND.
+    trailing: &'a [u8],
+}
+
+/// A PNG file mapped into memory. Chunk views are built over the mapping, so
+/// the file is never read into RAM as a whole.
+#[cfg(feature = "mmap")]
+pub struct MappedPng {
+    map: memmap2::Mmap,
+}
+
+impl TryFrom<&[u8]> for Png {
+    type Error = ParseError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Self::parse(value, &ParseOptions::default())
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PngRef<'a> {
+    type Error = ParseError;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        Self::parse(value, &ParseOptions::default())
+    }
+}
+
+impl IntoIterator for Png {
+    type Item = Chunk;
+    type IntoIter = alloc::vec::IntoIter<Chunk>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chunks.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Png {
+    type Item = &'a Chunk;
+    type IntoIter = core::slice::Iter<'a, Chunk>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chunks.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut Png {
+    type Item = &'a mut Chunk;
+    type IntoIter = core::slice::IterMut<'a, Chunk>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chunks.iter_mut()
+    }
+}
+
+impl Display for Png {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for chunk in self.chunks.iter() {
+            writeln!(f, "{}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for PngRef<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for chunk in self.chunks.iter() {
+            writeln!(f, "{}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for Png {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Png")
+            .field("chunks", &self.chunks)
+            .field("trailing", &HexPreview(&self.trailing))
+            .finish()
+    }
+}
+
+impl Debug for PngRef<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PngRef")
+            .field("chunks", &self.chunks)
+            .field("trailing", &HexPreview(self.trailing))
+            .finish()
+    }
+}
+
+impl Png {
+    pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+    pub fn parse(value: &[u8], options: &ParseOptions) -> Result<Self, ParseError> {
+        PngRef::parse(value, options).map(|p| p.into_png())
+    }
+    /// Like [`Png::parse`], reporting the bytes parsed after every chunk.
+    pub fn parse_with_progress(
+        value: &[u8],
+        options: &ParseOptions,
+        progress: &mut dyn Progress,
+    ) -> Result<Self, ParseError> {
+        PngRef::parse_with_progress(value, options, progress).map(|p| p.into_png())
+    }
+    /// Like [`Png::parse`], taking the buffer so that the chunks share it
+    /// instead of each copying its data out. Editing or removing a chunk
+    /// then copies nothing else, at the cost of keeping the whole buffer
+    /// alive while any chunk still uses it.
+    pub fn parse_owned(value: Vec<u8>, options: &ParseOptions) -> Result<Self, ParseError> {
+        Self::parse_owned_with_progress(value, options, &mut |_, _| {})
+    }
+    /// Like [`Png::parse_owned`], reporting the bytes parsed after every
+    /// chunk.
+    pub fn parse_owned_with_progress(
+        value: Vec<u8>,
+        options: &ParseOptions,
+        progress: &mut dyn Progress,
+    ) -> Result<Self, ParseError> {
+        let buffer = Arc::new(value);
+        let parsed = PngRef::parse_with_progress(&buffer, options, progress)?;
+        let base = buffer.as_ptr() as usize;
+        let chunks = parsed
+            .chunks()
+            .iter()
+            .map(|c| {
+                let start = c.data().as_ptr() as usize - base;
+                let range = start..start + c.data().len();
+                Chunk::shared(*c.chunk_type(), &buffer, range, c.crc())
+            })
+            .collect();
+        Ok(Self {
+            chunks,
+            trailing: parsed.trailing_data().to_vec(),
+        })
+    }
+    /// Like [`PngRef::parse_all`], copying the chunks.
+    pub fn parse_all(value: &[u8], options: &ParseOptions) -> Result<Vec<Self>, ParseError> {
+        PngRef::parse_all(value, options).map(|all| all.into_iter().map(|p| p.into_png()).collect())
+    }
+    pub fn from_chunks(chunks: Vec<Chunk>) -> Self {
+        Self {
+            chunks,
+            trailing: Vec::new(),
+        }
+    }
+    /// The bytes after IEND, such as an appended archive or payload. They
+    /// are written back out unless removed with [`Png::trim`].
+    pub fn trailing_data(&self) -> &[u8] {
+        &self.trailing
+    }
+    pub fn set_trailing_data(&mut self, data: Vec<u8>) {
+        self.trailing = data;
+    }
+    /// Drops the bytes after IEND, returning them.
+    pub fn trim(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.trailing)
+    }
+    pub fn append_chunk(&mut self, chunk: Chunk) {
+        self.chunks.push(chunk);
+    }
+    /// Inserts `chunk` at `placement`, refusing if that would break the chunk
+    /// ordering rules (e.g. a second IHDR, or iCCP after PLTE).
+    pub fn insert_chunk(&mut self, chunk: Chunk, placement: Placement) -> crate::Result<()> {
+        let types: Vec<_> = self.chunks.iter().map(|c| c.chunk_type()).collect();
+        let index = insertion_index(&types, chunk.chunk_type(), placement)?;
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+    /// Removes the chunks of type `chunk_type` selected by `which`, returning
+    /// them in file order. It is an error if nothing matched.
+    pub fn remove_chunk(&mut self, chunk_type: &str, which: Removal) -> crate::Result<Vec<Chunk>> {
+        let types: Vec<_> = self.chunks.iter().map(|c| c.chunk_type()).collect();
+        let indices = removal_indices(&types, chunk_type, which)?;
+        let mut removed: Vec<Chunk> = indices
+            .into_iter()
+            .rev()
+            .map(|i| self.chunks.remove(i))
+            .collect();
+        removed.reverse();
+        Ok(removed)
+    }
+    /// Replaces the data of the first chunk of type `chunk_type` in place,
+    /// recomputing its CRC, and returns the old data.
+    pub fn replace_chunk(&mut self, chunk_type: &str, data: Vec<u8>) -> crate::Result<Vec<u8>> {
+        let chunk = self
+            .chunks
+            .iter_mut()
+            .find(|c| c.chunk_type().matches(chunk_type))
+            .ok_or("chunk not found")?;
+        Ok(chunk.set_data(data))
+    }
+    pub fn header(&self) -> &[u8; 8] {
+        &Self::STANDARD_HEADER
+    }
+    pub fn chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.iter()
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Chunk> {
+        self.chunks.iter_mut()
+    }
+    /// Returns the first chunk whose type is exactly `chunk_type`.
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
+        self.chunks
+            .iter()
+            .find(|c| c.chunk_type().matches(chunk_type))
+    }
+    /// Returns every chunk whose type is exactly `chunk_type`, in file order.
+    pub fn chunks_of_type<'a>(&'a self, chunk_type: &'a str) -> impl Iterator<Item = &'a Chunk> {
+        self.chunks
+            .iter()
+            .filter(move |c| c.chunk_type().matches(chunk_type))
+    }
+    /// Like [`Png::chunk_by_type`], ignoring the case (and so the property
+    /// bits) of `chunk_type`.
+    pub fn chunk_by_type_ignore_case(&self, chunk_type: &str) -> Option<&Chunk> {
+        self.chunks
+            .iter()
+            .find(|c| c.chunk_type().matches_ignore_case(chunk_type))
+    }
+    /// Like [`Png::chunks_of_type`], ignoring the case (and so the property
+    /// bits) of `chunk_type`.
+    pub fn chunks_of_type_ignore_case<'a>(
+        &'a self,
+        chunk_type: &'a str,
+    ) -> impl Iterator<Item = &'a Chunk> {
+        self.chunks
+            .iter()
+            .filter(move |c| c.chunk_type().matches_ignore_case(chunk_type))
+    }
+    /// Decodes every tEXt, zTXt and iTXt chunk, in file order.
+    pub fn text_chunks(&self) -> impl Iterator<Item = crate::Result<TextChunk>> {
+        self.chunks
+            .iter()
+            .filter(|c| TextFormat::of(c.chunk_type()).is_some())
+            .map(TextChunk::try_from)
+    }
+    /// Stores `text` under `keyword`. Every text chunk with that keyword is
+    /// rewritten, keeping its format where it can hold the text; if there is
+    /// none a new chunk is added before IEND. Undecodable chunks are left
+    /// alone.
+    pub fn set_text(&mut self, keyword: &str, text: &str) -> crate::Result<()> {
+        let mut found = false;
+        for chunk in self.chunks.iter_mut() {
+            if TextFormat::of(chunk.chunk_type()).is_none() {
+                continue;
+            }
+            match TextChunk::try_from(&*chunk) {
+                Ok(existing) if existing.keyword == keyword => {
+                    *chunk = existing.with_text(text).encode()?;
+                    found = true;
+                }
+                _ => {}
+            }
+        }
+        if !found {
+            let chunk = TextChunk::new(TextFormat::Text, keyword, "").with_text(text);
+            self.insert_chunk(chunk.encode()?, Placement::BeforeIend)?;
+        }
+        Ok(())
+    }
+    /// The XMP packet, from the first iTXt chunk with the
+    /// [`XMP_KEYWORD`] keyword. Text chunks that cannot be decoded are
+    /// skipped, as [`Png::set_xmp`] skips them, so one broken comment does
+    /// not hide the packet.
+    pub fn xmp(&self) -> crate::Result<Option<String>> {
+        Ok(self
+            .text_chunks()
+            .filter_map(|text| text.ok())
+            .find(|text| text.format == TextFormat::Itxt && text.keyword == XMP_KEYWORD)
+            .map(|text| text.text))
+    }
+    /// Stores `packet` as the XMP packet. XMP requires an uncompressed iTXt
+    /// chunk with no language fields, so that is what is written, replacing
+    /// any existing packet in place or else going before the first IDAT,
+    /// where readers that stop at the image data still see it. Further XMP
+    /// chunks are removed.
+    pub fn set_xmp(&mut self, packet: &str) -> crate::Result<()> {
+        let chunk = TextChunk::new(TextFormat::Itxt, XMP_KEYWORD, packet).encode()?;
+        let mut existing = self.xmp_indices().into_iter();
+        match existing.next() {
+            Some(first) => {
+                self.chunks[first] = chunk;
+                for i in existing.rev() {
+                    self.chunks.remove(i);
+                }
+                Ok(())
+            }
+            None => self.insert_chunk(chunk, Placement::BeforeFirstIdat),
+        }
+    }
+    /// Removes every XMP chunk, returning how many there were.
+    pub fn remove_xmp(&mut self) -> usize {
+        let indices = self.xmp_indices();
+        for &i in indices.iter().rev() {
+            self.chunks.remove(i);
+        }
+        indices.len()
+    }
+    fn xmp_indices(&self) -> Vec<usize> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| *c.chunk_type() == ITXT)
+            .filter(|(_, c)| TextChunk::try_from(*c).is_ok_and(|t| t.keyword == XMP_KEYWORD))
+            .map(|(i, _)| i)
+            .collect()
+    }
+    /// Rewrites every text chunk in `format`, returning how many changed. If
+    /// any chunk cannot be decoded, or holds text `format` cannot store,
+    /// nothing is changed.
+    pub fn convert_text(&mut self, format: TextFormat) -> crate::Result<usize> {
+        let mut converted = Vec::new();
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            if TextFormat::of(chunk.chunk_type()).is_none_or(|f| f == format) {
+                continue;
+            }
+            let text = TextChunk::try_from(chunk)?;
+            let keyword = text.keyword.clone();
+            let text = text
+                .into_format(format)
+                .map_err(|e| format!("cannot convert {}: {}", keyword, e))?;
+            converted.push((i, text.encode()?));
+        }
+        let count = converted.len();
+        for (i, chunk) in converted {
+            self.chunks[i] = chunk;
+        }
+        Ok(count)
+    }
+    /// Moves misplaced ancillary chunks to positions the specification
+    /// allows, e.g. iCCP before PLTE or text from between IDATs to after them,
+    /// keeping everything else in its original order. Returns the chunks that
+    /// were moved.
+    pub fn reorder(&mut self) -> Vec<Move> {
+        let types: Vec<_> = self.chunks.iter().map(|c| c.chunk_type()).collect();
+        let order = order::repair(&types);
+        let moves = order
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, moved))| *moved)
+            .map(|(to, &(from, _))| Move {
+                chunk_type: *self.chunks[from].chunk_type(),
+                from,
+                to,
+            })
+            .collect();
+        let mut chunks: Vec<Option<Chunk>> = self.chunks.drain(..).map(Some).collect();
+        self.chunks = order
+            .into_iter()
+            .filter_map(|(i, _)| chunks[i].take())
+            .collect();
+        moves
+    }
+    /// Finds chunks that repeat an earlier chunk: byte-identical copies of
+    /// ancillary chunks or of chunks that may appear only once, and differing
+    /// copies of the latter. Identical IDAT chunks are part of the image
+    /// and are not reported.
+    pub fn find_duplicates(&self) -> Vec<Duplicate> {
+        let mut found = Vec::new();
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            let chunk_type = *chunk.chunk_type();
+            let singleton = order::is_singleton(&chunk_type);
+            if !singleton && chunk_type.is_critical() {
+                continue;
+            }
+            let earlier = &self.chunks[..index];
+            let duplicate = match earlier.iter().position(|c| c == chunk) {
+                Some(original) => Duplicate {
+                    chunk_type,
+                    index,
+                    original,
+                    identical: true,
+                },
+                None => match earlier.iter().position(|c| *c.chunk_type() == chunk_type) {
+                    Some(original) if singleton => Duplicate {
+                        chunk_type,
+                        index,
+                        original,
+                        identical: false,
+                    },
+                    _ => continue,
+                },
+            };
+            found.push(duplicate);
+        }
+        found
+    }
+    /// Removes the byte-identical duplicates [`Png::find_duplicates`] finds,
+    /// keeping the first copy of each, and returns them. Differing copies of
+    /// singleton chunks are left for the caller to resolve.
+    pub fn dedup(&mut self) -> Vec<Duplicate> {
+        let removed: Vec<_> = self
+            .find_duplicates()
+            .into_iter()
+            .filter(|d| d.identical)
+            .collect();
+        for d in removed.iter().rev() {
+            self.chunks.remove(d.index);
+        }
+        removed
+    }
+    /// The decompressed contents of the IDAT chunks.
+    pub(crate) fn image_data(&self) -> crate::Result<Vec<u8>> {
+        let compressed: Vec<u8> = self
+            .chunks_of_type("IDAT")
+            .flat_map(|c| c.data().iter().copied())
+            .collect();
+        miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(&compressed, MAX_IMAGE_DATA)
+            .map_err(|e| format!("cannot decompress image data: {}", e).into())
+    }
+    /// Rewrites the file so that the same image and metadata always encode to
+    /// the same bytes: the IDAT chunks are merged into one, recompressed with
+    /// fixed settings, and the ancillary chunks between each pair of critical
+    /// chunks are sorted by type, keeping chunks of one type in their original
+    /// order. Animated PNGs are refused, as their frame chunks depend on
+    /// order.
+    pub fn canonicalize(&mut self) -> crate::Result<()> {
+        if self.chunks.iter().any(|c| *c.chunk_type() == ACTL) {
+            return Err("cannot canonicalize an animated PNG".into());
+        }
+
+        if let Some(first) = self.chunks.iter().position(|c| *c.chunk_type() == IDAT) {
+            let raw = self.image_data()?;
+            let data = miniz_oxide::deflate::compress_to_vec_zlib(&raw, CANONICAL_LEVEL);
+            let mut seen = 0;
+            self.chunks.retain(|c| {
+                seen += (*c.chunk_type() == IDAT) as usize;
+                *c.chunk_type() != IDAT || seen == 1
+            });
+            self.chunks[first].set_data(data);
+        }
+
+        for run in self.chunks.split_mut(|c| c.chunk_type().is_critical()) {
+            run.sort_by_key(|c| *c.chunk_type());
+        }
+        Ok(())
+    }
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let len = self
+            .chunks
+            .iter()
+            .map(|c| c.data().len() + 12)
+            .sum::<usize>()
+            + self.trailing.len();
+        let mut bytes = Vec::with_capacity(Self::STANDARD_HEADER.len() + len);
+        bytes.extend_from_slice(&Self::STANDARD_HEADER);
+        for chunk in self.chunks.iter() {
+            chunk.write_bytes(&mut bytes);
+        }
+        bytes.extend_from_slice(&self.trailing);
+        bytes
+    }
+
+    /// Memory-maps the file at `path`.
+    ///
+    /// The file must not be modified by another process while the mapping is
+    /// alive.
+    #[cfg(feature = "mmap")]
+    pub fn from_mmap<P: AsRef<std::path::Path>>(path: P) -> crate::Result<MappedPng> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: the mapping is read-only and callers are told not to modify
+        // the file while it is mapped.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(MappedPng { map })
+    }
+
+    /// Reads and parses the file at `path`. Errors are
+    /// [`FileError`](crate::error::FileError)s naming the file, with the
+    /// [`ParseError`] or I/O error as their source.
+    #[cfg(feature = "std")]
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> crate::Result<Self> {
+        Self::from_file_with_options(path, &ParseOptions::default())
+    }
+
+    /// Like [`Png::from_file`], with explicit parse options.
+    #[cfg(feature = "std")]
+    pub fn from_file_with_options<P: AsRef<std::path::Path>>(
+        path: P,
+        options: &ParseOptions,
+    ) -> crate::Result<Self> {
+        Self::from_file_with_progress(path, options, &mut |_, _| {})
+    }
+
+    /// Like [`Png::from_file_with_options`], reporting the bytes parsed
+    /// after every chunk. The chunks share the file's buffer, as with
+    /// [`Png::parse_owned`].
+    #[cfg(feature = "std")]
+    pub fn from_file_with_progress<P: AsRef<std::path::Path>>(
+        path: P,
+        options: &ParseOptions,
+        progress: &mut dyn Progress,
+    ) -> crate::Result<Self> {
+        use crate::error::FileError;
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|e| FileError::new(path, e))?;
+        Ok(Self::parse_owned_with_progress(bytes, options, progress)
+            .map_err(|e| FileError::new(path, e))?)
+    }
+
+    /// Reads a whole PNG from `source`, chunk by chunk through a
+    /// [`ChunkReader`](crate::reader::ChunkReader), keeping whatever follows
+    /// IEND as trailing data.
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: std::io::Read>(source: R) -> crate::Result<Self> {
+        Self::from_reader_with_options(source, &ParseOptions::default())
+    }
+
+    /// Like [`Png::from_reader`], with explicit parse options.
+    #[cfg(feature = "std")]
+    pub fn from_reader_with_options<R: std::io::Read>(
+        source: R,
+        options: &ParseOptions,
+    ) -> crate::Result<Self> {
+        use std::io::Read;
+
+        let mut reader = crate::reader::ChunkReader::with_options(source, options)?;
+        let chunks = reader.by_ref().collect::<crate::Result<Vec<_>>>()?;
+        let offset = reader.offset();
+        // Read no more than one byte past the size limit, so an endless
+        // stream after IEND is rejected rather than buffered.
+        let limit = options
+            .max_file_size
+            .map_or(u64::MAX, |max| max.saturating_sub(offset).saturating_add(1));
+        let mut trailing = Vec::new();
+        reader.into_inner().take(limit).read_to_end(&mut trailing)?;
+        options
+            .check_file_size(offset + trailing.len() as u64)
+            .map_err(|kind| ParseError::new(kind, 0))?;
+        options
+            .check_trailing_data(trailing.len() as u64)
+            .map_err(|kind| ParseError::new(kind, offset))?;
+        Ok(Self { chunks, trailing })
+    }
+
+    /// Writes the file to `path`, replacing anything already there.
+    #[cfg(feature = "std")]
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> crate::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.as_bytes())
+            .map_err(|e| crate::error::FileError::writing(path, e))?;
+        Ok(())
+    }
+}
+
+impl<'a> PngRef<'a> {
+    pub fn parse(value: &'a [u8], options: &ParseOptions) -> Result<Self, ParseError> {
+        Self::parse_with_progress(value, options, &mut |_, _| {})
+    }
+    /// Like [`PngRef::parse`], reporting the bytes parsed after every chunk.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(len = value.len()))
+    )]
+    pub fn parse_with_progress(
+        value: &'a [u8],
+        options: &ParseOptions,
+        progress: &mut dyn Progress,
+    ) -> Result<Self, ParseError> {
+        let total = value.len() as u64;
+        options
+            .check_file_size(value.len() as u64)
+            .map_err(|kind| ParseError::new(kind, 0))?;
+        if value.len() < Png::STANDARD_HEADER.len()
+            || value[..Png::STANDARD_HEADER.len()] != Png::STANDARD_HEADER
+        {
+            return Err(ParseError::new(ParseErrorKind::InvalidSignature, 0));
+        }
+
+        let mut remain = &value[Png::STANDARD_HEADER.len()..];
+        let mut chunks = Vec::new();
+        let mut offsets = Vec::new();
+        while !remain.is_empty() {
+            let offset = (value.len() - remain.len()) as u64;
+            let index = chunks.len();
+            options
+                .check_total_chunks(index + 1)
+                .map_err(|kind| ParseError::new(kind, offset).in_chunk(Some(index), None))?;
+            let (chunk, rest) = ChunkRef::parse_prefix(remain, offset, Some(index), options)?;
+            let is_iend = *chunk.chunk_type() == IEND;
+            chunks.push(chunk);
+            offsets.push(offset);
+            remain = rest;
+            progress.update(total - remain.len() as u64, total);
+            if is_iend {
+                let offset = (value.len() - remain.len()) as u64;
+                options
+                    .check_trailing_data(remain.len() as u64)
+                    .map_err(|kind| ParseError::new(kind, offset))?;
+                break;
+            }
+        }
+
+        let types: Vec<_> = chunks.iter().map(|c| c.chunk_type()).collect();
+        options.check_structure(&types).map_err(|(index, kind)| {
+            let offset = offsets.get(index).copied().unwrap_or(value.len() as u64);
+            let typ = chunks.get(index).map(|c| *c.chunk_type());
+            ParseError::new(kind, offset).in_chunk(Some(index), typ)
+        })?;
+        event!(debug, chunks = chunks.len(), "parsed PNG");
+        Ok(Self {
+            chunks,
+            trailing: remain,
+        })
+    }
+    /// Parses every datastream in `value`, for capture tools and polyglot
+    /// files that put several PNGs back to back. A stream whose IEND is
+    /// followed by another signature ends there, unless what follows does
+    /// not parse: it is then kept as that stream's trailing data, so one
+    /// corrupt stream does not hide the good ones before it. Only the last
+    /// stream keeps trailing data, and only it is checked against
+    /// [`ParseOptions::allow_trailing_data`].
+    pub fn parse_all(value: &'a [u8], options: &ParseOptions) -> Result<Vec<Self>, ParseError> {
+        let each = ParseOptions {
+            allow_trailing_data: true,
+            ..*options
+        };
+        let mut streams = Vec::new();
+        let mut png = Self::parse(value, &each)?;
+        loop {
+            let next = value.len() - png.trailing.len();
+            let following = match png.trailing.starts_with(&Png::STANDARD_HEADER) {
+                true => Self::parse(&value[next..], &each).ok(),
+                false => None,
+            };
+            let Some(following) = following else {
+                options
+                    .check_trailing_data(png.trailing.len() as u64)
+                    .map_err(|kind| ParseError::new(kind, next as u64))?;
+                streams.push(png);
+                event!(debug, streams = streams.len(), "parsed PNG streams");
+                return Ok(streams);
+            };
+            png.trailing = &[];
+            streams.push(png);
+            png = following;
+        }
+    }
+    pub fn header(&self) -> &[u8; 8] {
+        &Png::STANDARD_HEADER
+    }
+    pub fn chunks(&self) -> &[ChunkRef<'a>] {
+        &self.chunks
+    }
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&ChunkRef<'a>> {
+        self.chunks
+            .iter()
+            .find(|c| c.chunk_type().matches(chunk_type))
+    }
+    pub fn chunks_of_type<'s>(
+        &'s self,
+        chunk_type: &'s str,
+    ) -> impl Iterator<Item = &'s ChunkRef<'a>> {
+        self.chunks
+            .iter()
+            .filter(move |c| c.chunk_type().matches(chunk_type))
+    }
+    pub fn chunk_by_type_ignore_case(&self, chunk_type: &str) -> Option<&ChunkRef<'a>> {
+        self.chunks
+            .iter()
+            .find(|c| c.chunk_type().matches_ignore_case(chunk_type))
+    }
+    pub fn chunks_of_type_ignore_case<'s>(
+        &'s self,
+        chunk_type: &'s str,
+    ) -> impl Iterator<Item = &'s ChunkRef<'a>> {
+        self.chunks
+            .iter()
+            .filter(move |c| c.chunk_type().matches_ignore_case(chunk_type))
+    }
+    /// Inserts `chunk` at `placement` under the same ordering rules as
+    /// [`Png::insert_chunk`].
+    pub fn insert_chunk(&mut self, chunk: Chunk, placement: Placement) -> crate::Result<()> {
+        let types: Vec<_> = self.chunks.iter().map(|c| c.chunk_type()).collect();
+        let index = insertion_index(&types, chunk.chunk_type(), placement)?;
+        self.chunks.insert(index, chunk.into());
+        Ok(())
+    }
+    /// Like [`Png::remove_chunk`]; the removed chunks still borrow from the
+    /// source buffer.
+    pub fn remove_chunk(
+        &mut self,
+        chunk_type: &str,
+        which: Removal,
+    ) -> crate::Result<Vec<ChunkRef<'a>>> {
+        let types: Vec<_> = self.chunks.iter().map(|c| c.chunk_type()).collect();
+        let indices = removal_indices(&types, chunk_type, which)?;
+        let mut removed: Vec<_> = indices
+            .into_iter()
+            .rev()
+            .map(|i| self.chunks.remove(i))
+            .collect();
+        removed.reverse();
+        Ok(removed)
+    }
+    /// Like [`Png::replace_chunk`]. Only the replaced chunk owns its data
+    /// afterwards; every other chunk still borrows from the source buffer.
+    pub fn replace_chunk(
+        &mut self,
+        chunk_type: &str,
+        data: Vec<u8>,
+    ) -> crate::Result<Cow<'a, [u8]>> {
+        let chunk = self
+            .chunks
+            .iter_mut()
+            .find(|c| c.chunk_type().matches(chunk_type))
+            .ok_or("chunk not found")?;
+        Ok(chunk.set_data(data))
+    }
+    /// Serializes the file, copying each chunk's data once straight from
+    /// wherever it lives.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let len = self
+            .chunks
+            .iter()
+            .map(|c| c.data().len() + 12)
+            .sum::<usize>()
+            + self.trailing.len();
+        let mut bytes = Vec::with_capacity(Png::STANDARD_HEADER.len() + len);
+        bytes.extend_from_slice(&Png::STANDARD_HEADER);
+        for chunk in self.chunks.iter() {
+            chunk.write_bytes(&mut bytes);
+        }
+        bytes.extend_from_slice(self.trailing);
+        bytes
+    }
+    /// The bytes after IEND, empty for a well-formed file.
+    pub fn trailing_data(&self) -> &'a [u8] {
+        self.trailing
+    }
+    /// The chunks hidden in [`PngRef::trailing_data`], such as those placed
+    /// with [`Placement::AfterIend`], up to the first bytes that are not a
+    /// valid chunk.
+    pub fn trailing_chunks(&self) -> Vec<ChunkRef<'a>> {
+        let mut chunks = Vec::new();
+        let mut remain = self.trailing;
+        while let Ok((chunk, rest)) =
+            ChunkRef::parse_prefix(remain, 0, None, &ParseOptions::default())
+        {
+            chunks.push(chunk);
+            remain = rest;
+        }
+        chunks
+    }
+    /// Copies every borrowed chunk into an owned [`Png`].
+    pub fn to_png(&self) -> Png {
+        Png {
+            chunks: self.chunks.iter().map(|c| c.to_chunk()).collect(),
+            trailing: self.trailing.to_vec(),
+        }
+    }
+    /// Like [`PngRef::to_png`], moving rather than copying the data of
+    /// chunks that already own it.
+    pub fn into_png(self) -> Png {
+        Png {
+            chunks: self.chunks.into_iter().map(|c| c.into_chunk()).collect(),
+            trailing: self.trailing.to_vec(),
+        }
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl MappedPng {
+    pub fn png(&self) -> crate::Result<PngRef<'_>> {
+        self.png_with_options(&ParseOptions::default())
+    }
+    pub fn png_with_options(&self, options: &ParseOptions) -> crate::Result<PngRef<'_>> {
+        Ok(PngRef::parse(&self.map[..], options)?)
+    }
+}
+
+/// Where a chunk of type `chunk_type` goes at `placement` among chunks of
+/// `types`, refusing if it would break an ordering rule the file did not
+/// already break.
+pub(crate) fn insertion_index(
+    types: &[&ChunkType],
+    chunk_type: &ChunkType,
+    placement: Placement,
+) -> crate::Result<usize> {
+    let position = |anchor: ChunkType| types.iter().position(|t| **t == anchor);
+    let index = match placement {
+        Placement::BeforeIend => position(IEND).ok_or("no IEND chunk")?,
+        Placement::AfterIhdr => position(IHDR).ok_or("no IHDR chunk")? + 1,
+        Placement::AfterIend => return Ok(position(IEND).ok_or("no IEND chunk")? + 1),
+        Placement::BeforeFirstIdat => position(IDAT).ok_or("no IDAT chunk")?,
+        Placement::Index(i) if i <= types.len() => i,
+        Placement::Index(_) => return Err("chunk index out of range".into()),
+    };
+
+    let mut types = types.to_vec();
+    let existing = order::violations(&types);
+    types.insert(index, chunk_type);
+    if let Some(v) = order::violations(&types)
+        .into_iter()
+        .find(|v| existing.iter().all(|e| e.message != v.message))
+    {
+        return Err(format!("cannot insert {}: {}", chunk_type, v.message).into());
+    }
+    Ok(index)
+}
+
+/// Indices, in file order, of the chunks of `types` that `which` selects
+/// among those matching `chunk_type`. It is an error if nothing matched.
+pub(crate) fn removal_indices(
+    types: &[&ChunkType],
+    chunk_type: &str,
+    which: Removal,
+) -> crate::Result<Vec<usize>> {
+    let matches = types
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| t.matches(chunk_type))
+        .map(|(i, _)| i);
+    let indices: Vec<usize> = match which {
+        Removal::First => matches.take(1).collect(),
+        Removal::All => matches.collect(),
+        Removal::Nth(n) => matches.skip(n).take(1).collect(),
+    };
+    if indices.is_empty() {
+        return Err("chunk not found".into());
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::ChunksExt;
+    use crate::chunk_type::ChunkType;
+    use crate::chunk_type::consts::{ICCP, TEXT};
+    use core::str::FromStr;
+
+    fn chunk_from_strings(chunk_type: &str, data: &str) -> Chunk {
+        let chunk_type = ChunkType::from_str(chunk_type).unwrap();
+        Chunk::new(chunk_type, data.as_bytes().to_vec())
+    }
+
+    fn testing_chunks() -> Vec<Chunk> {
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk"),
+            chunk_from_strings("miDl", "I am another chunk"),
+            chunk_from_strings("LASt", "I am the last chunk"),
+        ]
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(testing_chunks())
+    }
+
+    #[test]
+    fn test_from_chunks() {
+        let png = testing_png();
+        assert_eq!(png.chunks().count(), 3);
+    }
+
+    #[test]
+    fn test_valid_from_bytes() {
+        let bytes = testing_png().as_bytes();
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.chunks().count(), 3);
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut bytes = testing_png().as_bytes();
+        bytes[0] = 13;
+        assert!(Png::try_from(bytes.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_chunk() {
+        let mut bytes = testing_png().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        assert!(Png::try_from(bytes.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let bytes = testing_png().as_bytes();
+        assert!(Png::try_from(&bytes[..bytes.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let bytes = testing_png().as_bytes();
+        let limit = |options: ParseOptions| Png::parse(&bytes, &options).is_ok();
+
+        assert!(limit(ParseOptions::default()));
+        assert!(!limit(ParseOptions {
+            max_total_chunks: Some(2),
+            ..ParseOptions::default()
+        }));
+        assert!(!limit(ParseOptions {
+            max_file_size: Some(bytes.len() as u64 - 1),
+            ..ParseOptions::default()
+        }));
+        assert!(!limit(ParseOptions {
+            max_chunk_len: Some(19),
+            ..ParseOptions::default()
+        }));
+        assert!(limit(ParseOptions {
+            max_chunk_len: Some(20),
+            ..ParseOptions::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_strictness() {
+        let mut bytes = standard_png().as_bytes();
+        assert!(Png::parse(&bytes, &ParseOptions::strict()).is_ok());
+
+        bytes.extend(b"trailing");
+        assert!(Png::parse(&bytes, &ParseOptions::strict()).is_err());
+        let png = Png::parse(&bytes, &ParseOptions::default()).unwrap();
+        assert_eq!(types(&png), ["IHDR", "PLTE", "IDAT", "IDAT", "IEND"]);
+
+        let unordered = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header"),
+            chunk_from_strings("IDAT", "data"),
+            chunk_from_strings("PLTE", "palette"),
+        ])
+        .as_bytes();
+        assert!(Png::parse(&unordered, &ParseOptions::default()).is_ok());
+        assert!(
+            Png::parse(
+                &unordered,
+                &ParseOptions {
+                    enforce_ordering: true,
+                    ..ParseOptions::default()
+                }
+            )
+            .is_err()
+        );
+        assert!(
+            Png::parse(
+                &unordered,
+                &ParseOptions {
+                    require_iend: true,
+                    ..ParseOptions::default()
+                }
+            )
+            .is_err()
+        );
+
+        let unknown = testing_png().as_bytes();
+        assert!(
+            Png::parse(
+                &unknown,
+                &ParseOptions {
+                    allow_unknown_critical: false,
+                    ..ParseOptions::default()
+                }
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let mut bytes = testing_png().as_bytes();
+        // Corrupt the data of the second chunk, which starts after the
+        // signature and the 32-byte first chunk.
+        bytes[8 + 32 + 10] ^= 0xff;
+        let error = Png::try_from(bytes.as_ref()).unwrap_err();
+        assert_eq!(error.chunk_index(), Some(1));
+        assert_eq!(error.chunk_type().unwrap().to_string(), "miDl");
+        assert_eq!(error.offset(), 8 + 32 + 8 + 18);
+
+        let mut bytes = standard_png().as_bytes();
+        bytes.extend(b"junk");
+        let error = Png::parse(&bytes, &ParseOptions::strict()).unwrap_err();
+        assert_eq!(error.kind(), &ParseErrorKind::TrailingData { length: 4 });
+        assert_eq!(error.offset(), bytes.len() as u64 - 4);
+
+        let bytes = testing_png().as_bytes();
+        let error = Png::parse(&bytes, &ParseOptions::strict()).unwrap_err();
+        assert_eq!(error.kind(), &ParseErrorKind::MissingIend);
+        assert_eq!(error.offset(), bytes.len() as u64);
+    }
+
+    #[test]
+    fn test_parse_lenient_crc() {
+        let mut bytes = testing_png().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        assert!(Png::parse(&bytes, &ParseOptions::default()).is_err());
+        assert!(Png::parse(&bytes, &ParseOptions::lenient()).is_ok());
+    }
+
+    #[test]
+    fn test_parse_with_progress() {
+        let bytes = testing_png().as_bytes();
+        let mut seen = Vec::new();
+        let png = Png::parse_with_progress(&bytes, &ParseOptions::default(), &mut |p, t| {
+            seen.push((p, t))
+        })
+        .unwrap();
+        assert_eq!(png, testing_png());
+        let total = bytes.len() as u64;
+        assert_eq!(seen.len(), png.chunks().count());
+        assert!(seen.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(seen.last(), Some(&(total, total)));
+    }
+
+    #[test]
+    fn test_parse_owned_shares_the_buffer() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("IEND", ""));
+        png.set_trailing_data(b"tail".to_vec());
+        let bytes = png.as_bytes();
+        let mut parsed = Png::parse_owned(bytes.clone(), &ParseOptions::default()).unwrap();
+        assert_eq!(parsed, png);
+        assert!(parsed.chunks().all(|c| c.is_shared()));
+
+        parsed.replace_chunk("miDl", b"changed".to_vec()).unwrap();
+        let shared: Vec<_> = parsed.chunks().map(|c| c.is_shared()).collect();
+        assert_eq!(shared, [true, false, true, true]);
+        png.replace_chunk("miDl", b"changed".to_vec()).unwrap();
+        assert_eq!(parsed.as_bytes(), png.as_bytes());
+        assert!(Png::parse_owned(b"nope".to_vec(), &ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("IEND", ""));
+        let first = png.as_bytes();
+        let mut second = Png::from_chunks(vec![chunk_from_strings("IEND", "")]);
+        second.set_trailing_data(b"tail".to_vec());
+        let bytes = [first.clone(), second.as_bytes()].concat();
+
+        let all = Png::parse_all(&bytes, &ParseOptions::default()).unwrap();
+        assert_eq!(all, [png.clone(), second.clone()]);
+        assert_eq!(
+            Png::parse_all(&first, &ParseOptions::default()).unwrap(),
+            [png]
+        );
+        let one = Png::parse(&bytes, &ParseOptions::default()).unwrap();
+        assert_eq!(one.trailing_data(), &bytes[first.len()..]);
+
+        let strict = ParseOptions {
+            allow_trailing_data: false,
+            ..ParseOptions::default()
+        };
+        let e = Png::parse_all(&bytes, &strict).unwrap_err();
+        assert_eq!(*e.kind(), ParseErrorKind::TrailingData { length: 4 });
+        assert_eq!(e.offset(), bytes.len() as u64 - 4);
+        second.trim();
+        let bytes = [first.clone(), second.as_bytes()].concat();
+        assert_eq!(Png::parse_all(&bytes, &strict).unwrap().len(), 2);
+
+        // A corrupt later stream is trailing data of the one before it.
+        let mut broken = bytes.clone();
+        let last = broken.len() - 1;
+        broken[last] ^= 0xff;
+        let all = Png::parse_all(&broken, &ParseOptions::default()).unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].trailing_data(), &broken[first.len()..]);
+        let e = Png::parse_all(&broken, &strict).unwrap_err();
+        assert_eq!(e.offset(), first.len() as u64);
+        assert!(Png::parse_all(&broken[first.len()..], &ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_append_chunk() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "Message"));
+        assert_eq!(png.chunks().count(), 4);
+        assert_eq!(
+            &png.chunks().last().unwrap().chunk_type().to_string(),
+            "TeSt"
+        );
+    }
+
+    fn standard_png() -> Png {
+        Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header"),
+            chunk_from_strings("PLTE", "palette"),
+            chunk_from_strings("IDAT", "data"),
+            chunk_from_strings("IDAT", "more data"),
+            chunk_from_strings("IEND", ""),
+        ])
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks().map(|c| c.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_insert_chunk() {
+        let mut png = standard_png();
+        png.insert_chunk(chunk_from_strings("tEXt", "text"), Placement::BeforeIend)
+            .unwrap();
+        png.insert_chunk(chunk_from_strings("iCCP", "profile"), Placement::AfterIhdr)
+            .unwrap();
+        png.insert_chunk(
+            chunk_from_strings("tRNS", "alpha"),
+            Placement::BeforeFirstIdat,
+        )
+        .unwrap();
+        png.insert_chunk(chunk_from_strings("ruSt", "mine"), Placement::Index(0))
+            .unwrap_err();
+        png.insert_chunk(chunk_from_strings("ruSt", "mine"), Placement::Index(1))
+            .unwrap();
+
+        assert_eq!(
+            types(&png),
+            [
+                "IHDR", "ruSt", "iCCP", "PLTE", "tRNS", "IDAT", "IDAT", "tEXt", "IEND"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_insert_chunk_refuses_bad_order() {
+        let mut png = standard_png();
+        let before = png.as_bytes();
+
+        assert!(
+            png.insert_chunk(chunk_from_strings("IHDR", "again"), Placement::BeforeIend)
+                .is_err()
+        );
+        assert!(
+            png.insert_chunk(chunk_from_strings("iCCP", "late"), Placement::BeforeIend)
+                .is_err()
+        );
+        assert!(
+            png.insert_chunk(chunk_from_strings("tEXt", "split"), Placement::Index(3))
+                .is_err()
+        );
+        assert!(
+            png.insert_chunk(chunk_from_strings("tEXt", "end"), Placement::Index(5))
+                .is_err()
+        );
+        assert!(
+            png.insert_chunk(chunk_from_strings("tEXt", "far"), Placement::Index(9))
+                .is_err()
+        );
+        assert_eq!(png.as_bytes(), before);
+    }
+
+    #[test]
+    fn test_insert_chunk_after_iend() {
+        let mut png = standard_png();
+        let payload = chunk_from_strings("ruSt", "hidden");
+        png.insert_chunk(payload.clone(), Placement::AfterIend)
+            .unwrap();
+        assert_eq!(types(&png).last().unwrap(), "ruSt");
+
+        let bytes = png.as_bytes();
+        let parsed = PngRef::try_from(bytes.as_ref()).unwrap();
+        assert!(parsed.chunk_by_type("ruSt").is_none());
+        assert_eq!(parsed.trailing_data().len(), payload.as_bytes().len());
+        assert_eq!(parsed.trailing_chunks(), [ChunkRef::from(payload)]);
+        assert!(Png::parse(&bytes, &ParseOptions::strict()).is_err());
+
+        let mut bytes = standard_png().as_bytes();
+        bytes.extend(b"junk");
+        let parsed = PngRef::try_from(bytes.as_ref()).unwrap();
+        assert!(parsed.trailing_chunks().is_empty());
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut bytes = standard_png().as_bytes();
+        bytes.extend(b"PK\x03\x04zip");
+        let mut png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.trailing_data(), b"PK\x03\x04zip");
+        assert_eq!(png.as_bytes(), bytes);
+
+        assert_eq!(png.trim(), b"PK\x03\x04zip");
+        assert_eq!(png, standard_png());
+    }
+
+    #[test]
+    fn test_png_debug() {
+        let mut bytes = standard_png().as_bytes();
+        bytes.extend([0x50; 100]);
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        let preview = "trailing: <100 bytes: 50 50 50 50 50 50 50 50 50 50 50 50 50 50 50 50 ...>";
+        let debug = format!("{:?}", png);
+        assert!(
+            debug.starts_with("Png { chunks: [Chunk { chunk_type: IHDR"),
+            "{}",
+            debug
+        );
+        assert!(debug.ends_with(&format!("{} }}", preview)), "{}", debug);
+        let debug = format!("{:?}", PngRef::try_from(bytes.as_ref()).unwrap());
+        assert!(debug.ends_with(&format!("{} }}", preview)), "{}", debug);
+    }
+
+    #[test]
+    fn test_insert_chunk_missing_anchor() {
+        let mut png = testing_png();
+        assert!(
+            png.insert_chunk(chunk_from_strings("tEXt", "text"), Placement::BeforeIend)
+                .is_err()
+        );
+        assert!(
+            png.insert_chunk(chunk_from_strings("tEXt", "text"), Placement::AfterIhdr)
+                .is_err()
+        );
+        assert!(
+            png.insert_chunk(
+                chunk_from_strings("tEXt", "text"),
+                Placement::BeforeFirstIdat
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_remove_chunk() {
+        let mut png = testing_png();
+        let removed = png.remove_chunk("miDl", Removal::First).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(&removed[0].chunk_type().to_string(), "miDl");
+        assert_eq!(png.chunks().count(), 2);
+        assert!(png.remove_chunk("miDl", Removal::First).is_err());
+    }
+
+    #[test]
+    fn test_replace_chunk() {
+        let mut png = testing_png();
+        let old = png.replace_chunk("miDl", b"I was edited".to_vec()).unwrap();
+        assert_eq!(old, b"I am another chunk");
+        assert_eq!(types(&png), ["FrSt", "miDl", "LASt"]);
+
+        let bytes = png.as_bytes();
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(
+            &png.chunk_by_type("miDl").unwrap().data_as_string().unwrap(),
+            "I was edited"
+        );
+    }
+
+    #[test]
+    fn test_set_text() {
+        let mut png = standard_png();
+        png.set_text("Title", "first").unwrap();
+        png.set_text("Author", "ünïcode ✓").unwrap();
+        png.set_text("Title", "second").unwrap();
+        assert_eq!(
+            types(&png),
+            ["IHDR", "PLTE", "IDAT", "IDAT", "tEXt", "iTXt", "IEND"]
+        );
+
+        let texts: Vec<_> = png
+            .text_chunks()
+            .map(|t| t.map(|t| (t.keyword, t.text)).unwrap())
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                ("Title".to_string(), "second".to_string()),
+                ("Author".to_string(), "ünïcode ✓".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_xmp() {
+        let mut png = standard_png();
+        assert_eq!(png.xmp().unwrap(), None);
+        png.set_text("Title", "title").unwrap();
+        png.set_xmp("<x:xmpmeta/>").unwrap();
+        assert_eq!(
+            types(&png),
+            ["IHDR", "PLTE", "iTXt", "IDAT", "IDAT", "tEXt", "IEND"]
+        );
+        let chunk = png.chunk_by_type("iTXt").unwrap();
+        assert_eq!(chunk.data(), b"XML:com.adobe.xmp\0\0\0\0\0<x:xmpmeta/>");
+        assert_eq!(png.xmp().unwrap().as_deref(), Some("<x:xmpmeta/>"));
+
+        let second = TextChunk::new(TextFormat::Itxt, XMP_KEYWORD, "old");
+        png.append_chunk(second.encode().unwrap());
+        png.set_xmp("<x:xmpmeta>new</x:xmpmeta>").unwrap();
+        assert_eq!(
+            types(&png),
+            ["IHDR", "PLTE", "iTXt", "IDAT", "IDAT", "tEXt", "IEND"]
+        );
+        assert_eq!(
+            png.xmp().unwrap().as_deref(),
+            Some("<x:xmpmeta>new</x:xmpmeta>")
+        );
+
+        // An undecodable text chunk ahead of the packet is skipped.
+        png.insert_chunk(
+            chunk_from_strings("tEXt", "no separator"),
+            Placement::AfterIhdr,
+        )
+        .unwrap();
+        assert!(png.text_chunks().any(|t| t.is_err()));
+        assert_eq!(
+            png.xmp().unwrap().as_deref(),
+            Some("<x:xmpmeta>new</x:xmpmeta>")
+        );
+
+        assert_eq!(png.remove_xmp(), 1);
+        assert_eq!(png.remove_xmp(), 0);
+        assert_eq!(png.xmp().unwrap(), None);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let mut png = standard_png();
+        png.set_text("Title", "title").unwrap();
+        let text = png.chunk_by_type("tEXt").unwrap().clone();
+        let gama = Chunk::new(ChunkType::from_str("gAMA").unwrap(), vec![0, 0, 0, 1]);
+        let other_gama = Chunk::new(ChunkType::from_str("gAMA").unwrap(), vec![0, 0, 0, 2]);
+        png.insert_chunk(gama.clone(), Placement::AfterIhdr)
+            .unwrap();
+        png.append_chunk(text.clone());
+        png.append_chunk(gama);
+        png.append_chunk(other_gama);
+        png.append_chunk(text);
+        assert_eq!(
+            types(&png),
+            [
+                "IHDR", "gAMA", "PLTE", "IDAT", "IDAT", "tEXt", "IEND", "tEXt", "gAMA", "gAMA",
+                "tEXt"
+            ]
+        );
+
+        let found: Vec<_> = png
+            .find_duplicates()
+            .iter()
+            .map(|d| (d.index, d.original, d.identical))
+            .collect();
+        assert_eq!(
+            found,
+            [(7, 5, true), (8, 1, true), (9, 1, false), (10, 5, true)]
+        );
+        assert_eq!(png.dedup().len(), 3);
+        assert_eq!(
+            types(&png),
+            [
+                "IHDR", "gAMA", "PLTE", "IDAT", "IDAT", "tEXt", "IEND", "gAMA"
+            ]
+        );
+        assert_eq!(png.find_duplicates().len(), 1);
+        assert!(png.dedup().is_empty());
+    }
+
+    #[test]
+    fn test_convert_text() {
+        let mut png = standard_png();
+        png.set_text("Title", "title").unwrap();
+        png.set_text("Author", "author").unwrap();
+        assert_eq!(png.convert_text(TextFormat::Ztxt).unwrap(), 2);
+        assert_eq!(png.convert_text(TextFormat::Ztxt).unwrap(), 0);
+        assert_eq!(
+            types(&png),
+            ["IHDR", "PLTE", "IDAT", "IDAT", "zTXt", "zTXt", "IEND"]
+        );
+
+        png.set_text("Title", "✓").unwrap();
+        let before = png.clone();
+        assert!(png.convert_text(TextFormat::Text).is_err());
+        assert_eq!(png, before);
+        assert_eq!(png.convert_text(TextFormat::Itxt).unwrap(), 1);
+        assert_eq!(png.text_chunks().count(), 2);
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let raw = b"scanlines ".repeat(100);
+        let idat = miniz_oxide::deflate::compress_to_vec_zlib(&raw, 1);
+        let (head, tail) = idat.split_at(idat.len() / 2);
+        let build = |text_first: bool, idats: Vec<Chunk>| {
+            let mut chunks = vec![
+                chunk_from_strings("IHDR", "header"),
+                chunk_from_strings("tEXt", "Title\0a"),
+                chunk_from_strings("pHYs", "phys"),
+                chunk_from_strings("tEXt", "Title\0b"),
+            ];
+            if text_first {
+                chunks.swap(1, 2);
+            }
+            chunks.extend(idats);
+            chunks.push(chunk_from_strings("IEND", ""));
+            Png::from_chunks(chunks)
+        };
+
+        let mut split = build(
+            false,
+            vec![
+                Chunk::new(IDAT, head.to_vec()),
+                Chunk::new(IDAT, tail.to_vec()),
+            ],
+        );
+        let mut whole = build(true, vec![Chunk::new(IDAT, idat.clone())]);
+        split.canonicalize().unwrap();
+        whole.canonicalize().unwrap();
+        assert_eq!(split, whole);
+        assert_eq!(
+            types(&split),
+            ["IHDR", "pHYs", "tEXt", "tEXt", "IDAT", "IEND"]
+        );
+        assert_eq!(split.chunks[2].data(), b"Title\0a");
+        let data = split.chunk_by_type("IDAT").unwrap().data();
+        assert_eq!(
+            miniz_oxide::inflate::decompress_to_vec_zlib(data).unwrap(),
+            raw
+        );
+
+        let mut broken = build(false, vec![Chunk::new(IDAT, b"not zlib".to_vec())]);
+        assert!(broken.canonicalize().is_err());
+        let mut animated = build(false, vec![Chunk::new(IDAT, idat)]);
+        animated.append_chunk(chunk_from_strings("acTL", "frames"));
+        assert!(animated.canonicalize().is_err());
+    }
+
+    #[test]
+    fn test_reorder() {
+        let mut png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header"),
+            chunk_from_strings("PLTE", "palette"),
+            chunk_from_strings("iCCP", "profile"),
+            chunk_from_strings("IDAT", "data"),
+            chunk_from_strings("tEXt", "text"),
+            chunk_from_strings("IDAT", "more data"),
+            chunk_from_strings("IEND", ""),
+        ]);
+        let moves = png.reorder();
+        assert_eq!(
+            types(&png),
+            ["IHDR", "iCCP", "PLTE", "IDAT", "IDAT", "tEXt", "IEND"]
+        );
+        assert_eq!(
+            moves,
+            [
+                Move {
+                    chunk_type: ICCP,
+                    from: 2,
+                    to: 1
+                },
+                Move {
+                    chunk_type: TEXT,
+                    from: 4,
+                    to: 5
+                },
+            ]
+        );
+        assert_eq!(png.chunk_by_type("iCCP").unwrap().data(), b"profile");
+        assert!(png.reorder().is_empty());
+    }
+
+    #[test]
+    fn test_replace_missing_chunk() {
+        let mut png = testing_png();
+        assert!(png.replace_chunk("NoNe", Vec::new()).is_err());
+    }
+
+    fn png_with_duplicates() -> Png {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "second"));
+        png.append_chunk(chunk_from_strings("miDl", "third"));
+        png
+    }
+
+    #[test]
+    fn test_remove_all_chunks() {
+        let mut png = png_with_duplicates();
+        let removed = png.remove_chunk("miDl", Removal::All).unwrap();
+        let data: Vec<_> = removed
+            .iter()
+            .map(|c| c.data_as_string().unwrap())
+            .collect();
+        assert_eq!(data, ["I am another chunk", "second", "third"]);
+        assert_eq!(types(&png), ["FrSt", "LASt"]);
+    }
+
+    #[test]
+    fn test_remove_nth_chunk() {
+        let mut png = png_with_duplicates();
+        let removed = png.remove_chunk("miDl", Removal::Nth(1)).unwrap();
+        assert_eq!(&removed[0].data_as_string().unwrap(), "second");
+        assert_eq!(png.chunks_of_type("miDl").count(), 2);
+        assert!(png.remove_chunk("miDl", Removal::Nth(2)).is_err());
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let png = testing_png();
+        let types: Vec<_> = (&png)
+            .into_iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["FrSt", "miDl", "LASt"]);
+
+        let chunks: Vec<Chunk> = png.into_iter().collect();
+        assert_eq!(chunks.len(), 3);
+    }
+
+    #[test]
+    fn test_filter_ancillary() {
+        let png = testing_png();
+        let ancillary: Vec<_> = png.chunks().filter_ancillary().collect();
+        assert_eq!(ancillary.len(), 1);
+        assert_eq!(&ancillary[0].chunk_type().to_string(), "miDl");
+        assert_eq!(png.chunks().filter_critical().count(), 2);
+    }
+
+    #[test]
+    fn test_chunk_by_type() {
+        let png = testing_png();
+        let chunk = png.chunk_by_type("FrSt").unwrap();
+        assert_eq!(&chunk.data_as_string().unwrap(), "I am the first chunk");
+        assert!(png.chunk_by_type("frst").is_none());
+        assert!(png.chunk_by_type("NoNe").is_none());
+    }
+
+    #[test]
+    fn test_chunks_of_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am a second middle chunk"));
+        png.append_chunk(chunk_from_strings("MIDL", "I differ only in case"));
+
+        let data: Vec<_> = png
+            .chunks_of_type("miDl")
+            .map(|c| c.data_as_string().unwrap())
+            .collect();
+        assert_eq!(data, ["I am another chunk", "I am a second middle chunk"]);
+        assert_eq!(png.chunks_of_type_ignore_case("midl").count(), 3);
+        assert_eq!(
+            &png.chunk_by_type_ignore_case("MiDl")
+                .unwrap()
+                .data_as_string()
+                .unwrap(),
+            "I am another chunk"
+        );
+    }
+
+    #[test]
+    fn test_png_ref_chunk_by_type() {
+        let bytes = testing_png().as_bytes();
+        let png = PngRef::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(
+            png.chunk_by_type("LASt").unwrap().data(),
+            b"I am the last chunk"
+        );
+        assert_eq!(png.chunks_of_type_ignore_case("last").count(), 1);
+    }
+
+    #[test]
+    fn test_png_ref_borrows_input() {
+        let bytes = testing_png().as_bytes();
+        let png = PngRef::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.chunks().len(), 3);
+
+        let data = png.chunks()[1].data();
+        assert_eq!(data, b"I am another chunk");
+        assert!(bytes.as_ptr_range().contains(&data.as_ptr()));
+    }
+
+    #[test]
+    fn test_png_ref_to_png() {
+        let bytes = testing_png().as_bytes();
+        let png = PngRef::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.to_png().as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_png_ref_edits_copy_only_touched_chunks() {
+        let bytes = testing_png().as_bytes();
+        let mut png = PngRef::try_from(bytes.as_ref()).unwrap();
+        let old = png.replace_chunk("miDl", b"changed".to_vec()).unwrap();
+        assert_eq!(&*old, b"I am another chunk");
+        png.insert_chunk(chunk_from_strings("ruSt", "added"), Placement::Index(3))
+            .unwrap();
+        let removed = png.remove_chunk("FrSt", Removal::First).unwrap();
+        assert!(!removed[0].is_owned());
+
+        let owned: Vec<_> = png.chunks().iter().map(|c| c.is_owned()).collect();
+        assert_eq!(owned, [true, false, true]);
+
+        let mut expected = testing_png();
+        expected.replace_chunk("miDl", b"changed".to_vec()).unwrap();
+        expected.append_chunk(chunk_from_strings("ruSt", "added"));
+        expected.remove_chunk("FrSt", Removal::First).unwrap();
+        assert_eq!(png.as_bytes(), expected.as_bytes());
+        assert_eq!(png.into_png(), expected);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_from_file_and_save() {
+        use crate::error::FileError;
+        use core::error::Error;
+        let path = std::env::temp_dir().join(format!("pngme-save-{}.png", std::process::id()));
+        testing_png().save(&path).unwrap();
+        assert_eq!(Png::from_file(&path).unwrap(), testing_png());
+
+        std::fs::write(&path, b"not a png").unwrap();
+        let error = Png::from_file(&path).unwrap_err();
+        assert!(error.to_string().starts_with(&path.display().to_string()));
+        let error = error.downcast_ref::<FileError>().unwrap();
+        assert_eq!(error.path(), path);
+        let parse_error = error.source().unwrap().downcast_ref::<ParseError>();
+        assert_eq!(parse_error, error.parse_error());
+        assert_eq!(
+            parse_error.unwrap().kind(),
+            &ParseErrorKind::InvalidSignature
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let error = Png::from_file(&path).unwrap_err();
+        assert!(error.to_string().contains(&path.display().to_string()));
+        let error = error.downcast_ref::<FileError>().unwrap();
+        assert_eq!(
+            error.io_error().unwrap().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        let missing = path.join("dir").join("file.png");
+        let error = testing_png().save(&missing).unwrap_err();
+        assert!(error.to_string().starts_with("cannot write"));
+        let error = error.downcast_ref::<FileError>().unwrap();
+        assert_eq!(error.path(), missing);
+        assert_eq!(
+            error.io_error().unwrap().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_from_reader() {
+        let mut chunks = testing_chunks();
+        chunks.push(Chunk::new(IEND, Vec::new()));
+        let mut png = Png::from_chunks(chunks);
+        png.set_trailing_data(b"tail".to_vec());
+        let bytes = png.as_bytes();
+        assert_eq!(Png::from_reader(bytes.as_slice()).unwrap(), png);
+        let strict = ParseOptions::strict();
+        assert!(Png::from_reader_with_options(bytes.as_slice(), &strict).is_err());
+
+        let without_iend = &bytes[..bytes.len() - 16];
+        assert_eq!(Png::from_reader(without_iend).unwrap(), testing_png());
+        assert!(Png::from_reader_with_options(without_iend, &strict).is_err());
+        assert!(Png::from_reader(&bytes[..bytes.len() - 20]).is_err());
+
+        // Endless bytes after IEND fail at the size limit instead of being
+        // read forever.
+        let clean = &bytes[..bytes.len() - 4];
+        let options = ParseOptions {
+            max_file_size: Some(1 << 20),
+            ..ParseOptions::default()
+        };
+        let endless = std::io::Read::chain(clean, std::io::repeat(0));
+        let error = Png::from_reader_with_options(endless, &options).unwrap_err();
+        let error = error.downcast_ref::<ParseError>().unwrap();
+        assert!(matches!(error.kind(), ParseErrorKind::FileTooLarge { .. }));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_from_mmap() {
+        let bytes = testing_png().as_bytes();
+        let path = std::env::temp_dir().join(format!("pngme-mmap-{}.png", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mapped = Png::from_mmap(&path).unwrap();
+        let png = mapped.png().unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.chunks()[2].data(), b"I am the last chunk");
+
+        drop(mapped);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_png_clone_eq() {
+        let png = testing_png();
+        let mut copy = png.clone();
+        assert_eq!(copy, png);
+
+        copy.replace_chunk("miDl", b"changed".to_vec()).unwrap();
+        assert_ne!(copy, png);
+        assert_eq!(
+            format!("{:?}", copy.chunk_by_type("miDl").unwrap()),
+            "Chunk { chunk_type: miDl, length: 7, crc: 0xe7904012 }"
+        );
+    }
+
+    #[test]
+    fn test_png_trait_impls() {
+        let bytes = testing_png().as_bytes();
+        let png: Png = TryFrom::try_from(bytes.as_ref()).unwrap();
+        let _png_string = format!("{}", png);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::chunk::ChunkRef;
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::{ACTL, IDAT, IEND, IHDR, TRNS};
+use crate::image::ImageHeader;
+use crate::options::ParseOptions;
+use crate::png::Png;
+use crate::reader::read_full;
+use std::io::{self, Read};
+
+/// Chunks [`probe`] looks at after IHDR before giving up on finding acTL or
+/// tRNS, so a file padded with metadata is not read to the end.
+const MAX_PEEK: usize = 32;
+
+/// Bytes of chunk data [`probe`] reads past in all before giving up, so one
+/// huge chunk ahead of the image data cannot make it read the whole file.
+const MAX_SKIP: u64 = 64 * 1024;
+
+/// What [`probe`] learned about a file. Every field but `is_png` is zero or
+/// false when it is not a PNG.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Probe {
+    /// The signature is right and IHDR comes first, with a valid CRC and
+    /// valid fields.
+    pub is_png: bool,
+    pub width: u32,
+    pub height: u32,
+    pub color_type: u8,
+    /// The colour type has an alpha channel or a tRNS chunk precedes the
+    /// image data.
+    pub has_alpha: bool,
+    /// An acTL chunk precedes the image data.
+    pub is_apng: bool,
+}
+
+/// Checks whether `source` holds a PNG and reads its size, the cheap check
+/// to make before accepting an upload. Only the signature, IHDR and the
+/// chunk headers up to the first IDAT are looked at. The data of the chunks
+/// in between is read and discarded, since `source` need not seek, so the
+/// search stops after [`MAX_PEEK`] chunks or [`MAX_SKIP`] bytes of data.
+/// Only I/O errors fail: anything else that is not a PNG gives a probe with
+/// `is_png` false.
+pub fn probe(mut source: impl Read) -> crate::Result<Probe> {
+    let mut header = [0u8; 8 + 25];
+    if read_full(&mut source, &mut header)? < header.len() || header[..8] != Png::STANDARD_HEADER {
+        return Ok(Probe::default());
+    }
+    let Ok(ihdr) = ChunkRef::parse(&header[8..], &ParseOptions::default()) else {
+        return Ok(Probe::default());
+    };
+    if *ihdr.chunk_type() != IHDR {
+        return Ok(Probe::default());
+    }
+    let Ok(image) = ImageHeader::parse(ihdr.data()) else {
+        return Ok(Probe::default());
+    };
+
+    let mut probe = Probe {
+        is_png: true,
+        width: image.width,
+        height: image.height,
+        color_type: image.color_type,
+        has_alpha: matches!(image.color_type, 4 | 6),
+        is_apng: false,
+    };
+    let mut budget = MAX_SKIP;
+    for _ in 0..MAX_PEEK {
+        let mut prefix = [0u8; 8];
+        if read_full(&mut source, &mut prefix)? < prefix.len() {
+            break;
+        }
+        let length = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+        let Ok(typ) = ChunkType::try_from([prefix[4], prefix[5], prefix[6], prefix[7]]) else {
+            break;
+        };
+        match typ {
+            IDAT | IEND => break,
+            ACTL => probe.is_apng = true,
+            TRNS => probe.has_alpha = true,
+            _ => {}
+        }
+        let skip = length as u64 + 4;
+        if skip > budget {
+            break;
+        }
+        budget -= skip;
+        if io::copy(&mut (&mut source).take(skip), &mut io::sink())? < skip {
+            break;
+        }
+    }
+    Ok(probe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+
+    fn png(color_type: u8, before_idat: &[Chunk]) -> Vec<u8> {
+        let mut ihdr = Vec::new();
+        ihdr.extend(3u32.to_be_bytes());
+        ihdr.extend(2u32.to_be_bytes());
+        ihdr.extend([8, color_type, 0, 0, 0]);
+        let mut chunks = vec![Chunk::new(IHDR, ihdr)];
+        chunks.extend(before_idat.iter().cloned());
+        chunks.push(Chunk::new(IDAT, Vec::new()));
+        chunks.push(Chunk::new(IEND, Vec::new()));
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_probe() {
+        let probe = probe(&png(2, &[])[..]).unwrap();
+        assert_eq!(
+            probe,
+            Probe {
+                is_png: true,
+                width: 3,
+                height: 2,
+                color_type: 2,
+                has_alpha: false,
+                is_apng: false,
+            }
+        );
+        assert!(super::probe(&png(6, &[])[..]).unwrap().has_alpha);
+
+        let actl = Chunk::new(ACTL, vec![0, 0, 0, 1, 0, 0, 0, 0]);
+        let trns = Chunk::new(TRNS, vec![0, 0, 0, 0, 0, 0]);
+        let probe = super::probe(&png(2, &[actl, trns])[..]).unwrap();
+        assert!(probe.is_apng && probe.has_alpha);
+
+        // acTL behind more metadata than the budget allows goes unseen.
+        let padding = Chunk::new(
+            ChunkType::try_from(*b"teXt").unwrap(),
+            vec![0; MAX_SKIP as usize],
+        );
+        let actl = Chunk::new(ACTL, vec![0, 0, 0, 1, 0, 0, 0, 0]);
+        assert!(!super::probe(&png(2, &[padding, actl])[..]).unwrap().is_apng);
+    }
+
+    #[test]
+    fn test_probe_not_png() {
+        assert!(!probe(&b"GIF89a"[..]).unwrap().is_png);
+        let mut bytes = png(2, &[]);
+        bytes[20] ^= 1;
+        assert_eq!(probe(&bytes[..]).unwrap(), Probe::default());
+        // A truncated file still probes once IHDR is whole.
+        assert!(probe(&png(2, &[])[..33]).unwrap().is_png);
+        assert!(!probe(&png(2, &[])[..32]).unwrap().is_png);
+    }
+}
@@ -0,0 +1,29 @@
+/// Receives progress reports from long-running operations such as parsing a
+/// large file.
+///
+/// Any `FnMut(u64, u64)` closure is a `Progress`.
+pub trait Progress {
+    /// Called with the number of bytes processed so far and the total number
+    /// of bytes the operation will process.
+    fn update(&mut self, processed: u64, total: u64);
+}
+
+impl<F: FnMut(u64, u64)> Progress for F {
+    fn update(&mut self, processed: u64, total: u64) {
+        self(processed, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closure_progress() {
+        let mut seen = Vec::new();
+        let mut progress = |processed, total| seen.push((processed, total));
+        progress.update(1, 2);
+        progress.update(2, 2);
+        assert_eq!(seen, [(1, 2), (2, 2)]);
+    }
+}
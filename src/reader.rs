@@ -0,0 +1,274 @@
+use crate::chunk::{Checksum, Chunk};
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::IEND;
+use crate::error::{ParseError, ParseErrorKind};
+use crate::options::ParseOptions;
+use crate::png::Png;
+use std::io::{ErrorKind, Read};
+
+/// Reads chunks one at a time from any reader, so a caller can stop as soon
+/// as it has what it needs, e.g. the first tEXt chunk of a download, without
+/// reading the image data that follows.
+///
+/// The signature is checked up front. Iteration ends after IEND, or when the
+/// reader ends cleanly between chunks. After an error the iterator yields
+/// nothing more.
+///
+/// The file size limit is checked against each chunk before its data is
+/// read, and the structure checks once IEND or the end of the reader is
+/// reached, failing in place of the last chunk.
+pub struct ChunkReader<R> {
+    source: R,
+    options: ParseOptions,
+    /// Offset of the next chunk from the start of the signature.
+    offset: u64,
+    index: usize,
+    /// Types of the chunks read so far, for the structure checks.
+    types: Vec<ChunkType>,
+    done: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub fn new(source: R) -> crate::Result<Self> {
+        Self::with_options(source, &ParseOptions::default())
+    }
+
+    /// Reads and checks the signature, applying `options` to every chunk
+    /// read afterwards.
+    pub fn with_options(mut source: R, options: &ParseOptions) -> crate::Result<Self> {
+        let mut header = [0u8; 8];
+        if source.read_exact(&mut header).is_err() || header != Png::STANDARD_HEADER {
+            return Err(ParseError::new(ParseErrorKind::InvalidSignature, 0).into());
+        }
+        Ok(Self {
+            source,
+            options: *options,
+            offset: header.len() as u64,
+            index: 0,
+            types: Vec::new(),
+            done: false,
+        })
+    }
+
+    /// Offset of the next unread byte from the start of the signature.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the reader, positioned after the last chunk read. After IEND
+    /// that is at the start of any trailing data.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    fn read_chunk(&mut self) -> crate::Result<Option<Chunk>> {
+        let (offset, index) = (self.offset, Some(self.index));
+        let error = |kind, at: u64, typ| ParseError::new(kind, offset + at).in_chunk(index, typ);
+        self.options
+            .check_total_chunks(self.index + 1)
+            .map_err(|kind| error(kind, 0, None))?;
+
+        let mut prefix = [0u8; 8];
+        let read = read_full(&mut self.source, &mut prefix)?;
+        if read == 0 {
+            self.check_structure()?;
+            return Ok(None);
+        } else if read < prefix.len() {
+            return Err(error(ParseErrorKind::Truncated, 0, None).into());
+        }
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&prefix[..4]);
+        let length = u32::from_be_bytes(length);
+        let mut typ = [0u8; 4];
+        typ.copy_from_slice(&prefix[4..]);
+        let typ = ChunkType::try_from(typ)
+            .map_err(|_| error(ParseErrorKind::InvalidChunkType(typ), 4, None))?;
+        self.options
+            .check_chunk_len(length)
+            .map_err(|kind| error(kind, 0, Some(typ)))?;
+        self.options
+            .check_file_size(offset + 12 + length as u64)
+            .map_err(|kind| error(kind, 0, Some(typ)))?;
+
+        // Read through `take` rather than allocating `length` bytes up front,
+        // so a bogus length on a short stream costs no more than the stream.
+        let mut data = Vec::new();
+        (&mut self.source)
+            .take(length as u64)
+            .read_to_end(&mut data)?;
+        let mut crc = [0u8; 4];
+        if data.len() < length as usize || read_full(&mut self.source, &mut crc)? < crc.len() {
+            return Err(error(ParseErrorKind::Truncated, 0, Some(typ)).into());
+        }
+        let crc = u32::from_be_bytes(crc);
+
+        if self.options.verify_crc {
+            let mut checksum = Checksum::new(&typ);
+            checksum.update(&data);
+            let computed = checksum.finalize();
+            if computed != crc {
+                let kind = ParseErrorKind::CrcMismatch {
+                    stored: crc,
+                    computed,
+                };
+                return Err(error(kind, 8 + length as u64, Some(typ)).into());
+            }
+        }
+
+        event!(trace, offset, chunk_type = %typ, length, "read chunk");
+        self.offset += 12 + length as u64;
+        self.index += 1;
+        self.types.push(typ);
+        if typ == IEND {
+            self.check_structure()?;
+        }
+        Ok(Some(Chunk::with_crc(typ, data, crc)))
+    }
+
+    /// Applies the structure checks to every chunk read, once there are no
+    /// more.
+    fn check_structure(&self) -> crate::Result<()> {
+        let types: Vec<_> = self.types.iter().collect();
+        self.options
+            .check_structure(&types)
+            .map_err(|(index, kind)| {
+                let typ = self.types.get(index).copied();
+                ParseError::new(kind, self.offset)
+                    .in_chunk(Some(index), typ)
+                    .into()
+            })
+    }
+}
+
+/// Fills as much of `buf` as the reader has left, returning how much that was.
+pub(crate) fn read_full(source: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match source.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = crate::Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_chunk().transpose();
+        self.done = match &result {
+            Some(Ok(chunk)) => *chunk.chunk_type() == IEND,
+            _ => true,
+        };
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::consts::*;
+    use std::io::Cursor;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(IHDR, vec![0; 13]),
+            Chunk::new(TEXT, b"Title\0found".to_vec()),
+            Chunk::new(IDAT, vec![1; 100]),
+            Chunk::new(IEND, Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_read_chunks() {
+        let mut png = testing_png();
+        png.set_trailing_data(b"extra".to_vec());
+        let bytes = png.as_bytes();
+        let mut reader = ChunkReader::new(Cursor::new(&bytes)).unwrap();
+        let chunks: Vec<_> = reader.by_ref().map(|c| c.unwrap()).collect();
+        assert_eq!(chunks, png.chunks().cloned().collect::<Vec<_>>());
+        assert_eq!(reader.offset(), bytes.len() as u64 - 5);
+        let mut rest = Vec::new();
+        reader.into_inner().read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"extra");
+    }
+
+    #[test]
+    fn test_stop_early() {
+        let bytes = testing_png().as_bytes();
+        let mut source = Cursor::new(&bytes);
+        let text = ChunkReader::new(&mut source)
+            .unwrap()
+            .map(|c| c.unwrap())
+            .find(|c| *c.chunk_type() == TEXT)
+            .unwrap();
+        assert_eq!(text.data(), b"Title\0found");
+        // Nothing past the tEXt chunk was read.
+        assert_eq!(source.position(), 8 + 25 + 23);
+    }
+
+    #[test]
+    fn test_read_errors() {
+        assert!(ChunkReader::new(Cursor::new(b"not a png")).is_err());
+
+        let bytes = testing_png().as_bytes();
+        let truncated = &bytes[..bytes.len() - 20];
+        let results: Vec<_> = ChunkReader::new(Cursor::new(truncated)).unwrap().collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[2].is_err());
+
+        let mut bad_crc = bytes.clone();
+        bad_crc[8 + 12] ^= 1;
+        let mut reader = ChunkReader::new(Cursor::new(&bad_crc)).unwrap();
+        let error = reader.next().unwrap().unwrap_err();
+        let error = error.downcast_ref::<ParseError>().unwrap();
+        assert!(matches!(error.kind(), ParseErrorKind::CrcMismatch { .. }));
+        assert!(reader.next().is_none());
+
+        let options = ParseOptions::lenient();
+        let chunks: Vec<_> = ChunkReader::with_options(Cursor::new(&bad_crc), &options)
+            .unwrap()
+            .collect::<crate::Result<_>>()
+            .unwrap();
+        assert_eq!(chunks.len(), 4);
+        // The stored CRC is kept rather than recomputed.
+        assert_eq!(chunks[0].as_bytes(), bad_crc[8..8 + 25]);
+    }
+
+    #[test]
+    fn test_limits() {
+        let bytes = testing_png().as_bytes();
+        let options = ParseOptions {
+            max_file_size: Some(8 + 25 + 23 + 50),
+            ..ParseOptions::default()
+        };
+        let results: Vec<_> = ChunkReader::with_options(Cursor::new(&bytes), &options)
+            .unwrap()
+            .collect();
+        assert_eq!(results.len(), 3);
+        let error = results[2].as_ref().unwrap_err();
+        let error = error.downcast_ref::<ParseError>().unwrap();
+        assert!(matches!(error.kind(), ParseErrorKind::FileTooLarge { .. }));
+        assert_eq!(error.chunk_type(), Some(IDAT));
+
+        let no_iend = &bytes[..bytes.len() - 12];
+        let strict = ParseOptions::strict();
+        let results: Vec<_> = ChunkReader::with_options(Cursor::new(no_iend), &strict)
+            .unwrap()
+            .collect();
+        let error = results.last().unwrap().as_ref().unwrap_err();
+        let error = error.downcast_ref::<ParseError>().unwrap();
+        assert_eq!(*error.kind(), ParseErrorKind::MissingIend);
+        assert!(
+            ChunkReader::new(Cursor::new(no_iend))
+                .unwrap()
+                .all(|c| c.is_ok())
+        );
+    }
+}
@@ -0,0 +1,469 @@
+use crate::ancillary::Histogram;
+use crate::chunk::ChunkRef;
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::{HIST, IEND, PLTE};
+use crate::error::ParseErrorKind;
+use crate::handler::Registry;
+use crate::options::ParseOptions;
+use crate::order;
+use crate::png::Png;
+use crate::progress::Progress;
+use crate::text::{self, TextChunk, TextFormat};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// One problem found in a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Absolute byte offset the finding refers to.
+    pub offset: u64,
+    pub chunk_index: Option<usize>,
+    pub chunk_type: Option<ChunkType>,
+    pub message: String,
+}
+
+/// Every problem found in a file, in the order they were found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    findings: Vec<Finding>,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{}: {} at offset {}",
+            self.severity, self.message, self.offset
+        )?;
+        match (self.chunk_index, self.chunk_type) {
+            (Some(index), Some(typ)) => write!(f, " (chunk {}, {})", index, typ),
+            (Some(index), None) => write!(f, " (chunk {})", index),
+            (None, Some(typ)) => write!(f, " ({})", typ),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for finding in self.findings.iter() {
+            writeln!(f, "{}", finding)?;
+        }
+        write!(
+            f,
+            "{} error(s), {} warning(s)",
+            self.count(Severity::Error),
+            self.count(Severity::Warning)
+        )
+    }
+}
+
+impl Report {
+    pub fn findings(&self) -> &[Finding] {
+        &self.findings
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == severity)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        offset: u64,
+        chunk: Option<(usize, ChunkType)>,
+        message: String,
+    ) {
+        self.findings.push(Finding {
+            severity,
+            offset,
+            chunk_index: chunk.map(|(i, _)| i),
+            chunk_type: chunk.map(|(_, t)| t),
+            message,
+        });
+    }
+}
+
+impl Png {
+    /// Checks the whole of `bytes`, collecting every problem rather than
+    /// stopping at the first. Only a broken chunk header, after which chunk
+    /// boundaries can no longer be found, ends the walk early.
+    pub fn check_all(bytes: &[u8]) -> Report {
+        Self::check_all_with_progress(bytes, &mut |_, _| {})
+    }
+    /// Like [`Png::check_all`], reporting the bytes checked after every chunk.
+    pub fn check_all_with_progress(bytes: &[u8], progress: &mut dyn Progress) -> Report {
+        Self::check_all_with_registry(bytes, &Registry::new(), progress)
+    }
+
+    /// Like [`Png::check_all_with_progress`], also flagging chunks that the
+    /// handler registered for their type fails to parse.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(len = bytes.len()))
+    )]
+    pub fn check_all_with_registry(
+        bytes: &[u8],
+        registry: &Registry,
+        progress: &mut dyn Progress,
+    ) -> Report {
+        let total = bytes.len() as u64;
+        let mut report = Report::default();
+        let header_len = Png::STANDARD_HEADER.len();
+        if bytes.len() < header_len || bytes[..header_len] != Png::STANDARD_HEADER {
+            report.push(
+                Severity::Error,
+                0,
+                None,
+                String::from("invalid PNG signature"),
+            );
+            return report;
+        }
+
+        let walk = ParseOptions {
+            verify_crc: false,
+            ..ParseOptions::unlimited()
+        };
+        let limits = ParseOptions::default();
+        let mut remain = &bytes[header_len..];
+        let mut walked = Vec::new();
+        let mut stop = None;
+        while !remain.is_empty() {
+            let offset = (bytes.len() - remain.len()) as u64;
+            let index = walked.len();
+            let (chunk, rest) = match ChunkRef::parse_prefix(remain, offset, Some(index), &walk) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    let chunk = e.chunk_type().map(|t| (index, t));
+                    stop = Some((Severity::Error, e.offset(), chunk, format!("{}", e.kind())));
+                    break;
+                }
+            };
+            let typ = *chunk.chunk_type();
+            walked.push((offset, chunk));
+            remain = rest;
+            progress.update(total - remain.len() as u64, total);
+            if typ == IEND && !remain.is_empty() {
+                let offset = (bytes.len() - remain.len()) as u64;
+                let kind = ParseErrorKind::TrailingData {
+                    length: remain.len() as u64,
+                };
+                stop = Some((Severity::Warning, offset, None, format!("{}", kind)));
+                break;
+            }
+        }
+
+        // Checksumming is the only costly check, so it runs for every chunk
+        // at once before the findings are gathered in file order.
+        let crc_ok = verify_crcs(&walked);
+        let mut chunks = Vec::new();
+        let mut offsets = Vec::new();
+        for (index, ((offset, chunk), crc_ok)) in walked.iter().zip(crc_ok).enumerate() {
+            let offset = *offset;
+            let typ = *chunk.chunk_type();
+            let located = Some((index, typ));
+
+            if !crc_ok {
+                let crc_offset = offset + 8 + chunk.length() as u64;
+                report.push(
+                    Severity::Error,
+                    crc_offset,
+                    located,
+                    String::from("CRC mismatch"),
+                );
+            }
+            if let Err(kind) = limits.check_chunk_len(chunk.length()) {
+                report.push(Severity::Warning, offset, located, format!("{}", kind));
+            }
+            if !typ.is_reserved_bit_valid() {
+                let message = String::from("reserved bit is set in the chunk type");
+                report.push(Severity::Error, offset + 4, located, message);
+            }
+            if typ.is_critical() && !typ.is_standard() {
+                let message = format!("{}", ParseErrorKind::UnknownCritical);
+                report.push(Severity::Error, offset, located, message);
+            } else if !typ.is_standard() {
+                let message = String::from("non-standard ancillary chunk");
+                report.push(Severity::Info, offset, located, message);
+            }
+
+            if TextFormat::of(&typ).is_some() {
+                check_text(&mut report, offset, located, chunk);
+            }
+            // hIST is checked against the palette below.
+            if let Some(handler) = registry.get(&typ).filter(|_| typ != HIST)
+                && let Err(e) = handler.parse(chunk.data())
+            {
+                let message = format!("invalid {}: {}", typ, e);
+                report.push(Severity::Error, offset + 8, located, message);
+            }
+
+            chunks.push(typ);
+            offsets.push(offset);
+        }
+        if let Some((severity, offset, chunk, message)) = stop {
+            report.push(severity, offset, chunk, message);
+        }
+
+        if chunks.last() != Some(&IEND) {
+            let message = format!("{}", ParseErrorKind::MissingIend);
+            report.push(Severity::Error, bytes.len() as u64, None, message);
+        }
+        let types: Vec<_> = chunks.iter().collect();
+        for v in order::violations(&types) {
+            let chunk = Some((v.index, chunks[v.index]));
+            report.push(Severity::Error, offsets[v.index], chunk, v.message);
+        }
+        check_histogram(&mut report, &walked);
+        event!(debug, findings = report.findings.len(), "checked PNG");
+        report
+    }
+}
+
+/// Whether each chunk's stored CRC matches its contents, spread across all
+/// cores with the `parallel` feature since files with thousands of chunks
+/// spend most of their validation time here.
+#[cfg(feature = "parallel")]
+fn verify_crcs(chunks: &[(u64, ChunkRef)]) -> Vec<bool> {
+    use rayon::prelude::*;
+    chunks.par_iter().map(|(_, c)| c.verify_crc()).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn verify_crcs(chunks: &[(u64, ChunkRef)]) -> Vec<bool> {
+    chunks.iter().map(|(_, c)| c.verify_crc()).collect()
+}
+
+/// Flags a hIST chunk that cannot be decoded or does not have one entry per
+/// palette entry.
+fn check_histogram(report: &mut Report, chunks: &[(u64, ChunkRef)]) {
+    let Some((index, (offset, hist))) = chunks
+        .iter()
+        .enumerate()
+        .find(|(_, (_, c))| *c.chunk_type() == HIST)
+    else {
+        return;
+    };
+    let located = Some((index, HIST));
+    let palette_len = chunks
+        .iter()
+        .find(|(_, c)| *c.chunk_type() == PLTE)
+        .map(|(_, c)| c.data().len() / 3);
+    let result = match palette_len {
+        None => Err("hIST without PLTE".into()),
+        Some(len) => Histogram::decode(hist.data()).and_then(|h| h.check(len)),
+    };
+    if let Err(e) = result {
+        report.push(Severity::Error, *offset, located, format!("{}", e));
+    }
+}
+
+/// Flags a text chunk that cannot be decoded or whose keyword breaks the
+/// specification's rules.
+fn check_text(
+    report: &mut Report,
+    offset: u64,
+    chunk: Option<(usize, ChunkType)>,
+    data: &ChunkRef,
+) {
+    let text = match TextChunk::decode(data.chunk_type(), data.data()) {
+        Ok(text) => text,
+        Err(e) => {
+            report.push(
+                Severity::Warning,
+                offset,
+                chunk,
+                format!("unreadable text: {}", e),
+            );
+            return;
+        }
+    };
+    if let Err(e) = text::check_keyword(&text.keyword) {
+        report.push(Severity::Warning, offset + 8, chunk, format!("{}", e));
+    } else if !text::is_registered_keyword(&text.keyword) {
+        let message = format!("unregistered keyword {:?}", text.keyword);
+        report.push(Severity::Info, offset + 8, chunk, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::consts::*;
+    use core::str::FromStr;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(IHDR, b"header".to_vec()),
+            Chunk::new(IDAT, b"data".to_vec()),
+            Chunk::new(TEXT, b"Comment\0hello".to_vec()),
+            Chunk::new(IEND, Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_clean_file() {
+        let report = Png::check_all(&testing_png().as_bytes());
+        assert!(report.findings().is_empty());
+        assert!(!report.has_errors());
+        assert_eq!(report.to_string(), "0 error(s), 0 warning(s)");
+    }
+
+    #[test]
+    fn test_collects_every_problem() {
+        let mut png = testing_png();
+        png.insert_chunk(
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"mine".to_vec()),
+            crate::png::Placement::BeforeIend,
+        )
+        .unwrap();
+        let mut bytes = png.as_bytes();
+        // Corrupt the data of IHDR and IDAT, then add bytes after IEND.
+        bytes[8 + 8] ^= 0xff;
+        bytes[8 + 18 + 8] ^= 0xff;
+        bytes.extend(b"trailing");
+
+        let report = Png::check_all(&bytes);
+        let messages: Vec<_> = report
+            .findings()
+            .iter()
+            .map(|f| (f.severity, f.chunk_index, f.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (Severity::Error, Some(0), "CRC mismatch"),
+                (Severity::Error, Some(1), "CRC mismatch"),
+                (Severity::Info, Some(3), "non-standard ancillary chunk"),
+                (
+                    Severity::Warning,
+                    None,
+                    "8 bytes of unexpected trailing data"
+                ),
+            ]
+        );
+        assert_eq!(report.findings()[0].offset, 8 + 8 + 6);
+        assert!(report.has_errors());
+    }
+
+    #[test]
+    fn test_structure_problems() {
+        let png = Png::from_chunks(vec![
+            Chunk::new(IHDR, b"header".to_vec()),
+            Chunk::new(IDAT, b"data".to_vec()),
+            Chunk::new(PLTE, b"palette".to_vec()),
+            Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"mine".to_vec()),
+        ]);
+        let report = Png::check_all(&png.as_bytes());
+        let messages: Vec<_> = report
+            .findings()
+            .iter()
+            .map(|f| f.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "unknown critical chunk",
+                "file does not end with IEND",
+                "PLTE must come before IDAT",
+            ]
+        );
+        assert_eq!(report.count(Severity::Error), 3);
+    }
+
+    #[test]
+    fn test_histogram_problems() {
+        let chunks = |hist: &[u8]| {
+            Png::from_chunks(vec![
+                Chunk::new(IHDR, b"header".to_vec()),
+                Chunk::new(PLTE, vec![0; 6]),
+                Chunk::new(HIST, hist.to_vec()),
+                Chunk::new(IDAT, b"data".to_vec()),
+                Chunk::new(IEND, Vec::new()),
+            ])
+        };
+        let report = Png::check_all(&chunks(&[0, 1, 0, 2]).as_bytes());
+        assert_eq!(report.count(Severity::Error), 0);
+
+        let report = Png::check_all(&chunks(&[0, 1, 0, 2, 0, 3]).as_bytes());
+        let findings = report.findings();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].chunk_index, Some(2));
+        assert_eq!(findings[0].message, "hIST has 3 entries but PLTE has 2");
+
+        let report = Png::check_all(&chunks(&[0, 1, 0]).as_bytes());
+        assert_eq!(report.count(Severity::Error), 1);
+    }
+
+    #[test]
+    fn test_text_problems() {
+        let png = Png::from_chunks(vec![
+            Chunk::new(IHDR, b"header".to_vec()),
+            Chunk::new(IDAT, b"data".to_vec()),
+            Chunk::new(TEXT, b"Title\0fine".to_vec()),
+            Chunk::new(TEXT, b"Creation  Time\0today".to_vec()),
+            Chunk::new(TEXT, b"Camera\0Mk II".to_vec()),
+            Chunk::new(ZTXT, b"Comment\0\0not zlib".to_vec()),
+            Chunk::new(IEND, Vec::new()),
+        ]);
+        let report = Png::check_all(&png.as_bytes());
+        let messages: Vec<_> = report
+            .findings()
+            .iter()
+            .map(|f| (f.severity, f.chunk_index, f.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (Severity::Warning, Some(3), "keyword has consecutive spaces"),
+                (Severity::Info, Some(4), "unregistered keyword \"Camera\""),
+                (
+                    Severity::Warning,
+                    Some(5),
+                    "unreadable text: cannot decompress text: Invalid input data"
+                ),
+            ]
+        );
+        assert!(!report.has_errors());
+    }
+
+    #[test]
+    fn test_broken_header_stops_walk() {
+        let mut bytes = testing_png().as_bytes();
+        bytes.truncate(bytes.len() - 2);
+        let report = Png::check_all(&bytes);
+        assert_eq!(report.findings()[0].message, "unexpected end of data");
+        assert_eq!(report.findings()[0].chunk_index, Some(3));
+
+        let report = Png::check_all(b"not a png");
+        assert_eq!(report.findings()[0].message, "invalid PNG signature");
+    }
+}
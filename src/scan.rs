@@ -0,0 +1,317 @@
+use crate::chunk::ChunkRef;
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::IEND;
+use crate::error::{ParseError, ParseErrorKind};
+use crate::options::ParseOptions;
+use crate::order;
+use crate::payload;
+use crate::png::Png;
+use crate::stats;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+/// How well known a chunk type is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    /// Defined by the PNG specification.
+    Standard,
+    /// A public type registered as an extension, such as oFFs.
+    Extension,
+    /// A private type, which only the software that wrote it understands.
+    Private,
+    /// A public type that is neither standard nor registered.
+    UnknownPublic,
+}
+
+/// Something about a chunk worth a closer look.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Suspicion {
+    /// A critical chunk no decoder knows, so no decoder can show the image.
+    UnknownCritical,
+    /// A second (or later) chunk of a type allowed only once.
+    Duplicate,
+    /// A non-standard ancillary chunk whose data is close to random yet is
+    /// not in a compression format, as an encrypted payload would be.
+    HighEntropy,
+}
+
+/// Shortest data [`Suspicion::HighEntropy`] is judged on; below this even
+/// plain text can look random.
+const MIN_ENTROPY_LEN: usize = 64;
+
+/// One chunk as [`Png::scan`] found it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScannedChunk {
+    /// Absolute byte offset of the chunk's length field.
+    pub offset: u64,
+    pub chunk_type: ChunkType,
+    pub length: u32,
+    pub class: Class,
+    /// Entropy of the data in bits per byte, see [`stats::entropy`].
+    pub entropy: f64,
+    pub suspicions: Vec<Suspicion>,
+}
+
+/// Every chunk of a file, classified, together with anything hidden after
+/// IEND.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scan {
+    pub chunks: Vec<ScannedChunk>,
+    /// Absolute offset and length of any bytes after IEND.
+    pub trailing_data: Option<(u64, u64)>,
+    /// Why the walk stopped early, if it did. The chunks before that point
+    /// are still listed.
+    pub error: Option<ParseError>,
+}
+
+impl Class {
+    pub fn of(chunk_type: &ChunkType) -> Self {
+        if chunk_type.is_standard() {
+            Class::Standard
+        } else if chunk_type.is_registered_extension() {
+            Class::Extension
+        } else if !chunk_type.is_public() {
+            Class::Private
+        } else {
+            Class::UnknownPublic
+        }
+    }
+}
+
+impl Display for Class {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Class::Standard => "standard",
+            Class::Extension => "registered-extension",
+            Class::Private => "private",
+            Class::UnknownPublic => "unknown-public",
+        })
+    }
+}
+
+impl Display for Suspicion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Suspicion::UnknownCritical => "unknown critical chunk",
+            Suspicion::Duplicate => "duplicate of a singleton chunk",
+            Suspicion::HighEntropy => "high-entropy data, possibly encrypted",
+        })
+    }
+}
+
+/// Whether `data` starts like zlib, zstd or a compressed pngme payload, any
+/// of which explains high entropy.
+fn is_compressed(data: &[u8]) -> bool {
+    let zlib = match data {
+        [cmf, flg, ..] => cmf & 0x0f == 8 && (*cmf as u16 * 256 + *flg as u16).is_multiple_of(31),
+        _ => false,
+    };
+    zlib || data.starts_with(b"\x28\xb5\x2f\xfd") || payload::is_compressed(data)
+}
+
+/// Whether `entropy` is within 10% of the most `len` bytes can have, which
+/// random data reaches and text or structured data does not.
+fn looks_random(entropy: f64, len: usize) -> bool {
+    let max = libm::log2(len as f64).min(8.0);
+    len >= MIN_ENTROPY_LEN && entropy >= 0.9 * max
+}
+
+impl Scan {
+    /// Whether anything in the file deserves a closer look.
+    pub fn is_suspicious(&self) -> bool {
+        self.trailing_data.is_some() || self.chunks.iter().any(|c| !c.suspicions.is_empty())
+    }
+}
+
+impl Png {
+    /// Lists and classifies every chunk of `bytes` without checking CRCs or
+    /// limits, flagging unknown critical chunks, repeated singletons and data
+    /// after IEND.
+    pub fn scan(bytes: &[u8]) -> Scan {
+        let mut scan = Scan::default();
+        let header_len = Png::STANDARD_HEADER.len();
+        if bytes.len() < header_len || bytes[..header_len] != Png::STANDARD_HEADER {
+            scan.error = Some(ParseError::new(ParseErrorKind::InvalidSignature, 0));
+            return scan;
+        }
+
+        let walk = ParseOptions {
+            verify_crc: false,
+            ..ParseOptions::unlimited()
+        };
+        let mut remain = &bytes[header_len..];
+        while !remain.is_empty() {
+            let offset = (bytes.len() - remain.len()) as u64;
+            let index = scan.chunks.len();
+            let (chunk, rest) = match ChunkRef::parse_prefix(remain, offset, Some(index), &walk) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    scan.error = Some(e);
+                    break;
+                }
+            };
+            let chunk_type = *chunk.chunk_type();
+            let class = Class::of(&chunk_type);
+            let mut suspicions = Vec::new();
+            if chunk_type.is_critical() && class != Class::Standard {
+                suspicions.push(Suspicion::UnknownCritical);
+            }
+            if order::is_singleton(&chunk_type)
+                && scan.chunks.iter().any(|c| c.chunk_type == chunk_type)
+            {
+                suspicions.push(Suspicion::Duplicate);
+            }
+            let entropy = stats::entropy(chunk.data());
+            if !chunk_type.is_critical()
+                && class != Class::Standard
+                && looks_random(entropy, chunk.data().len())
+                && !is_compressed(chunk.data())
+            {
+                suspicions.push(Suspicion::HighEntropy);
+            }
+            scan.chunks.push(ScannedChunk {
+                offset,
+                chunk_type,
+                length: chunk.length(),
+                class,
+                entropy,
+                suspicions,
+            });
+
+            remain = rest;
+            if chunk_type == IEND && !remain.is_empty() {
+                let offset = (bytes.len() - remain.len()) as u64;
+                scan.trailing_data = Some((offset, remain.len() as u64));
+                break;
+            }
+        }
+        scan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use core::str::FromStr;
+
+    fn chunk(name: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(name).unwrap(), b"data".to_vec())
+    }
+
+    #[test]
+    fn test_classes() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR"),
+            chunk("oFFs"),
+            chunk("ruSt"),
+            chunk("cATs"),
+            chunk("IDAT"),
+            chunk("IEND"),
+        ]);
+        let scan = Png::scan(&png.as_bytes());
+        let classes: Vec<_> = scan.chunks.iter().map(|c| c.class).collect();
+        assert_eq!(
+            classes,
+            [
+                Class::Standard,
+                Class::Extension,
+                Class::Private,
+                Class::UnknownPublic,
+                Class::Standard,
+                Class::Standard,
+            ]
+        );
+        assert_eq!(scan.chunks[1].offset, 8 + 16);
+        assert_eq!(scan.chunks[1].length, 4);
+        assert!(!scan.is_suspicious());
+        assert!(scan.error.is_none());
+    }
+
+    #[test]
+    fn test_suspicions() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR"),
+            chunk("gAMA"),
+            chunk("gAMA"),
+            chunk("RUST"),
+            chunk("IDAT"),
+            chunk("IDAT"),
+            Chunk::new(IEND, Vec::new()),
+        ]);
+        let mut bytes = png.as_bytes();
+        let end = bytes.len() as u64;
+        bytes.extend(b"hidden");
+
+        let scan = Png::scan(&bytes);
+        let suspicions: Vec<_> = scan
+            .chunks
+            .iter()
+            .map(|c| c.suspicions.as_slice())
+            .collect();
+        assert_eq!(
+            suspicions,
+            [
+                &[][..],
+                &[],
+                &[Suspicion::Duplicate],
+                &[Suspicion::UnknownCritical],
+                &[],
+                &[],
+                &[],
+            ]
+        );
+        assert_eq!(scan.trailing_data, Some((end, 6)));
+        assert!(scan.is_suspicious());
+    }
+
+    #[test]
+    fn test_high_entropy() {
+        // Every byte value once, shuffled by a multiplier coprime to 256.
+        let random: Vec<u8> = (0..=255u8).map(|i| i.wrapping_mul(167)).collect();
+        let deflated = miniz_oxide::deflate::compress_to_vec_zlib(&random, 0);
+        let sealed = Chunk::new(ChunkType::from_str("seCr").unwrap(), random.clone());
+        let zlib = Chunk::new(ChunkType::from_str("zlIb").unwrap(), deflated);
+        let short = Chunk::new(ChunkType::from_str("shRt").unwrap(), random[..32].to_vec());
+        let text = Chunk::new(
+            ChunkType::from_str("teXt").unwrap(),
+            b"plain words repeat and repeat, so their entropy stays low".repeat(4),
+        );
+        let standard = Chunk::new(ChunkType::from_str("iCCP").unwrap(), random);
+        let png = Png::from_chunks(vec![
+            chunk("IHDR"),
+            standard,
+            sealed,
+            zlib,
+            short,
+            text,
+            chunk("IDAT"),
+            Chunk::new(IEND, Vec::new()),
+        ]);
+        let scan = Png::scan(&png.as_bytes());
+        let flagged: Vec<_> = scan
+            .chunks
+            .iter()
+            .filter(|c| c.suspicions.contains(&Suspicion::HighEntropy))
+            .map(|c| c.chunk_type.to_string())
+            .collect();
+        assert_eq!(flagged, ["seCr"]);
+        assert_eq!(scan.chunks[2].entropy, 8.0);
+    }
+
+    #[test]
+    fn test_stops_at_broken_chunk() {
+        let mut bytes = Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT")]).as_bytes();
+        bytes.truncate(bytes.len() - 2);
+        let scan = Png::scan(&bytes);
+        assert_eq!(scan.chunks.len(), 1);
+        assert_eq!(scan.error.unwrap().kind(), &ParseErrorKind::Truncated);
+
+        let scan = Png::scan(b"not a png");
+        assert!(scan.chunks.is_empty());
+        assert_eq!(
+            scan.error.unwrap().kind(),
+            &ParseErrorKind::InvalidSignature
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::IDAT;
+use crate::png::{Png, PngRef};
+use alloc::vec::Vec;
+
+/// The Shannon entropy of `data` in bits per byte: 0 for a single repeated
+/// value, 8 for uniformly random bytes. Compressed and encrypted data sit
+/// close to 8.
+pub fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0u64; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * libm::log2(p)
+        })
+        .sum()
+}
+
+/// The chunks of one type, added up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TypeStats {
+    pub chunk_type: ChunkType,
+    pub count: usize,
+    /// Bytes taken in the file, headers and CRCs included.
+    pub bytes: u64,
+}
+
+/// One chunk's size and entropy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkStats {
+    pub index: usize,
+    pub chunk_type: ChunkType,
+    pub length: u32,
+    /// Entropy of the data, see [`entropy`].
+    pub entropy: f64,
+}
+
+/// Where the bytes of a file go, to answer what is making it large.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// Bytes in the file: signature, chunks and trailing data.
+    pub file_size: u64,
+    /// One entry per chunk type, in order of first appearance.
+    pub types: Vec<TypeStats>,
+    /// Every chunk, in file order.
+    pub chunks: Vec<ChunkStats>,
+    /// Bytes taken by IDAT chunks, headers and CRCs included.
+    pub idat_bytes: u64,
+    /// Bytes taken by ancillary chunks, headers and CRCs included.
+    pub ancillary_bytes: u64,
+    pub trailing_bytes: u64,
+}
+
+impl TypeStats {
+    /// Mean bytes per chunk, headers and CRCs included.
+    pub fn average(&self) -> f64 {
+        self.bytes as f64 / self.count as f64
+    }
+}
+
+impl Stats {
+    fn of<'c>(chunks: impl Iterator<Item = (&'c ChunkType, &'c [u8])>, trailing: u64) -> Self {
+        let mut stats = Stats {
+            file_size: Png::STANDARD_HEADER.len() as u64 + trailing,
+            types: Vec::new(),
+            chunks: Vec::new(),
+            idat_bytes: 0,
+            ancillary_bytes: 0,
+            trailing_bytes: trailing,
+        };
+        for (index, (chunk_type, data)) in chunks.enumerate() {
+            let bytes = data.len() as u64 + 12;
+            stats.file_size += bytes;
+            if *chunk_type == IDAT {
+                stats.idat_bytes += bytes;
+            } else if !chunk_type.is_critical() {
+                stats.ancillary_bytes += bytes;
+            }
+            match stats.types.iter_mut().find(|t| t.chunk_type == *chunk_type) {
+                Some(t) => {
+                    t.count += 1;
+                    t.bytes += bytes;
+                }
+                None => stats.types.push(TypeStats {
+                    chunk_type: *chunk_type,
+                    count: 1,
+                    bytes,
+                }),
+            }
+            stats.chunks.push(ChunkStats {
+                index,
+                chunk_type: *chunk_type,
+                length: data.len() as u32,
+                entropy: entropy(data),
+            });
+        }
+        stats
+    }
+
+    /// The `n` largest chunks, largest first; ties keep file order.
+    pub fn largest(&self, n: usize) -> Vec<&ChunkStats> {
+        let mut chunks: Vec<_> = self.chunks.iter().collect();
+        chunks.sort_by_key(|c| core::cmp::Reverse(c.length));
+        chunks.truncate(n);
+        chunks
+    }
+
+    /// `bytes` as a percentage of the file.
+    pub fn percent(&self, bytes: u64) -> f64 {
+        100.0 * bytes as f64 / self.file_size as f64
+    }
+}
+
+impl Png {
+    /// Counts and sizes the chunks by type and measures their entropy.
+    pub fn stats(&self) -> Stats {
+        let chunks = self.chunks().map(|c| (c.chunk_type(), c.data()));
+        Stats::of(chunks, self.trailing_data().len() as u64)
+    }
+}
+
+impl PngRef<'_> {
+    /// Like [`Png::stats`].
+    pub fn stats(&self) -> Stats {
+        let chunks = self.chunks().iter().map(|c| (c.chunk_type(), c.data()));
+        Stats::of(chunks, self.trailing_data().len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::consts::{IEND, IHDR, TEXT};
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(b""), 0.0);
+        assert_eq!(entropy(&[7; 100]), 0.0);
+        assert_eq!(entropy(b"abab"), 1.0);
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(entropy(&all), 8.0);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(IHDR, vec![0; 13]),
+            Chunk::new(TEXT, b"Title\0a".to_vec()),
+            Chunk::new(IDAT, vec![1; 100]),
+            Chunk::new(IDAT, vec![2; 40]),
+            Chunk::new(TEXT, b"Title\0b".to_vec()),
+            Chunk::new(IEND, Vec::new()),
+        ]);
+        png.set_trailing_data(vec![0; 5]);
+        let stats = png.stats();
+        assert_eq!(stats.file_size, png.as_bytes().len() as u64);
+        assert_eq!(stats.idat_bytes, 112 + 52);
+        assert_eq!(stats.ancillary_bytes, 2 * 19);
+        assert_eq!(stats.trailing_bytes, 5);
+
+        let types: Vec<_> = stats
+            .types
+            .iter()
+            .map(|t| (t.chunk_type.to_string(), t.count, t.bytes))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ("IHDR".to_string(), 1, 25),
+                ("tEXt".to_string(), 2, 38),
+                ("IDAT".to_string(), 2, 164),
+                ("IEND".to_string(), 1, 12),
+            ]
+        );
+        assert_eq!(stats.types[2].average(), 82.0);
+
+        let largest: Vec<_> = stats.largest(3).iter().map(|c| c.index).collect();
+        assert_eq!(largest, [2, 3, 0]);
+        assert_eq!(stats.chunks[2].entropy, 0.0);
+        assert_eq!(stats.percent(stats.file_size), 100.0);
+    }
+}
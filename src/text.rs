@@ -0,0 +1,393 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::chunk_type::consts::{ITXT, TEXT, ZTXT};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+/// Largest decompressed text accepted, so a small zTXt chunk cannot expand
+/// into gigabytes.
+const MAX_TEXT_LEN: usize = 16 << 20;
+
+/// The compression level used for zTXt and compressed iTXt chunks.
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// The keywords the PNG specification defines. Others are allowed but carry
+/// no agreed meaning.
+pub const REGISTERED_KEYWORDS: &[&str] = &[
+    "Title",
+    "Author",
+    "Description",
+    "Copyright",
+    "Creation Time",
+    "Software",
+    "Disclaimer",
+    "Warning",
+    "Source",
+    "Comment",
+    "XML:com.adobe.xmp",
+    "Collection",
+];
+
+/// The keyword of the iTXt chunk holding an XMP packet.
+pub const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+/// Longest keyword the specification allows, in bytes.
+const MAX_KEYWORD_LEN: usize = 79;
+
+pub fn is_registered_keyword(keyword: &str) -> bool {
+    REGISTERED_KEYWORDS.contains(&keyword)
+}
+
+/// Checks `keyword` against the specification: 1 to 79 printable Latin-1
+/// characters with no leading, trailing or consecutive spaces.
+pub fn check_keyword(keyword: &str) -> crate::Result<()> {
+    let bytes = to_latin1(keyword).map_err(|_| "keyword is not Latin-1")?;
+    if bytes.is_empty() {
+        return Err("keyword is empty".into());
+    }
+    if bytes.len() > MAX_KEYWORD_LEN {
+        return Err("keyword is longer than 79 bytes".into());
+    }
+    if !bytes.iter().all(|&b| matches!(b, 32..=126 | 161..=255)) {
+        return Err("keyword contains a non-printable character".into());
+    }
+    if bytes.starts_with(b" ") || bytes.ends_with(b" ") {
+        return Err("keyword has leading or trailing spaces".into());
+    }
+    if bytes.windows(2).any(|w| w == b"  ") {
+        return Err("keyword has consecutive spaces".into());
+    }
+    Ok(())
+}
+
+/// Which of the three textual chunk types holds a [`TextChunk`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextFormat {
+    /// tEXt: uncompressed Latin-1.
+    Text,
+    /// zTXt: compressed Latin-1.
+    Ztxt,
+    /// iTXt: UTF-8, optionally compressed, with a language tag.
+    Itxt,
+}
+
+impl TextFormat {
+    pub fn of(chunk_type: &ChunkType) -> Option<Self> {
+        match *chunk_type {
+            TEXT => Some(Self::Text),
+            ZTXT => Some(Self::Ztxt),
+            ITXT => Some(Self::Itxt),
+            _ => None,
+        }
+    }
+
+    pub fn chunk_type(self) -> ChunkType {
+        match self {
+            Self::Text => TEXT,
+            Self::Ztxt => ZTXT,
+            Self::Itxt => ITXT,
+        }
+    }
+}
+
+impl Display for TextFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.chunk_type())
+    }
+}
+
+/// A keyword and its text from a tEXt, zTXt or iTXt chunk, decoded and
+/// decompressed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    pub format: TextFormat,
+    pub keyword: String,
+    pub text: String,
+    /// iTXt only: the language of the text, e.g. `en-GB`.
+    pub language: String,
+    /// iTXt only: the keyword translated into `language`.
+    pub translated_keyword: String,
+    /// iTXt only: whether the text is stored compressed. zTXt always is.
+    pub compressed: bool,
+}
+
+pub(crate) fn from_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+pub(crate) fn to_latin1(s: &str) -> crate::Result<Vec<u8>> {
+    s.chars()
+        .map(|c| u8::try_from(c).map_err(|_| "text is not Latin-1".into()))
+        .collect()
+}
+
+fn decompress(data: &[u8]) -> crate::Result<Vec<u8>> {
+    miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(data, MAX_TEXT_LEN)
+        .map_err(|e| alloc::format!("cannot decompress text: {}", e).into())
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec_zlib(data, COMPRESSION_LEVEL)
+}
+
+/// Splits `data` at the first NUL, dropping the NUL.
+pub(crate) fn split_nul(data: &[u8]) -> crate::Result<(&[u8], &[u8])> {
+    let i = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or("missing null separator")?;
+    Ok((&data[..i], &data[i + 1..]))
+}
+
+/// Reads a compression flag and method, accepting only zlib (method 0).
+fn compression_method(byte: Option<&u8>) -> crate::Result<()> {
+    match byte {
+        Some(0) => Ok(()),
+        Some(_) => Err("unknown compression method".into()),
+        None => Err("missing compression method".into()),
+    }
+}
+
+impl TextChunk {
+    /// A chunk of `format` holding `text` under `keyword`, compressed if the
+    /// format is zTXt.
+    pub fn new(format: TextFormat, keyword: &str, text: &str) -> Self {
+        Self {
+            format,
+            keyword: keyword.to_string(),
+            text: text.to_string(),
+            language: String::new(),
+            translated_keyword: String::new(),
+            compressed: format == TextFormat::Ztxt,
+        }
+    }
+
+    /// Replaces the text, switching tEXt and zTXt to iTXt if the new text is
+    /// not Latin-1. Compression is kept.
+    pub fn with_text(mut self, text: &str) -> Self {
+        if self.format != TextFormat::Itxt && to_latin1(text).is_err() {
+            self.format = TextFormat::Itxt;
+        }
+        self.text = text.to_string();
+        self
+    }
+
+    /// Converts to `format`, compressing zTXt and keeping the compression of
+    /// a zTXt turned into iTXt. The iTXt language fields are dropped when
+    /// leaving iTXt. Fails if `format` is tEXt or zTXt and the text is not
+    /// Latin-1.
+    pub fn into_format(self, format: TextFormat) -> crate::Result<Self> {
+        if format != TextFormat::Itxt {
+            to_latin1(&self.text)?;
+        }
+        let compressed = match format {
+            TextFormat::Text => false,
+            TextFormat::Ztxt => true,
+            TextFormat::Itxt => self.compressed,
+        };
+        let mut converted = Self {
+            format,
+            compressed,
+            ..self
+        };
+        if format != TextFormat::Itxt {
+            converted.language.clear();
+            converted.translated_keyword.clear();
+        }
+        Ok(converted)
+    }
+
+    /// Decodes the data of a chunk of type `chunk_type`.
+    pub fn decode(chunk_type: &ChunkType, data: &[u8]) -> crate::Result<Self> {
+        let format = TextFormat::of(chunk_type).ok_or("not a text chunk")?;
+        let (keyword, rest) = split_nul(data)?;
+        let keyword = from_latin1(keyword);
+        match format {
+            TextFormat::Text => Ok(Self {
+                text: from_latin1(rest),
+                ..Self::new(format, &keyword, "")
+            }),
+            TextFormat::Ztxt => {
+                compression_method(rest.first())?;
+                Ok(Self {
+                    text: from_latin1(&decompress(&rest[1..])?),
+                    ..Self::new(format, &keyword, "")
+                })
+            }
+            TextFormat::Itxt => {
+                let compressed = match rest.first() {
+                    Some(0) => false,
+                    Some(1) => true,
+                    Some(_) => return Err("invalid compression flag".into()),
+                    None => return Err("missing compression flag".into()),
+                };
+                compression_method(rest.get(1))?;
+                let (language, rest) = split_nul(&rest[2..])?;
+                let (translated, text) = split_nul(rest)?;
+                let text = match compressed {
+                    true => decompress(text)?,
+                    false => text.to_vec(),
+                };
+                Ok(Self {
+                    format,
+                    keyword,
+                    text: String::from_utf8(text).map_err(|_| "text is not UTF-8")?,
+                    language: from_latin1(language),
+                    translated_keyword: String::from_utf8(translated.to_vec())
+                        .map_err(|_| "translated keyword is not UTF-8")?,
+                    compressed,
+                })
+            }
+        }
+    }
+
+    /// Encodes the chunk. Fails if the keyword breaks [`check_keyword`], the
+    /// language fields hold a NUL, or tEXt or zTXt text is not Latin-1.
+    pub fn encode(&self) -> crate::Result<Chunk> {
+        check_keyword(&self.keyword)?;
+        if self.language.contains('\0') || self.translated_keyword.contains('\0') {
+            return Err("language fields must not contain NUL".into());
+        }
+        let mut data = to_latin1(&self.keyword)?;
+        data.push(0);
+        match self.format {
+            TextFormat::Text => data.extend(to_latin1(&self.text)?),
+            TextFormat::Ztxt => {
+                data.push(0);
+                data.extend(compress(&to_latin1(&self.text)?));
+            }
+            TextFormat::Itxt => {
+                data.extend([self.compressed as u8, 0]);
+                data.extend(to_latin1(&self.language)?);
+                data.push(0);
+                data.extend(self.translated_keyword.bytes());
+                data.push(0);
+                match self.compressed {
+                    true => data.extend(compress(self.text.as_bytes())),
+                    false => data.extend(self.text.bytes()),
+                }
+            }
+        }
+        Ok(Chunk::new(self.format.chunk_type(), data))
+    }
+}
+
+impl TryFrom<&Chunk> for TextChunk {
+    type Error = crate::Error;
+
+    fn try_from(chunk: &Chunk) -> crate::Result<Self> {
+        Self::decode(chunk.chunk_type(), chunk.data())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::consts::IEND;
+
+    fn round_trip(text: &TextChunk) -> TextChunk {
+        TextChunk::try_from(&text.encode().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_text() {
+        let text = TextChunk::new(TextFormat::Text, "Comment", "héllo");
+        let chunk = text.encode().unwrap();
+        assert_eq!(chunk.data(), b"Comment\0h\xe9llo");
+        assert_eq!(TextChunk::try_from(&chunk).unwrap(), text);
+        assert!(
+            TextChunk::new(TextFormat::Text, "Comment", "✓")
+                .encode()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_ztxt() {
+        let long = "a long and repetitive comment ".repeat(20);
+        let text = TextChunk::new(TextFormat::Ztxt, "Comment", &long);
+        let chunk = text.encode().unwrap();
+        assert!(chunk.data().len() < long.len());
+        assert_eq!(round_trip(&text), text);
+    }
+
+    #[test]
+    fn test_itxt() {
+        for compressed in [false, true] {
+            let text = TextChunk {
+                language: "fr".to_string(),
+                translated_keyword: "Titre".to_string(),
+                compressed,
+                ..TextChunk::new(TextFormat::Itxt, "Title", "café ✓")
+            };
+            assert_eq!(round_trip(&text), text);
+        }
+    }
+
+    #[test]
+    fn test_into_format() {
+        let ztxt = TextChunk::new(TextFormat::Ztxt, "Comment", "café");
+        let itxt = ztxt.clone().into_format(TextFormat::Itxt).unwrap();
+        assert!(itxt.compressed);
+        assert_eq!(round_trip(&itxt), itxt);
+        let text = itxt.into_format(TextFormat::Text).unwrap();
+        assert_eq!(text, TextChunk::new(TextFormat::Text, "Comment", "café"));
+        assert_eq!(text.into_format(TextFormat::Ztxt).unwrap(), ztxt);
+
+        let wide = TextChunk {
+            language: "en".to_string(),
+            ..TextChunk::new(TextFormat::Itxt, "Title", "✓")
+        };
+        assert!(wide.clone().into_format(TextFormat::Text).is_err());
+        assert!(wide.into_format(TextFormat::Ztxt).is_err());
+    }
+
+    #[test]
+    fn test_check_keyword() {
+        for keyword in ["Title", "Creation Time", "Ünïcode", &"k".repeat(79)] {
+            assert!(check_keyword(keyword).is_ok(), "{}", keyword);
+        }
+        for keyword in [
+            "",
+            &"k".repeat(80),
+            " Title",
+            "Title ",
+            "Creation  Time",
+            "tab\there",
+            "nul\0",
+            "\u{a0}nbsp",
+            "✓",
+        ] {
+            assert!(check_keyword(keyword).is_err(), "{:?}", keyword);
+        }
+        assert!(
+            TextChunk::new(TextFormat::Text, " bad", "text")
+                .encode()
+                .is_err()
+        );
+        assert!(is_registered_keyword("Comment"));
+        assert!(!is_registered_keyword("comment"));
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        assert!(TextChunk::decode(&TEXT, b"no separator").is_err());
+        assert!(TextChunk::decode(&ZTXT, b"Comment\0\x01data").is_err());
+        assert!(TextChunk::decode(&ZTXT, b"Comment\0\0not zlib").is_err());
+        assert!(TextChunk::decode(&ITXT, b"Title\0\x02\0\0\0text").is_err());
+        assert!(TextChunk::decode(&ITXT, b"Title\0\0\0en").is_err());
+        assert!(TextChunk::decode(&ITXT, b"Title\0\0\0\0\0\xff").is_err());
+        assert!(TextChunk::decode(&IEND, b"").is_err());
+    }
+
+    #[test]
+    fn test_with_text() {
+        let text = TextChunk::new(TextFormat::Ztxt, "Comment", "old");
+        assert_eq!(text.clone().with_text("new").format, TextFormat::Ztxt);
+        let upgraded = text.with_text("✓");
+        assert_eq!(upgraded.format, TextFormat::Itxt);
+        assert!(upgraded.compressed);
+        assert_eq!(round_trip(&upgraded), upgraded);
+    }
+}
@@ -0,0 +1,442 @@
+use crate::args::TuiArgs;
+use crate::commands;
+use pngme::Result;
+use pngme::png::{Png, Removal};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::fmt::Write;
+use std::fs;
+
+/// Bytes shown on each line of the hex preview.
+const HEX_WIDTH: usize = 16;
+
+const HELP: &str =
+    "↑↓ select  PgUp/PgDn scroll  / search  n next  e edit  d delete  x export  s save  q quit";
+
+/// What the bottom line is collecting keystrokes for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prompt {
+    /// A chunk type to jump to, matched ignoring case.
+    Search,
+    /// The new data of the selected chunk, as hex digits.
+    Edit,
+    /// The path to write the selected chunk's data to.
+    Export,
+}
+
+struct App {
+    args: TuiArgs,
+    png: Png,
+    list: ListState,
+    /// First line of the hex preview on screen.
+    scroll: usize,
+    prompt: Option<(Prompt, String)>,
+    last_search: String,
+    status: String,
+    /// Whether there are edits that have not been saved.
+    dirty: bool,
+    done: bool,
+}
+
+/// Formats one line of a hex dump: the offset, up to [`HEX_WIDTH`] bytes in
+/// hex and the same bytes as ASCII.
+fn hex_line(offset: usize, bytes: &[u8]) -> String {
+    let mut line = format!("{:08x}  ", offset);
+    for i in 0..HEX_WIDTH {
+        match bytes.get(i) {
+            Some(b) => write!(line, "{:02x} ", b).unwrap(),
+            None => line.push_str("   "),
+        }
+    }
+    line.push(' ');
+    line.extend(bytes.iter().map(|&b| {
+        if b.is_ascii_graphic() || b == b' ' {
+            b as char
+        } else {
+            '.'
+        }
+    }));
+    line
+}
+
+impl App {
+    fn new(args: TuiArgs, png: Png) -> Self {
+        let mut list = ListState::default();
+        list.select_first();
+        Self {
+            args,
+            png,
+            list,
+            scroll: 0,
+            prompt: None,
+            last_search: String::new(),
+            status: String::from(HELP),
+            dirty: false,
+            done: false,
+        }
+    }
+
+    fn selected(&self) -> Option<usize> {
+        let len = self.png.chunks().count();
+        self.list.selected().filter(|i| *i < len)
+    }
+
+    fn select(&mut self, index: usize) {
+        self.list.select(Some(index));
+        self.scroll = 0;
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        while !self.done {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                self.handle_key(key.code);
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, bottom] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Length(28), Constraint::Min(0)]).areas(main);
+
+        let items: Vec<Line> = self
+            .png
+            .chunks()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let line = Line::from(format!(
+                    "{:>3} {} {:>10}",
+                    i,
+                    chunk.chunk_type(),
+                    chunk.length()
+                ));
+                if chunk.chunk_type().is_critical() {
+                    line.bold()
+                } else {
+                    line
+                }
+            })
+            .collect();
+        let title = format!(
+            " {}{} ",
+            self.args.file.display(),
+            if self.dirty { " [modified]" } else { "" }
+        );
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, left, &mut self.list);
+
+        let rows = right.height.saturating_sub(2) as usize;
+        let (title, preview) = match self.selected() {
+            Some(i) => {
+                let chunk = self.png.chunks().nth(i).unwrap();
+                let lines: Vec<Line> = chunk
+                    .data()
+                    .chunks(HEX_WIDTH)
+                    .enumerate()
+                    .skip(self.scroll)
+                    .take(rows)
+                    .map(|(n, bytes)| Line::from(hex_line(n * HEX_WIDTH, bytes)))
+                    .collect();
+                let title = format!(
+                    " {} ({} bytes, crc {:08x}) ",
+                    chunk.chunk_type(),
+                    chunk.length(),
+                    chunk.crc()
+                );
+                (title, lines)
+            }
+            None => (String::from(" no chunks "), Vec::new()),
+        };
+        frame.render_widget(
+            Paragraph::new(preview).block(Block::bordered().title(title)),
+            right,
+        );
+
+        let bottom_line = match &self.prompt {
+            Some((prompt, input)) => {
+                let label = match prompt {
+                    Prompt::Search => "search type: ",
+                    Prompt::Edit => "data (hex): ",
+                    Prompt::Export => "export to: ",
+                };
+                let end = bottom.x as usize + label.chars().count() + input.chars().count();
+                let x = end.min(bottom.right().saturating_sub(1) as usize) as u16;
+                frame.set_cursor_position((x, bottom.y));
+                Line::from(format!("{}{}", label, input))
+            }
+            None => Line::from(self.status.as_str()).fg(Color::DarkGray),
+        };
+        frame.render_widget(bottom_line, bottom);
+    }
+
+    fn handle_key(&mut self, code: KeyCode) {
+        if let Some((prompt, mut input)) = self.prompt.take() {
+            match code {
+                KeyCode::Enter => {
+                    if let Err(e) = self.submit(prompt, input) {
+                        self.status = format!("error: {}", e);
+                    }
+                }
+                KeyCode::Esc => self.status = String::from(HELP),
+                KeyCode::Backspace => {
+                    input.pop();
+                    self.prompt = Some((prompt, input));
+                }
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    self.prompt = Some((prompt, input));
+                }
+                _ => self.prompt = Some((prompt, input)),
+            }
+            return;
+        }
+
+        let quitting = code == KeyCode::Char('q') || code == KeyCode::Esc;
+        let result = match code {
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.list.select_previous();
+                self.scroll = 0;
+                Ok(())
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.list.select_next();
+                self.scroll = 0;
+                Ok(())
+            }
+            KeyCode::PageDown => {
+                self.scroll += 8;
+                Ok(())
+            }
+            KeyCode::PageUp => {
+                self.scroll = self.scroll.saturating_sub(8);
+                Ok(())
+            }
+            KeyCode::Char('/') => {
+                self.prompt = Some((Prompt::Search, String::new()));
+                Ok(())
+            }
+            KeyCode::Char('n') => self.search(&self.last_search.clone()),
+            KeyCode::Char('e') => self.start_edit(),
+            KeyCode::Char('x') => self.start_export(),
+            KeyCode::Char('d') => self.delete(),
+            KeyCode::Char('s') => self.save(),
+            _ if quitting => {
+                // With unsaved edits, the first press only warns.
+                if self.dirty && !self.status.starts_with("unsaved") {
+                    self.status = String::from("unsaved changes; press q again to quit");
+                } else {
+                    self.done = true;
+                }
+                return;
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.status = format!("error: {}", e);
+        }
+    }
+
+    fn submit(&mut self, prompt: Prompt, input: String) -> Result<()> {
+        match prompt {
+            Prompt::Search => {
+                self.last_search = input;
+                self.search(&self.last_search.clone())
+            }
+            Prompt::Edit => {
+                let index = self.selected().ok_or("no chunk selected")?;
+                let data = commands::parse_hex(&input)?;
+                let chunk = self.png.iter_mut().nth(index).unwrap();
+                chunk.set_data(data);
+                self.status = format!("edited chunk {} ({} bytes)", index, chunk.length());
+                self.dirty = true;
+                Ok(())
+            }
+            Prompt::Export => {
+                let index = self.selected().ok_or("no chunk selected")?;
+                let chunk = self.png.chunks().nth(index).unwrap();
+                fs::write(&input, chunk.data()).map_err(|e| format!("{}: {}", input, e))?;
+                self.status = format!("wrote {} bytes to {}", chunk.length(), input);
+                Ok(())
+            }
+        }
+    }
+
+    /// Selects the next chunk after the current one whose type is `name`,
+    /// ignoring case, wrapping around at the end.
+    fn search(&mut self, name: &str) -> Result<()> {
+        if name.is_empty() {
+            return Err("nothing to search for".into());
+        }
+        let len = self.png.chunks().count();
+        let start = self.selected().map_or(0, |i| i + 1);
+        let found = (0..len)
+            .map(|n| (start + n) % len)
+            .find(|&i| {
+                let chunk = self.png.chunks().nth(i).unwrap();
+                chunk.chunk_type().matches_ignore_case(name)
+            })
+            .ok_or_else(|| format!("no {} chunk", name))?;
+        self.select(found);
+        self.status = String::from(HELP);
+        Ok(())
+    }
+
+    fn start_edit(&mut self) -> Result<()> {
+        let index = self.selected().ok_or("no chunk selected")?;
+        let data = self.png.chunks().nth(index).unwrap().data();
+        let hex = data.iter().map(|b| format!("{:02x}", b)).collect();
+        self.prompt = Some((Prompt::Edit, hex));
+        Ok(())
+    }
+
+    fn start_export(&mut self) -> Result<()> {
+        let index = self.selected().ok_or("no chunk selected")?;
+        let chunk_type = self.png.chunks().nth(index).unwrap().chunk_type();
+        // Named the way explode names its files.
+        let name = format!("{:03}_{}.bin", index, chunk_type);
+        self.prompt = Some((Prompt::Export, name));
+        Ok(())
+    }
+
+    fn delete(&mut self) -> Result<()> {
+        let index = self.selected().ok_or("no chunk selected")?;
+        let chunk_type = *self.png.chunks().nth(index).unwrap().chunk_type();
+        let nth = self
+            .png
+            .chunks()
+            .take(index)
+            .filter(|c| *c.chunk_type() == chunk_type)
+            .count();
+        self.png
+            .remove_chunk(&chunk_type.to_string(), Removal::Nth(nth))?;
+        let len = self.png.chunks().count();
+        if len > 0 {
+            self.select(index.min(len - 1));
+        }
+        self.status = format!("deleted chunk {} ({})", index, chunk_type);
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<()> {
+        if self.args.write.dry_run {
+            return Err("not saving under --dry-run".into());
+        }
+        commands::write_png(&self.png, &self.args.file, &self.args.write)?;
+        self.status = String::from("saved");
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+pub fn tui(args: TuiArgs, options: &pngme::options::ParseOptions) -> Result<()> {
+    let png = commands::read_png(&args.file, options)?;
+    let mut app = App::new(args, png);
+    ratatui::run(|terminal| app.run(terminal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::WriteArgs;
+    use pngme::chunk::Chunk;
+    use pngme::chunk_type::ChunkType;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    fn app() -> App {
+        let chunk =
+            |name: &str, data: &[u8]| Chunk::new(ChunkType::from_str(name).unwrap(), data.to_vec());
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", b"header"),
+            chunk("tEXt", b"one"),
+            chunk("IDAT", b"pixels"),
+            chunk("tEXt", b"two"),
+            chunk("IEND", b""),
+        ]);
+        let args = TuiArgs {
+            file: PathBuf::from("test.png"),
+            write: WriteArgs {
+                output: None,
+                force: false,
+                dry_run: true,
+                backup: None,
+            },
+        };
+        App::new(args, png)
+    }
+
+    fn types(app: &App) -> Vec<String> {
+        app.png
+            .chunks()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    fn keys(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            app.handle_key(match c {
+                '\n' => KeyCode::Enter,
+                c => KeyCode::Char(c),
+            });
+        }
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(
+            hex_line(16, b"ab\x00"),
+            format!("00000010  61 62 00 {} ab.", " ".repeat(3 * 13))
+        );
+    }
+
+    #[test]
+    fn test_search_wraps() {
+        let mut app = app();
+        keys(&mut app, "/text\n");
+        assert_eq!(app.selected(), Some(1));
+        keys(&mut app, "n");
+        assert_eq!(app.selected(), Some(3));
+        keys(&mut app, "n");
+        assert_eq!(app.selected(), Some(1));
+
+        keys(&mut app, "/ruSt\n");
+        assert_eq!(app.status, "error: no ruSt chunk");
+        assert_eq!(app.selected(), Some(1));
+    }
+
+    #[test]
+    fn test_edit_and_delete() {
+        let mut app = app();
+        keys(&mut app, "/text\nn");
+        app.handle_key(KeyCode::Char('e'));
+        assert_eq!(app.prompt, Some((Prompt::Edit, String::from("74776f"))));
+        app.prompt = Some((Prompt::Edit, String::from("6869")));
+        keys(&mut app, "\n");
+        assert_eq!(app.png.chunks().nth(3).unwrap().data(), b"hi");
+        assert!(app.dirty);
+
+        keys(&mut app, "d");
+        assert_eq!(types(&app), ["IHDR", "tEXt", "IDAT", "IEND"]);
+        assert_eq!(app.png.chunks().nth(1).unwrap().data(), b"one");
+        assert_eq!(app.selected(), Some(3));
+
+        keys(&mut app, "s");
+        assert_eq!(app.status, "error: not saving under --dry-run");
+        keys(&mut app, "q");
+        assert!(!app.done);
+        keys(&mut app, "q");
+        assert!(app.done);
+    }
+}